@@ -0,0 +1,379 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Player accounts
+//!
+//! Accounts outlive any one game or server process: they are kept in a
+//! JSON file on disk, rewritten after every change.  A player who
+//! registers for a game under an account's name must give the
+//! account's key, and the game's player then refers to the account id.
+//! Two player games between accounts are rated; bots and humans share
+//! one rating pool.
+
+use auth;
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use rating::{Rating, RatingSystem};
+use registry::Entrant;
+use result::Reason;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identify a player as `Accounts::entrant` does, locking `accounts`
+/// only to look up the name: checking or hashing the key is slow, and
+/// nobody else could use the accounts meanwhile
+pub fn identify(accounts: &Mutex<Accounts>, name: &str, key: &str) -> QuoridorResult<Entrant>
+{
+    let account = accounts.lock().unwrap().find(name).map(|a| (a.id, a.key_hash.clone()));
+    entrant(name, key, account)
+}
+
+/// Identify a player given the id and key hash of the account called
+/// `name`, if there is one
+fn entrant(name: &str, key: &str, account: Option<(u64, String)>) -> QuoridorResult<Entrant>
+{
+    match account {
+        Some((id, key_hash)) => match auth::verify_key(key, &key_hash) {
+            true => Ok(Entrant { name: name.to_string(), key_hash: key_hash, account: Some(id) }),
+            false => Err(QuoridorError::Unauthorized),
+        },
+        None => Ok(Entrant::guest(name, key)),
+    }
+}
+
+/// Results of the finished games of an account
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub id: u64,
+    pub name: String,
+    pub key_hash: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    pub stats: Stats,
+    /// Is the account played by a program
+    pub bot: bool,
+    pub rating: Rating,
+    /// Rating after each rated game, with when it was played
+    pub history: Vec<(u64, Rating)>,
+}
+
+pub struct Accounts {
+    /// How ratings are updated
+    pub rating_system: RatingSystem,
+    path: Option<PathBuf>,
+    accounts: BTreeMap<u64, Account>,
+    next_id: u64,
+}
+
+/// Seconds since the Unix epoch
+pub fn now_secs() -> u64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Stats {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("games".to_string(), self.games.to_json());
+        d.insert("wins".to_string(), self.wins.to_json());
+        d.insert("losses".to_string(), self.losses.to_json());
+        d.insert("draws".to_string(), self.draws.to_json());
+        Json::Object(d)
+    }
+
+    fn from_json(json: &Json) -> Option<Stats>
+    {
+        let field = |name| json.find(name).and_then(|v| v.as_u64()).map(|v| v as u32);
+        match (field("games"), field("wins"), field("losses"), field("draws")) {
+            (Some(games), Some(wins), Some(losses), Some(draws)) => Some(Stats {
+                games: games,
+                wins: wins,
+                losses: losses,
+                draws: draws,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Account {
+    /// The account as JSON, leaving out the key hash and rating
+    /// history unless `private`
+    pub fn to_json(&self, private: bool) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_json());
+        d.insert("name".to_string(), self.name.to_json());
+        d.insert("created".to_string(), self.created.to_json());
+        d.insert("stats".to_string(), self.stats.to_json());
+        d.insert("bot".to_string(), self.bot.to_json());
+        d.insert("rating".to_string(), self.rating.to_json());
+        if private {
+            d.insert("key_hash".to_string(), self.key_hash.to_json());
+            d.insert("history".to_string(), self.history_json());
+        }
+        Json::Object(d)
+    }
+
+    /// Rating history as JSON
+    pub fn history_json(&self) -> Json
+    {
+        Json::Array(self.history.iter().map(|&(time, ref rating)| {
+            let mut d = BTreeMap::new();
+            d.insert("time".to_string(), time.to_json());
+            d.insert("rating".to_string(), rating.to_json());
+            Json::Object(d)
+        }).collect())
+    }
+
+    fn from_json(json: &Json) -> Option<Account>
+    {
+        let string = |name| json.find(name).and_then(|v| v.as_string()).map(String::from);
+        let number = |name| json.find(name).and_then(|v| v.as_u64());
+        match (number("id"), string("name"), string("key_hash"), number("created"),
+               json.find("stats").and_then(Stats::from_json)) {
+            (Some(id), Some(name), Some(key_hash), Some(created), Some(stats)) => {
+                // Accounts saved before ratings start unrated
+                let history = json.find("history").and_then(|h| h.as_array()).map_or(vec![], |h| {
+                    h.iter().filter_map(|entry| {
+                        match (entry.find("time").and_then(|t| t.as_u64()),
+                               entry.find("rating").and_then(Rating::from_json)) {
+                            (Some(time), Some(rating)) => Some((time, rating)),
+                            _ => None,
+                        }
+                    }).collect()
+                });
+                Some(Account {
+                    id: id,
+                    name: name,
+                    key_hash: key_hash,
+                    created: created,
+                    stats: stats,
+                    bot: json.find("bot").and_then(|b| b.as_boolean()).unwrap_or(false),
+                    rating: json.find("rating").and_then(Rating::from_json).unwrap_or_default(),
+                    history: history,
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Accounts {
+    /// Accounts that are not saved anywhere
+    pub fn in_memory() -> Accounts
+    {
+        Accounts {
+            rating_system: RatingSystem::Glicko2,
+            path: None,
+            accounts: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Load the accounts kept in `path`, which is created on the first
+    /// change if it doesn't exist
+    pub fn open(path: PathBuf) -> QuoridorResult<Accounts>
+    {
+        let mut accounts = Accounts::in_memory();
+        if path.exists() {
+            let json = match Json::from_reader(&mut BufReader::new(try!(File::open(&path)))) {
+                Ok(json) => json,
+                Err(e) => return Err(QuoridorError::ParseError(e.to_string())),
+            };
+            let invalid = || QuoridorError::ParseError(format!("Unable to parse accounts in {}", path.display()));
+            for account in try!(json.find("accounts").and_then(|a| a.as_array()).ok_or_else(&invalid)) {
+                let account = try!(Account::from_json(account).ok_or_else(&invalid));
+                accounts.next_id = ::std::cmp::max(accounts.next_id, account.id + 1);
+                accounts.accounts.insert(account.id, account);
+            }
+        }
+        accounts.path = Some(path);
+        Ok(accounts)
+    }
+
+    /// Write the accounts to disk, replacing the old file in one step
+    pub fn save(&self) -> QuoridorResult<()>
+    {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut d = BTreeMap::new();
+        d.insert("accounts".to_string(),
+                 Json::Array(self.accounts.values().map(|a| a.to_json(true)).collect()));
+
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(try!(File::create(&tmp)));
+            try!(write!(w, "{}", Json::Object(d)));
+            try!(w.flush());
+            try!(w.get_ref().sync_all());
+        }
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+
+    /// Create an account, for a bot if `bot`
+    pub fn register(&mut self, name: &str, key: &str, bot: bool) -> QuoridorResult<Account>
+    {
+        if name.is_empty() || key.is_empty() {
+            return Err(QuoridorError::RegistrationError("An account needs a name and a key".into()))
+        }
+        if self.find(name).is_some() {
+            return Err(QuoridorError::RegistrationError(format!("Account {} already exists", name)))
+        }
+        let account = Account {
+            id: self.next_id,
+            name: name.to_string(),
+            key_hash: auth::hash_key(key),
+            created: now_secs(),
+            stats: Stats::default(),
+            bot: bot,
+            rating: Rating::default(),
+            history: vec![],
+        };
+        self.next_id += 1;
+        self.accounts.insert(account.id, account.clone());
+        try!(self.save());
+        Ok(account)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Account>
+    {
+        self.accounts.get(&id)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Account>
+    {
+        self.accounts.values().find(|a| a.name == name)
+    }
+
+    /// Every account, in order of creation
+    pub fn all(&self) -> Vec<&Account>
+    {
+        self.accounts.values().collect()
+    }
+
+    /// The account called `name`, if `key` is its key
+    pub fn authenticate(&self, name: &str, key: &str) -> QuoridorResult<&Account>
+    {
+        match self.find(name) {
+            Some(account) if auth::verify_key(key, &account.key_hash) => Ok(account),
+            Some(_) => Err(QuoridorError::Unauthorized),
+            None => Err(QuoridorError::PlayerNotFound),
+        }
+    }
+
+    /// Identify a player by name and key.  A name that belongs to an
+    /// account must come with the account's key and is linked to it;
+    /// any other name is a guest.
+    pub fn entrant(&self, name: &str, key: &str) -> QuoridorResult<Entrant>
+    {
+        entrant(name, key, self.find(name).map(|a| (a.id, a.key_hash.clone())))
+    }
+
+    /// Add a player to a game.  A name that belongs to an account must
+    /// come with the account's key, and the player is linked to it;
+    /// any other name joins as a guest.
+    pub fn join_game(&self, game: &mut Game, name: &str, key: &str) -> QuoridorResult<String>
+    {
+        self.join_game_in_seat(game, name, key, None)
+    }
+
+    /// Add a player to a game in the seat they chose, if any, as
+    /// `join_game` does
+    pub fn join_game_in_seat(&self, game: &mut Game, name: &str, key: &str, seat: Option<u8>) -> QuoridorResult<String>
+    {
+        try!(self.entrant(name, key)).join(game, seat)
+    }
+
+    /// Accounts ordered by rating, best first, optionally only bots or
+    /// only humans
+    pub fn leaderboard(&self, bots: Option<bool>) -> Vec<&Account>
+    {
+        let mut accounts: Vec<_> = self.accounts.values().filter(|a| bots.map_or(true, |b| a.bot == b)).collect();
+        accounts.sort_by(|a, b| b.rating.rating.partial_cmp(&a.rating.rating).unwrap_or(Ordering::Equal));
+        accounts
+    }
+
+    /// Count a finished game in the stats of its players' accounts,
+    /// and rate it if it was between two accounts on even terms:
+    /// handicap games are not rated.  Abandoned games don't count.
+    pub fn record_result(&mut self, game: &Game) -> QuoridorResult<()>
+    {
+        if game.state != GameState::GameOver || game.result.map(|r| r.reason) == Some(Reason::Abandonment) {
+            return Ok(())
+        }
+        let winner = game.winner();
+
+        let mut players: Vec<_> = game.players.values().collect();
+        players.sort_by_key(|p| p.id);
+        let rated: Vec<_> = players.iter().filter_map(|p| p.account.and_then(|id| self.accounts.get(&id))).collect();
+        if players.len() == 2 && rated.len() == 2 && !game.options.setup.as_ref().map_or(false, |s| s.is_handicap()) {
+            let score = match winner {
+                Some(0) => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            let (a, b) = (rated[0].rating, rated[1].rating);
+            let ratings = [self.rating_system.update(&a, &b, score), self.rating_system.update(&b, &a, 1.0 - score)];
+            let ids = [rated[0].id, rated[1].id];
+            let now = now_secs();
+            for (id, rating) in ids.iter().zip(ratings.iter()) {
+                let account = self.accounts.get_mut(id).unwrap();
+                account.rating = *rating;
+                account.history.push((now, *rating));
+            }
+        }
+
+        for player in game.players.values() {
+            if let Some(account) = player.account.and_then(|id| self.accounts.get_mut(&id)) {
+                account.stats.games += 1;
+                match winner {
+                    Some(id) if id == player.id => account.stats.wins += 1,
+                    Some(_) => account.stats.losses += 1,
+                    None => account.stats.draws += 1,
+                }
+            }
+        }
+        self.save()
+    }
+}
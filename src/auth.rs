@@ -0,0 +1,168 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Player authentication
+//!
+//! Player keys are only kept as salted PBKDF2 hashes.  Registering or
+//! logging in hands out a random session token, which clients send as
+//! `Authorization: Bearer <token>` until it expires or is revoked.
+
+use crypto::pbkdf2;
+use crypto::util::fixed_time_eq;
+use errors::QuoridorError;
+use iron::headers::{Authorization, Bearer};
+use iron::prelude::*;
+use iron::status;
+use iron::typemap::Key;
+use iron::BeforeMiddleware;
+use rand::{OsRng, Rng};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// PBKDF2 iterations used to hash keys
+pub const HASH_ROUNDS: u32 = 10000;
+
+/// Seconds a session token stays valid
+pub const TOKEN_LIFETIME: u64 = 24 * 60 * 60;
+
+/// Hash a key with a random salt.  An empty key stays empty, and can
+/// never be verified.
+pub fn hash_key(key: &str) -> String
+{
+    match key.is_empty() {
+        true => String::new(),
+        false => pbkdf2::pbkdf2_simple(key, HASH_ROUNDS).expect("Unable to read OS randomness"),
+    }
+}
+
+/// Does `key` match a hash made by `hash_key`
+pub fn verify_key(key: &str, hash: &str) -> bool
+{
+    !hash.is_empty() && pbkdf2::pbkdf2_check(key, hash).unwrap_or(false)
+}
+
+/// Header carrying the server's admin key
+pub const ADMIN_HEADER: &'static str = "X-Admin-Key";
+
+/// Does the request carry `admin_key` in its `X-Admin-Key` header.
+/// Nobody is an admin if the server has no admin key.
+pub fn is_admin(request: &Request, admin_key: Option<&str>) -> bool
+{
+    match (admin_key, request.headers.get_raw(ADMIN_HEADER)) {
+        (Some(admin_key), Some(values)) if values.len() == 1 => fixed_time_eq(admin_key.as_bytes(), &values[0]),
+        _ => false,
+    }
+}
+
+/// A new random session token
+pub fn new_token() -> String
+{
+    let mut rng = OsRng::new().expect("Unable to read OS randomness");
+    rng.gen_ascii_chars().take(32).collect()
+}
+
+/// Who a token was given to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    pub game: String,
+    pub name: String,
+    pub expires: Instant,
+}
+
+pub struct Sessions {
+    sessions: HashMap<String, Session>,
+    /// How long new tokens are valid for
+    pub lifetime: Duration,
+}
+
+impl Sessions {
+    pub fn new(lifetime: Duration) -> Sessions
+    {
+        Sessions {
+            sessions: HashMap::new(),
+            lifetime: lifetime,
+        }
+    }
+
+    /// Start a session for player `name` of game `game`
+    pub fn create<S: Into<String>>(&mut self, game: S, name: S, now: Instant) -> Session
+    {
+        self.sessions.retain(|_, s| s.expires > now);
+        let session = Session {
+            token: new_token(),
+            game: game.into(),
+            name: name.into(),
+            expires: now + self.lifetime,
+        };
+        self.sessions.insert(session.token.clone(), session.clone());
+        session
+    }
+
+    /// The session of a token, unless it has expired or been revoked
+    pub fn resolve(&self, token: &str, now: Instant) -> Option<Session>
+    {
+        self.sessions.get(token).and_then(|s| match s.expires > now {
+            true => Some(s.clone()),
+            false => None,
+        })
+    }
+
+    /// End a session, returning whether the token was valid
+    pub fn revoke(&mut self, token: &str) -> bool
+    {
+        self.sessions.remove(token).is_some()
+    }
+}
+
+/// Request extension holding the session of an authenticated player
+pub struct AuthenticatedPlayer;
+
+impl Key for AuthenticatedPlayer {
+    type Value = Session;
+}
+
+/// Middleware resolving the bearer token of a request, if it has one,
+/// to the player's session.  Requests with an invalid or expired token
+/// are refused.
+pub struct Authenticate {
+    pub sessions: Arc<Mutex<Sessions>>,
+}
+
+impl BeforeMiddleware for Authenticate {
+    fn before(&self, request: &mut Request) -> IronResult<()>
+    {
+        let token = match request.headers.get::<Authorization<Bearer>>() {
+            Some(header) => header.token.clone(),
+            None => return Ok(()),
+        };
+        match self.sessions.lock().unwrap().resolve(&token, Instant::now()) {
+            Some(session) => {
+                request.extensions.insert::<AuthenticatedPlayer>(session);
+                Ok(())
+            },
+            None => Err(IronError::new(QuoridorError::Unauthorized,
+                                       (status::Unauthorized, "Invalid or expired token"))),
+        }
+    }
+}
@@ -24,6 +24,7 @@
 
 use constants::N;
 use errors::{QuoridorError, QuoridorResult};
+use std::fmt;
 
 /// Convert (x, y) tuple to a point
 macro_rules! point { ( $x:expr, $y:expr ) => { { Point{ x: $x, y: $y} } }; }
@@ -79,6 +80,23 @@ impl<S> From<S> for Direction
     }
 }
 
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Direction::North => "NORTH",
+            Direction::East => "EAST",
+            Direction::South => "SOUTH",
+            Direction::West => "WEST",
+            Direction::NorthEast => "NORTHEAST",
+            Direction::SouthEast => "SOUTHEAST",
+            Direction::SouthWest => "SOUTHWEST",
+            Direction::NorthWest => "NORTHWEST",
+            Direction::Invalid => "INVALID",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl Direction {
     pub fn reversed(&self) -> Direction
     {
@@ -0,0 +1,231 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Opening books
+//!
+//! A book maps position hashes to weighted candidate turns.  Positions
+//! are stored in their canonical form so that mirrored and rotated
+//! openings share an entry.  Books are built from game records and
+//! saved as JSON:
+//!
+//! ```text
+//! {"positions": {"<hash in hex>": [{"turn": {"move": "SOUTH"}, "weight": 12}, ...]}}
+//! ```
+
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use rand::Rng;
+use record::GameRecord;
+use rustc_serialize::json::{Json, ToJson};
+use symmetry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use turn::Turn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookMove {
+    pub turn: Turn,
+    pub weight: u32,
+}
+
+pub struct OpeningBook {
+    entries: HashMap<u64, Vec<BookMove>>,
+}
+
+impl OpeningBook {
+    /// Create an empty book
+    pub fn new() -> OpeningBook
+    {
+        OpeningBook { entries: HashMap::new() }
+    }
+
+    /// Number of positions in the book
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    /// Add `weight` to the candidate `turn` in the position of `game`
+    pub fn insert(&mut self, game: &Game, turn: Turn, weight: u32)
+    {
+        let (key, turn) = symmetry::canonical_turn(game, turn);
+        let moves = self.entries.entry(key).or_insert(vec![]);
+        match moves.iter().position(|m| m.turn == turn) {
+            Some(i) => moves[i].weight += weight,
+            None => moves.push(BookMove { turn: turn, weight: weight }),
+        }
+        moves.sort_by(|a, b| b.weight.cmp(&a.weight).then(a.turn.cmp(&b.turn)));
+    }
+
+    /// Candidate turns for the position of `game`, heaviest first
+    pub fn lookup(&self, game: &Game) -> Vec<BookMove>
+    {
+        let (key, back) = (symmetry::canonical_hash(game), symmetry::canonical_symmetry(game).inverse());
+        match self.entries.get(&key) {
+            Some(moves) => moves.iter().map(|m| BookMove {
+                turn: back.turn(m.turn),
+                weight: m.weight,
+            }).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The heaviest candidate turn, if the position is in the book
+    pub fn best(&self, game: &Game) -> Option<Turn>
+    {
+        self.lookup(game).first().map(|m| m.turn)
+    }
+
+    /// Pick a candidate turn at random in proportion to its weight
+    pub fn choose<R: Rng>(&self, game: &Game, rng: &mut R) -> Option<Turn>
+    {
+        let moves = self.lookup(game);
+        let total: u32 = moves.iter().map(|m| m.weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = rng.gen_range(0, total);
+        for m in moves {
+            if pick < m.weight {
+                return Some(m.turn);
+            }
+            pick -= m.weight;
+        }
+        None
+    }
+
+    /// Return the book as JSON
+    pub fn to_json(&self) -> Json
+    {
+        let mut positions = BTreeMap::new();
+        for (key, moves) in self.entries.iter() {
+            let moves = moves.iter().map(|m| {
+                let mut d = BTreeMap::new();
+                d.insert("turn".to_string(), m.turn.to_json());
+                d.insert("weight".to_string(), m.weight.to_json());
+                Json::Object(d)
+            }).collect();
+            positions.insert(format!("{:016x}", key), Json::Array(moves));
+        }
+        let mut d = BTreeMap::new();
+        d.insert("positions".to_string(), Json::Object(positions));
+        Json::Object(d)
+    }
+
+    /// Parse a book written by `to_json`
+    pub fn from_json(json: &Json) -> QuoridorResult<OpeningBook>
+    {
+        let invalid = || QuoridorError::ParseError("Unable to parse opening book".into());
+        let positions = try!(json.find("positions").and_then(|p| p.as_object()).ok_or_else(&invalid));
+
+        let mut book = OpeningBook::new();
+        for (key, moves) in positions.iter() {
+            let key = try!(u64::from_str_radix(key, 16).map_err(|_| invalid()));
+            let mut entry = vec![];
+            for m in try!(moves.as_array().ok_or_else(&invalid)) {
+                let turn = try!(Turn::from_json(try!(m.find("turn").ok_or_else(&invalid))));
+                let weight = try!(m.find("weight").and_then(|w| w.as_u64()).ok_or_else(&invalid));
+                entry.push(BookMove { turn: turn, weight: weight as u32 });
+            }
+            book.entries.insert(key, entry);
+        }
+        Ok(book)
+    }
+
+    /// Read a JSON book
+    pub fn read<R: Read>(r: &mut R) -> QuoridorResult<OpeningBook>
+    {
+        match Json::from_reader(r) {
+            Ok(json) => OpeningBook::from_json(&json),
+            Err(e) => Err(QuoridorError::ParseError(e.to_string())),
+        }
+    }
+
+    /// Write the book as JSON
+    pub fn write<W: Write>(&self, w: &mut W) -> QuoridorResult<()>
+    {
+        try!(write!(w, "{}", self.to_json()));
+        Ok(())
+    }
+}
+
+/// Builds an opening book from game records
+pub struct BookBuilder {
+    /// Only the first `max_plies` turns of each game are used
+    pub max_plies: usize,
+    /// Turns played fewer times than this are left out
+    pub min_count: u32,
+    /// Turns scoring less than this (1 for a win, 0.5 for a draw) on
+    /// average for the player making them are left out
+    pub min_score: f64,
+    stats: HashMap<(u64, Turn), (u32, f64)>,
+}
+
+impl BookBuilder {
+    pub fn new() -> BookBuilder
+    {
+        BookBuilder {
+            max_plies: 12,
+            min_count: 2,
+            min_score: 0.0,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Count the opening turns of a game
+    pub fn add_record(&mut self, record: &GameRecord) -> QuoridorResult<()>
+    {
+        let positions = try!(record.positions());
+        for (game, turn) in positions.iter().zip(record.turns.iter()).take(self.max_plies) {
+            let mover = match game.state {
+                GameState::Started(id) => id,
+                _ => break,
+            };
+            let score = match record.winner {
+                Some(w) if w == mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            let (key, turn) = symmetry::canonical_turn(game, *turn);
+            let stats = self.stats.entry((key, turn)).or_insert((0, 0.0));
+            stats.0 += 1;
+            stats.1 += score;
+        }
+        Ok(())
+    }
+
+    /// Create a book of the turns that pass the filters, weighted by
+    /// how often they were played
+    pub fn build(&self) -> OpeningBook
+    {
+        let mut book = OpeningBook::new();
+        for (&(key, turn), &(count, score)) in self.stats.iter() {
+            if count >= self.min_count && score / count as f64 >= self.min_score {
+                book.entries.entry(key).or_insert(vec![]).push(BookMove { turn: turn, weight: count });
+            }
+        }
+        for moves in book.entries.values_mut() {
+            moves.sort_by(|a, b| b.weight.cmp(&a.weight).then(a.turn.cmp(&b.turn)));
+        }
+        book
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Game clocks
+//!
+//! Times are in milliseconds.  A time control gives each player a
+//! base time, optionally topped up by a Fischer increment after every
+//! turn, and/or limits how long any single turn may take.  Only the
+//! clock of the player to move runs.
+
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeControl {
+    /// Time each player has for the whole game, if limited
+    pub base: Option<u64>,
+    /// Time added to a player's clock after each of their turns
+    pub increment: u64,
+    /// Time allowed for a single turn, if limited
+    pub move_limit: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// Time left for each seat, not counting the running turn
+    pub remaining: Vec<u64>,
+    /// Time left for the player who moved, after each turn
+    pub log: Vec<u64>,
+    /// Seat that ran out of time
+    pub flagged: Option<u8>,
+    running: Option<(u8, Instant)>,
+}
+
+/// Milliseconds from `since` to `now`
+fn elapsed(since: Instant, now: Instant) -> u64
+{
+    let d = now.duration_since(since);
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+}
+
+impl TimeControl {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("base".to_string(), self.base.to_json());
+        d.insert("increment".to_string(), self.increment.to_json());
+        d.insert("move_limit".to_string(), self.move_limit.to_json());
+        Json::Object(d)
+    }
+
+    /// Parse a time control written by `to_json`
+    pub fn from_json(json: &Json) -> Option<TimeControl>
+    {
+        let limit = |key| json.find(key).and_then(|v| v.as_u64());
+        Some(TimeControl {
+            base: limit("base"),
+            increment: match json.find("increment").and_then(|v| v.as_u64()) {
+                Some(increment) => increment,
+                None => return None,
+            },
+            move_limit: limit("move_limit"),
+        })
+    }
+}
+
+impl Clock {
+    /// Create stopped clocks for `seats` players
+    pub fn new(control: TimeControl, seats: usize) -> Clock
+    {
+        Clock {
+            remaining: vec![control.base.unwrap_or(0); seats],
+            control: control,
+            log: vec![],
+            flagged: None,
+            running: None,
+        }
+    }
+
+    /// Start the clock of `seat`
+    pub fn start(&mut self, seat: u8, now: Instant)
+    {
+        self.running = Some((seat, now));
+    }
+
+    /// Stop whichever clock is running
+    pub fn stop(&mut self)
+    {
+        self.running = None;
+    }
+
+    /// Seat whose clock is running
+    pub fn running(&self) -> Option<u8>
+    {
+        self.running.map(|(seat, _)| seat)
+    }
+
+    /// Time `seat` has left for their current or next turn, or None
+    /// if nothing limits it
+    pub fn time_left(&self, seat: u8, now: Instant) -> Option<u64>
+    {
+        let spent = match self.running {
+            Some((s, since)) if s == seat => elapsed(since, now),
+            _ => 0,
+        };
+        let game = self.control.base.map(|_| self.remaining[seat as usize].saturating_sub(spent));
+        let turn = self.control.move_limit.map(|limit| limit.saturating_sub(spent));
+        match (game, turn) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Flag the player to move if they are out of time, returning
+    /// their seat
+    pub fn check(&mut self, now: Instant) -> Option<u8>
+    {
+        if let Some((seat, _)) = self.running {
+            if self.time_left(seat, now) == Some(0) {
+                self.flagged = Some(seat);
+                self.running = None;
+            }
+        }
+        self.flagged
+    }
+
+    /// End the turn of the player to move and start the clock of
+    /// `next`.  Returns false, flagging the player, if the turn
+    /// took longer than they had.
+    pub fn punch(&mut self, next: u8, now: Instant) -> bool
+    {
+        if self.check(now).is_some() {
+            return false
+        }
+        if let Some((seat, since)) = self.running {
+            let remaining = &mut self.remaining[seat as usize];
+            if self.control.base.is_some() {
+                *remaining = *remaining - elapsed(since, now) + self.control.increment;
+            }
+            self.log.push(*remaining);
+        }
+        self.start(next, now);
+        true
+    }
+
+    /// Go back to `plies` turns into the game, with the clock of `seat`
+    /// running.  The player whose clock was running keeps the time
+    /// they spent.
+    pub fn rewind(&mut self, plies: usize, seat: u8, now: Instant)
+    {
+        if let Some((running, since)) = self.running {
+            if self.control.base.is_some() {
+                let remaining = &mut self.remaining[running as usize];
+                *remaining = remaining.saturating_sub(elapsed(since, now));
+            }
+        }
+        self.log.truncate(plies);
+        self.start(seat, now);
+    }
+
+    /// The players in seats `a` and `b` swap seats, each keeping their
+    /// own time.  The running clock is charged the time spent so far
+    /// and runs on for the player now in its seat.
+    pub fn swap(&mut self, a: u8, b: u8, now: Instant)
+    {
+        if let Some((running, since)) = self.running {
+            if self.control.base.is_some() {
+                let remaining = &mut self.remaining[running as usize];
+                *remaining = remaining.saturating_sub(elapsed(since, now));
+            }
+            self.start(running, now);
+        }
+        self.remaining.swap(a as usize, b as usize);
+    }
+
+    /// Return the clocks as JSON
+    pub fn to_json(&self, now: Instant) -> Json
+    {
+        let seats = 0..self.remaining.len() as u8;
+        let mut d = BTreeMap::new();
+        d.insert("control".to_string(), self.control.to_json());
+        d.insert("remaining".to_string(), seats.map(|s| self.time_left(s, now)).collect::<Vec<_>>().to_json());
+        d.insert("running".to_string(), self.running().to_json());
+        d.insert("flagged".to_string(), self.flagged.to_json());
+        Json::Object(d)
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Domain events a game is built from
+//!
+//! Every change to a game is recorded as an event, and the board,
+//! players, history and state are a fold of those events.  Commands
+//! like `Game::move_player_to` check the rules and then record the
+//! events they produce; nothing else changes the game.  Snapshots of
+//! the fold are kept every `SNAPSHOT_INTERVAL` events so that earlier
+//! positions can be rebuilt without replaying the whole game.  They
+//! leave out the history and the repetition counts, which grow with the
+//! game and are rebuilt from the events instead.
+
+use board::{Point, Wall};
+use player::Player;
+use quoridor::{Game, GameOptions, GameState};
+use result::GameResult;
+use std::mem;
+use turn::Turn;
+
+/// Events between snapshots
+pub const SNAPSHOT_INTERVAL: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DomainEvent {
+    PlayerJoined { seat: u8, name: String, key_hash: String, start: Point, walls: u8 },
+    GameStarted { first: u8 },
+    PawnMoved { seat: u8, from: Point, to: Point },
+    /// A wall placed from a player's hand, or on the board during
+    /// setup when `seat` is None
+    WallPlaced { seat: Option<u8>, wall: Wall },
+    /// The player to move finished their turn
+    TurnEnded { next: u8 },
+    Resigned { seat: u8 },
+    TimedOut { seat: u8 },
+    DrawOffered { seat: u8 },
+    DrawDeclined { seat: u8 },
+    TakebackRequested { seat: u8 },
+    TakebackDeclined { seat: u8 },
+    /// The game went back to how it was after its first `to` events,
+    /// taking back the last move of `seat`
+    TakenBack { seat: u8, to: usize },
+    /// Under the pie rule `seat` took the side of `with`, who took
+    /// theirs: their pawns, walls and seats change hands
+    SidesSwapped { seat: u8, with: u8 },
+    GameEnded { result: GameResult },
+}
+
+/// The fold of the first `len` events of a game, without its events,
+/// clock, history or positions
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub len: usize,
+    pub game: Game,
+}
+
+/// The events of a game in order, and snapshots along the way
+#[derive(Debug, Clone, Default)]
+pub struct EventStream {
+    events: Vec<DomainEvent>,
+    snapshots: Vec<Snapshot>,
+    /// The index of each event that starts a turn, and the
+    /// `position_hash` after it
+    positions: Vec<(usize, u64)>,
+}
+
+impl EventStream {
+    /// Every event so far
+    pub fn events(&self) -> &[DomainEvent]
+    {
+        &self.events
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.events.len()
+    }
+
+    /// Indices of the first `len` events that haven't been taken back
+    pub fn in_effect(&self, len: usize) -> Vec<usize>
+    {
+        let mut kept = vec![];
+        let mut i = len;
+        while i > 0 {
+            i -= 1;
+            match self.events[i] {
+                DomainEvent::TakenBack { to, .. } => i = to,
+                _ => kept.push(i),
+            }
+        }
+        kept.reverse();
+        kept
+    }
+
+    /// The latest snapshot of at most `len` events
+    pub fn snapshot(&self, len: usize) -> Option<&Snapshot>
+    {
+        self.snapshots.iter().rev().find(|s| s.len <= len)
+    }
+
+    /// Number of turns in the history after the first `len` events
+    pub fn turns(&self, len: usize) -> usize
+    {
+        self.in_effect(len).into_iter().filter(|&i| self.events[i].turn().is_some()).count()
+    }
+
+    /// Did the game end in the events after the first `len`?
+    pub fn ended_after(&self, len: usize) -> bool
+    {
+        self.events[len..].iter().any(|e| match *e {
+            DomainEvent::GameEnded { .. } => true,
+            _ => false,
+        })
+    }
+}
+
+impl DomainEvent {
+    /// The seat of the player whose action this is, if any
+    pub fn seat(&self) -> Option<u8>
+    {
+        match *self {
+            DomainEvent::PlayerJoined { seat, .. } |
+            DomainEvent::PawnMoved { seat, .. } |
+            DomainEvent::Resigned { seat } |
+            DomainEvent::TimedOut { seat } |
+            DomainEvent::DrawOffered { seat } |
+            DomainEvent::DrawDeclined { seat } |
+            DomainEvent::TakebackRequested { seat } |
+            DomainEvent::TakebackDeclined { seat } |
+            DomainEvent::TakenBack { seat, .. } |
+            DomainEvent::SidesSwapped { seat, .. } => Some(seat),
+            DomainEvent::WallPlaced { seat, .. } => seat,
+            _ => None,
+        }
+    }
+
+    /// The turn this event records in the game's history
+    pub fn turn(&self) -> Option<Turn>
+    {
+        match *self {
+            DomainEvent::PawnMoved { from, to, .. } => Turn::between(from, to),
+            DomainEvent::WallPlaced { seat: Some(_), wall } => Some(Turn::PlaceWall(wall)),
+            _ => None,
+        }
+    }
+}
+
+impl Game {
+    /// Fold `event` into the board, players, history and state.  The
+    /// event isn't recorded and its rules aren't checked; see `record`.
+    pub fn apply(&mut self, event: &DomainEvent)
+    {
+        if let Some(turn) = event.turn() {
+            self.history.push(turn);
+            // Playing on declines the opponent's offer of a draw or
+            // request to take back a move
+            if self.draw_offer.is_some() && self.draw_offer != event.seat() {
+                self.draw_offer = None;
+            }
+            if self.takeback_request.is_some() && self.takeback_request != event.seat() {
+                self.takeback_request = None;
+            }
+        }
+        match *event {
+            DomainEvent::PlayerJoined { seat, ref name, ref key_hash, start, walls } => {
+                self.players.insert(name.clone(), Player {
+                    p: start,
+                    key_hash: key_hash.clone(),
+                    id: seat,
+                    walls: walls,
+                    name: name.clone(),
+                    account: None,
+                });
+            },
+            DomainEvent::GameStarted { first } => {
+                if self.players.len() == 4 {
+                    for p in self.players.values_mut() {
+                        p.walls = self.options.walls / 2;
+                    }
+                }
+                self.state = GameState::Started(first);
+                self.positions.push(self.position_hash());
+            },
+            DomainEvent::PawnMoved { seat, to, .. } => {
+                if let Some(p) = self.players.values_mut().find(|p| p.id == seat) {
+                    p.p = to;
+                }
+            },
+            DomainEvent::WallPlaced { seat, wall } => {
+                self.walls.insert(wall);
+                if let Some(p) = self.players.values_mut().find(|p| Some(p.id) == seat) {
+                    p.walls = p.walls.saturating_sub(1);
+                }
+            },
+            DomainEvent::TurnEnded { next } => {
+                self.state = GameState::Started(next);
+                self.positions.push(self.position_hash());
+            },
+            DomainEvent::Resigned { .. } | DomainEvent::TimedOut { .. } => {},
+            DomainEvent::DrawOffered { seat } => self.draw_offer = Some(seat),
+            DomainEvent::DrawDeclined { .. } => self.draw_offer = None,
+            DomainEvent::TakebackRequested { seat } => self.takeback_request = Some(seat),
+            DomainEvent::TakebackDeclined { .. } => self.takeback_request = None,
+            DomainEvent::TakenBack { to, .. } => {
+                let mut game = self.at(to);
+                game.stream = mem::replace(&mut self.stream, EventStream::default());
+                game.clock = self.clock.take();
+                game.takeback_request = None;
+                game.draw_offer = None;
+                *self = game;
+            },
+            DomainEvent::SidesSwapped { seat, with } => {
+                let sides: Vec<_> = self.players.values().filter(|p| p.id == seat || p.id == with)
+                    .map(|p| (p.id, p.p, p.walls)).collect();
+                for p in self.players.values_mut().filter(|p| p.id == seat || p.id == with) {
+                    if let Some(&(id, pos, walls)) = sides.iter().find(|s| s.0 != p.id) {
+                        p.id = id;
+                        p.p = pos;
+                        p.walls = walls;
+                    }
+                }
+                self.draw_offer = None;
+                self.takeback_request = None;
+            },
+            DomainEvent::GameEnded { result } => {
+                self.state = GameState::GameOver;
+                self.result = Some(result);
+                self.draw_offer = None;
+                self.takeback_request = None;
+            },
+        }
+    }
+
+    /// Apply `event` and add it to the game's events
+    pub fn record(&mut self, event: DomainEvent)
+    {
+        self.apply(&event);
+        match event {
+            DomainEvent::GameStarted { .. } | DomainEvent::TurnEnded { .. } =>
+                self.stream.positions.push((self.stream.events.len(), self.position_hash())),
+            _ => (),
+        }
+        self.stream.events.push(event);
+        if self.stream.events.len() % SNAPSHOT_INTERVAL == 0 {
+            let stream = mem::replace(&mut self.stream, EventStream::default());
+            let clock = self.clock.take();
+            let history = mem::replace(&mut self.history, vec![]);
+            let positions = mem::replace(&mut self.positions, vec![]);
+            let snapshot = Snapshot { len: stream.events.len(), game: self.clone() };
+            self.stream = stream;
+            self.clock = clock;
+            self.history = history;
+            self.positions = positions;
+            self.stream.snapshots.push(snapshot);
+        }
+    }
+
+    /// Length of the history after the first `len` events, counted from
+    /// the events since then unless a move was taken back
+    pub fn history_len(&self, len: usize) -> usize
+    {
+        let since = &self.stream.events[len..];
+        let taken_back = since.iter().any(|e| match *e {
+            DomainEvent::TakenBack { .. } => true,
+            _ => false,
+        });
+        match taken_back {
+            true => self.stream.turns(len),
+            false => self.history.len() - since.iter().filter(|e| e.turn().is_some()).count(),
+        }
+    }
+
+    /// The game made of `events`, without a clock
+    pub fn replay(options: GameOptions, events: &[DomainEvent]) -> Game
+    {
+        let mut game = Game::with_options(options);
+        for event in events {
+            game.record(event.clone());
+        }
+        game
+    }
+
+    /// The game as it was after its first `len` events, rebuilt from
+    /// the nearest snapshot.  The clock isn't part of the events and is
+    /// left out.
+    pub fn at(&self, len: usize) -> Game
+    {
+        let len = ::std::cmp::min(len, self.stream.len());
+        let (mut game, start) = match self.stream.snapshot(len) {
+            Some(snapshot) => (snapshot.game.clone(), snapshot.len),
+            None => (Game::with_options(self.options.clone()), 0),
+        };
+        game.stream = EventStream {
+            events: self.stream.events[..start].to_vec(),
+            snapshots: self.stream.snapshots.iter().filter(|s| s.len <= start).cloned().collect(),
+            positions: self.stream.positions.iter().filter(|p| p.0 < start).cloned().collect(),
+        };
+        for event in &self.stream.events[start..len] {
+            game.record(event.clone());
+        }
+
+        // Snapshots have no history or positions: they are the turns
+        // and positions of the events still in effect
+        let kept = self.stream.in_effect(len);
+        game.history = kept.iter().filter_map(|&i| self.stream.events[i].turn()).collect();
+        game.positions = game.stream.positions.iter()
+            .filter(|&&(i, _)| kept.binary_search(&i).is_ok())
+            .map(|&(_, hash)| hash)
+            .collect();
+        for p in game.players.values_mut() {
+            p.account = self.players.get(&p.name).and_then(|q| q.account);
+        }
+        game
+    }
+
+    /// Go back to the game as it was after its first `len` events,
+    /// keeping the clock
+    pub fn rewind(&mut self, len: usize)
+    {
+        let clock = self.clock.take();
+        *self = self.at(len);
+        self.clock = clock;
+    }
+}
@@ -22,7 +22,9 @@
 
 //! Quoridor errors
 
+use std::error::Error;
 use std::fmt;
+use std::io;
 
 pub type QuoridorResult<T> = Result<T, QuoridorError>;
 
@@ -30,10 +32,17 @@ pub type QuoridorResult<T> = Result<T, QuoridorError>;
 pub enum QuoridorError {
     TurnError(String),
     PlayerNotFound,
+    Unauthorized,
     RegistrationError(String),
     InvalidJump(String),
     InvalidMove(String),
     InvalidWall(String),
+    AnalysisError(String),
+    IoError(io::Error),
+    ParseError(String),
+    TournamentError(String),
+    StorageError(String),
+    SetupError(String),
 }
 
 impl fmt::Display for QuoridorError {
@@ -41,10 +50,29 @@ impl fmt::Display for QuoridorError {
         match *self {
             QuoridorError::TurnError(ref s) => write!(f, "Invalid turn: {}", s),
             QuoridorError::PlayerNotFound => write!(f, "Player not found"),
+            QuoridorError::Unauthorized => write!(f, "Unauthorized"),
             QuoridorError::InvalidJump(ref s) => write!(f, "Invalid jump: {}", s),
             QuoridorError::InvalidMove(ref s) => write!(f, "Invalid move: {}", s),
             QuoridorError::InvalidWall(ref s) => write!(f, "Invalid wall: {}", s),
             QuoridorError::RegistrationError(ref s) => write!(f, "Registration error: {}", s),
+            QuoridorError::AnalysisError(ref s) => write!(f, "Analysis error: {}", s),
+            QuoridorError::IoError(ref e) => write!(f, "I/O error: {}", e),
+            QuoridorError::ParseError(ref s) => write!(f, "Parse error: {}", s),
+            QuoridorError::TournamentError(ref s) => write!(f, "Tournament error: {}", s),
+            QuoridorError::StorageError(ref s) => write!(f, "Storage error: {}", s),
+            QuoridorError::SetupError(ref s) => write!(f, "Invalid setup: {}", s),
         }
     }
 }
+
+impl Error for QuoridorError {
+    fn description(&self) -> &str {
+        "Quoridor error"
+    }
+}
+
+impl From<io::Error> for QuoridorError {
+    fn from(e: io::Error) -> QuoridorError {
+        QuoridorError::IoError(e)
+    }
+}
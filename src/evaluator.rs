@@ -0,0 +1,207 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Position evaluation
+//!
+//! Positions are scored from one player's point of view as a weighted
+//! sum of features, each the difference between that player and their
+//! opponent.  Only two player games can be evaluated.
+
+use board::Point;
+use constants::N;
+use errors::{QuoridorError, QuoridorResult};
+use player::Player;
+use quoridor::Game;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Number of evaluation features
+pub const FEATURES: usize = 5;
+
+/// Names of the evaluation features, in order
+pub const FEATURE_NAMES: [&'static str; FEATURES] = [
+    "path_difference",
+    "walls_in_hand",
+    "mobility",
+    "distance_to_edge",
+    "region_size",
+];
+
+/// Feature values of a position for one player
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features(pub [f64; FEATURES]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluator {
+    pub weights: [f64; FEATURES],
+}
+
+fn index(p: Point) -> usize
+{
+    (p.y * N + p.x) as usize
+}
+
+/// Breadth first distances from `src` to every square (-1 where
+/// unreachable) and the square each was reached from, considering
+/// walls but not pawns
+fn distances(game: &Game, src: Point) -> (Vec<i32>, Vec<Point>)
+{
+    let mut dist = vec![-1; (N * N) as usize];
+    let mut prev = vec![src; (N * N) as usize];
+    let mut queue = VecDeque::new();
+    dist[index(src)] = 0;
+    queue.push_back(src);
+    while let Some(p) = queue.pop_front() {
+        for q in vec![p.north(), p.east(), p.south(), p.west()] {
+            if q.inbounds() && dist[index(q)] < 0 && !game.has_wall_between(p, q) {
+                dist[index(q)] = dist[index(p)] + 1;
+                prev[index(q)] = p;
+                queue.push_back(q);
+            }
+        }
+    }
+    (dist, prev)
+}
+
+/// A shortest path for `player` to their goal, considering walls but
+/// not pawns, starting with their current square
+pub fn shortest_path(game: &Game, player: &Player) -> Vec<Point>
+{
+    let (dist, prev) = distances(game, player.p);
+    let mut goal = None;
+    for y in 0..N {
+        for x in 0..N {
+            let q = point!(x, y);
+            if player.is_goal(q) && dist[index(q)] >= 0 && goal.map_or(true, |g| dist[index(q)] < dist[index(g)]) {
+                goal = Some(q);
+            }
+        }
+    }
+
+    let mut path = vec![];
+    if let Some(mut q) = goal {
+        path.push(q);
+        while q != player.p {
+            q = prev[index(q)];
+            path.insert(0, q);
+        }
+    }
+    path
+}
+
+/// Shortest path to the goal, number of reachable squares and rows
+/// left to cross for one player
+fn player_stats(game: &Game, player: &Player) -> (f64, f64, f64)
+{
+    let (dist, _) = distances(game, player.p);
+    let (mut path, mut region, mut rows) = (N * N, 0, N);
+    for x in 0..N {
+        for y in 0..N {
+            let d = dist[index(point!(x, y))];
+            if d >= 0 {
+                region += 1;
+            }
+            if player.is_goal(point!(x, y)) {
+                rows = cmp::min(rows, cmp::max((x - player.p.x).abs(), (y - player.p.y).abs()));
+                if d >= 0 {
+                    path = cmp::min(path, d);
+                }
+            }
+        }
+    }
+    (path as f64, region as f64, rows as f64)
+}
+
+impl Features {
+    /// Compute the features of `game` for player `id`
+    pub fn compute(game: &Game, id: u8) -> QuoridorResult<Features>
+    {
+        if game.players.len() != 2 {
+            return Err(QuoridorError::AnalysisError("Only two player games can be evaluated".into()))
+        }
+        let me = try!(game.players.values().find(|p| p.id == id).ok_or(QuoridorError::PlayerNotFound));
+        let them = game.players.values().find(|p| p.id != id).unwrap();
+
+        let (my_path, my_region, my_rows) = player_stats(game, me);
+        let (their_path, their_region, their_rows) = player_stats(game, them);
+        Ok(Features([
+            their_path - my_path,
+            me.walls as f64 - them.walls as f64,
+            game.valid_moves(me.p).len() as f64 - game.valid_moves(them.p).len() as f64,
+            their_rows - my_rows,
+            my_region - their_region,
+        ]))
+    }
+}
+
+impl Evaluator {
+    /// Create an evaluator with the given weights
+    pub fn new(weights: [f64; FEATURES]) -> Evaluator
+    {
+        Evaluator { weights: weights }
+    }
+
+    /// Weighted sum of the features
+    pub fn score(&self, features: &Features) -> f64
+    {
+        self.weights.iter().zip(features.0.iter()).map(|(w, f)| w * f).sum()
+    }
+
+    /// Score `game` for player `id`; positive is good for them
+    pub fn evaluate(&self, game: &Game, id: u8) -> QuoridorResult<f64>
+    {
+        Ok(self.score(&try!(Features::compute(game, id))))
+    }
+
+    /// Return the weights as JSON, keyed by feature name
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        for (name, w) in FEATURE_NAMES.iter().zip(self.weights.iter()) {
+            d.insert(name.to_string(), w.to_json());
+        }
+        Json::Object(d)
+    }
+
+    /// Parse weights written by `to_json`.  Missing features get a
+    /// weight of zero.
+    pub fn from_json(json: &Json) -> QuoridorResult<Evaluator>
+    {
+        if !json.is_object() {
+            return Err(QuoridorError::ParseError(format!("Unable to parse weights {}", json)))
+        }
+        let mut weights = [0.0; FEATURES];
+        for (i, name) in FEATURE_NAMES.iter().enumerate() {
+            weights[i] = json.find(name).and_then(|w| w.as_f64()).unwrap_or(0.0);
+        }
+        Ok(Evaluator::new(weights))
+    }
+}
+
+impl Default for Evaluator {
+    /// Hand tuned weights
+    fn default() -> Evaluator
+    {
+        Evaluator::new([1.0, 0.3, 0.05, 0.1, 0.01])
+    }
+}
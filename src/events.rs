@@ -0,0 +1,243 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Game events pushed to clients
+//!
+//! Every change to a hosted game is described by events numbered in
+//! the order they happened, so a client that reconnects can ask for
+//! everything after the last number it saw.
+
+use board::{Point, Wall};
+use domain::DomainEvent;
+use quoridor::Game;
+use result::GameResult;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use turn::Turn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    PlayerJoined { name: String, seat: u8 },
+    Move { seat: u8, from: Point, to: Point },
+    Wall { seat: u8, wall: Wall },
+    DrawOffered { seat: u8 },
+    DrawDeclined { seat: u8 },
+    TakebackRequested { seat: u8 },
+    TakebackDeclined { seat: u8 },
+    /// The last move of `seat` was taken back, leaving `turns` turns
+    TakenBack { seat: u8, turns: usize },
+    /// `seat` swapped sides with `with` under the pie rule
+    SidesSwapped { seat: u8, with: u8 },
+    GameOver { result: GameResult },
+    Clock { remaining: Vec<Option<u64>>, running: Option<u8> },
+}
+
+/// An event, its sequence number and when it happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub seq: u64,
+    pub time: Instant,
+    pub event: GameEvent,
+}
+
+/// Every event of a game so far
+pub struct EventLog {
+    events: Vec<Event>,
+    /// Set when the game is removed and no more events will follow
+    pub closed: bool,
+}
+
+fn point_json(p: Point) -> Json
+{
+    vec![p.x, p.y].to_json()
+}
+
+impl GameEvent {
+    /// Name of the event in JSON
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            GameEvent::PlayerJoined { .. } => "joined",
+            GameEvent::Move { .. } => "move",
+            GameEvent::Wall { .. } => "wall",
+            GameEvent::DrawOffered { .. } => "draw_offered",
+            GameEvent::DrawDeclined { .. } => "draw_declined",
+            GameEvent::TakebackRequested { .. } => "takeback_requested",
+            GameEvent::TakebackDeclined { .. } => "takeback_declined",
+            GameEvent::TakenBack { .. } => "taken_back",
+            GameEvent::SidesSwapped { .. } => "sides_swapped",
+            GameEvent::GameOver { .. } => "game_over",
+            GameEvent::Clock { .. } => "clock",
+        }
+    }
+}
+
+impl Event {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("seq".to_string(), self.seq.to_json());
+        d.insert("type".to_string(), self.event.name().to_json());
+        match self.event {
+            GameEvent::PlayerJoined { ref name, seat } => {
+                d.insert("name".to_string(), name.to_json());
+                d.insert("seat".to_string(), seat.to_json());
+            },
+            GameEvent::Move { seat, from, to } => {
+                d.insert("seat".to_string(), seat.to_json());
+                d.insert("from".to_string(), point_json(from));
+                d.insert("to".to_string(), point_json(to));
+            },
+            GameEvent::Wall { seat, wall } => {
+                let (a, b) = wall.to_tuples();
+                d.insert("seat".to_string(), seat.to_json());
+                d.insert("wall".to_string(), vec![vec![a.0, a.1], vec![b.0, b.1]].to_json());
+            },
+            GameEvent::DrawOffered { seat } | GameEvent::DrawDeclined { seat } |
+            GameEvent::TakebackRequested { seat } | GameEvent::TakebackDeclined { seat } => {
+                d.insert("seat".to_string(), seat.to_json());
+            },
+            GameEvent::TakenBack { seat, turns } => {
+                d.insert("seat".to_string(), seat.to_json());
+                d.insert("turns".to_string(), turns.to_json());
+            },
+            GameEvent::SidesSwapped { seat, with } => {
+                d.insert("seat".to_string(), seat.to_json());
+                d.insert("with".to_string(), with.to_json());
+            },
+            GameEvent::GameOver { result } => {
+                d.insert("winner".to_string(), result.winner.to_json());
+                d.insert("reason".to_string(), result.reason.name().to_string().to_json());
+            },
+            GameEvent::Clock { ref remaining, running } => {
+                d.insert("remaining".to_string(), remaining.to_json());
+                d.insert("running".to_string(), running.to_json());
+            },
+        }
+        Json::Object(d)
+    }
+}
+
+impl EventLog {
+    pub fn new() -> EventLog
+    {
+        EventLog {
+            events: vec![],
+            closed: false,
+        }
+    }
+
+    /// Sequence number of the latest event, 0 if there are none
+    pub fn last_seq(&self) -> u64
+    {
+        self.events.len() as u64
+    }
+
+    /// Append an event, returning its sequence number
+    pub fn push(&mut self, event: GameEvent) -> u64
+    {
+        let seq = self.last_seq() + 1;
+        self.events.push(Event { seq: seq, time: Instant::now(), event: event });
+        seq
+    }
+
+    /// Events numbered after `seq`
+    pub fn since(&self, seq: u64) -> Vec<Event>
+    {
+        let start = ::std::cmp::min(seq, self.last_seq()) as usize;
+        self.events[start..].to_vec()
+    }
+}
+
+/// The turns of `game` described by `events`, which must be the
+/// latest events of the game
+pub fn turns_since(events: &[Event], game: &Game) -> Vec<Turn>
+{
+    let count = events.iter().filter(|e| match e.event {
+        GameEvent::Move { .. } | GameEvent::Wall { .. } => true,
+        _ => false,
+    }).count();
+    // Everything after a takeback is new
+    let taken_back = events.iter().rev().filter_map(|e| match e.event {
+        GameEvent::TakenBack { turns, .. } => Some(turns),
+        _ => None,
+    }).next();
+    let start = match taken_back {
+        Some(turns) => cmp::min(turns, game.history.len()),
+        None => game.history.len().saturating_sub(count),
+    };
+    game.history[start..].to_vec()
+}
+
+/// Describe the change to `after` since it had `len` domain events,
+/// the domain events recorded since, as events
+pub fn diff(len: usize, after: &Game) -> Vec<GameEvent>
+{
+    let mut events = vec![];
+    let mut ended = None;
+    let mut clock = false;
+
+    for event in after.stream.events().iter().skip(len) {
+        match *event {
+            DomainEvent::PlayerJoined { ref name, seat, .. } =>
+                events.push(GameEvent::PlayerJoined { name: name.clone(), seat: seat }),
+            DomainEvent::PawnMoved { seat, from, to } =>
+                events.push(GameEvent::Move { seat: seat, from: from, to: to }),
+            DomainEvent::WallPlaced { seat: Some(seat), wall } =>
+                events.push(GameEvent::Wall { seat: seat, wall: wall }),
+            DomainEvent::DrawOffered { seat } => events.push(GameEvent::DrawOffered { seat: seat }),
+            DomainEvent::DrawDeclined { seat } => events.push(GameEvent::DrawDeclined { seat: seat }),
+            DomainEvent::TakebackRequested { seat } => events.push(GameEvent::TakebackRequested { seat: seat }),
+            DomainEvent::TakebackDeclined { seat } => events.push(GameEvent::TakebackDeclined { seat: seat }),
+            DomainEvent::TakenBack { seat, to } => {
+                let turns = after.stream.in_effect(to).into_iter().filter(|&i| after.stream.events()[i].turn().is_some()).count();
+                events.push(GameEvent::TakenBack { seat: seat, turns: turns })
+            },
+            DomainEvent::SidesSwapped { seat, with } => events.push(GameEvent::SidesSwapped { seat: seat, with: with }),
+            DomainEvent::GameEnded { result } => ended = Some(result),
+            _ => {},
+        }
+        clock = clock || match *event {
+            DomainEvent::PlayerJoined { .. } | DomainEvent::WallPlaced { seat: None, .. } |
+            DomainEvent::DrawOffered { .. } | DomainEvent::DrawDeclined { .. } |
+            DomainEvent::TakebackRequested { .. } | DomainEvent::TakebackDeclined { .. } => false,
+            _ => true,
+        };
+    }
+
+    if let Some(ref c) = after.clock {
+        if clock {
+            let now = Instant::now();
+            events.push(GameEvent::Clock {
+                remaining: (0..c.remaining.len() as u8).map(|s| c.time_left(s, now)).collect(),
+                running: c.running(),
+            });
+        }
+    }
+
+    if let Some(result) = ended {
+        events.push(GameEvent::GameOver { result: result });
+    }
+    events
+}
@@ -0,0 +1,382 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Write-ahead journal of the games in progress
+//!
+//! Every change to a game is appended to the journal, and synced to
+//! disk, before the player who made it gets an answer: the game being
+//! created, each player joining and the seat they took, each turn
+//! taken (with the game's version, the player, the time and the clocks
+//! after the turn), each move taken back and sides being swapped.  An entry marks each game that ends or is
+//! deleted.
+//!
+//! On startup the journal is replayed to rebuild the games still in
+//! progress, under their old ids, and rewritten with only their
+//! entries and the highest id used so far, which isn't given out again.  The player to move gets back the time they had when the
+//! last turn was taken; the time the server was down isn't counted.
+
+use domain::DomainEvent;
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameOptions, GameState};
+use registry::Registry;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use turn::Turn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Create { game: String, options: GameOptions },
+    /// Journals from before seats could be chosen have no seat
+    Join { game: String, name: String, key_hash: String, account: Option<u64>, seat: Option<u8> },
+    Turn {
+        game: String,
+        version: u64,
+        player: String,
+        turn: Turn,
+        /// Unix time in milliseconds
+        time: u64,
+        /// Time each player had left after the turn, if timed
+        clocks: Option<Vec<u64>>,
+    },
+    /// `player` took back their last move
+    Takeback { game: String, player: String },
+    /// `player` swapped sides under the pie rule
+    Swap { game: String, player: String },
+    /// The game ended or was deleted
+    End { game: String },
+    /// No new game is given this id or any numeric id before it
+    Reserve { game: String },
+}
+
+pub struct Journal {
+    file: File,
+    /// Games with entries in the journal
+    games: HashSet<String>,
+}
+
+/// Milliseconds since the Unix epoch
+fn now_millis() -> u64
+{
+    let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+}
+
+impl Entry {
+    /// Id of the game the entry is for
+    pub fn game(&self) -> &str
+    {
+        match *self {
+            Entry::Create { ref game, .. } | Entry::Join { ref game, .. } |
+            Entry::Turn { ref game, .. } | Entry::Takeback { ref game, .. } |
+            Entry::Swap { ref game, .. } | Entry::End { ref game } | Entry::Reserve { ref game } => game,
+        }
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("game".to_string(), self.game().to_json());
+        let kind = match *self {
+            Entry::Create { ref options, .. } => {
+                d.insert("options".to_string(), options.to_json());
+                "create"
+            },
+            Entry::Join { ref name, ref key_hash, account, seat, .. } => {
+                d.insert("name".to_string(), name.to_json());
+                d.insert("key_hash".to_string(), key_hash.to_json());
+                d.insert("account".to_string(), account.to_json());
+                d.insert("seat".to_string(), seat.to_json());
+                "join"
+            },
+            Entry::Turn { version, ref player, ref turn, time, ref clocks, .. } => {
+                d.insert("version".to_string(), version.to_json());
+                d.insert("player".to_string(), player.to_json());
+                d.insert("turn".to_string(), turn.to_json());
+                d.insert("time".to_string(), time.to_json());
+                d.insert("clocks".to_string(), clocks.to_json());
+                "turn"
+            },
+            Entry::Takeback { ref player, .. } => {
+                d.insert("player".to_string(), player.to_json());
+                "takeback"
+            },
+            Entry::Swap { ref player, .. } => {
+                d.insert("player".to_string(), player.to_json());
+                "swap"
+            },
+            Entry::End { .. } => "end",
+            Entry::Reserve { .. } => "reserve",
+        };
+        d.insert("type".to_string(), kind.to_json());
+        Json::Object(d)
+    }
+
+    /// Parse an entry written by `to_json`
+    pub fn from_json(json: &Json) -> QuoridorResult<Entry>
+    {
+        let invalid = || QuoridorError::ParseError(format!("Invalid journal entry {}", json));
+        let string = |key| json.find(key).and_then(|v| v.as_string()).map(|s| s.to_string()).ok_or_else(&invalid);
+        let number = |key| json.find(key).and_then(|v| v.as_u64()).ok_or_else(&invalid);
+        let game = try!(string("game"));
+        match json.find("type").and_then(|t| t.as_string()) {
+            Some("create") => Ok(Entry::Create {
+                game: game,
+                options: try!(json.find("options").and_then(GameOptions::from_json).ok_or_else(&invalid)),
+            }),
+            Some("join") => Ok(Entry::Join {
+                game: game,
+                name: try!(string("name")),
+                key_hash: try!(string("key_hash")),
+                account: json.find("account").and_then(|a| a.as_u64()),
+                seat: json.find("seat").and_then(|s| s.as_u64()).map(|s| s as u8),
+            }),
+            Some("turn") => Ok(Entry::Turn {
+                game: game,
+                version: try!(number("version")),
+                player: try!(string("player")),
+                turn: try!(Turn::from_json(try!(json.find("turn").ok_or_else(&invalid)))),
+                time: try!(number("time")),
+                clocks: match json.find("clocks").and_then(|c| c.as_array()) {
+                    Some(clocks) => Some(try!(clocks.iter().map(|c| c.as_u64().ok_or_else(&invalid)).collect())),
+                    None => None,
+                },
+            }),
+            Some("takeback") => Ok(Entry::Takeback { game: game, player: try!(string("player")) }),
+            Some("swap") => Ok(Entry::Swap { game: game, player: try!(string("player")) }),
+            Some("end") => Ok(Entry::End { game: game }),
+            Some("reserve") => Ok(Entry::Reserve { game: game }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Journal {
+    /// Open the journal in `path`, creating it if it doesn't exist.
+    /// Returns the journal, rewritten with only the games still in
+    /// progress, and their entries.
+    pub fn open(path: PathBuf) -> QuoridorResult<(Journal, Vec<Entry>)>
+    {
+        let mut entries = vec![];
+        if path.exists() {
+            let reader = BufReader::new(try!(File::open(&path)));
+            for line in reader.lines() {
+                let line = try!(line);
+                // The last line is cut short if the server died
+                // writing it
+                let entry = match Json::from_str(&line) {
+                    Ok(json) => try!(Entry::from_json(&json)),
+                    Err(_) => break,
+                };
+                entries.push(entry);
+            }
+        }
+        let ended: HashSet<String> = entries.iter().filter_map(|e| match *e {
+            Entry::End { ref game } => Some(game.clone()),
+            _ => None,
+        }).collect();
+        // Ended games are dropped, but their ids stay used
+        let highest = entries.iter().filter_map(|e| e.game().parse::<u64>().ok()).max();
+        entries.retain(|e| !ended.contains(e.game()) && match *e {
+            Entry::Reserve { .. } => false,
+            _ => true,
+        });
+        if let Some(highest) = highest {
+            entries.insert(0, Entry::Reserve { game: highest.to_string() });
+        }
+
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(try!(File::create(&tmp)));
+            for entry in &entries {
+                try!(writeln!(w, "{}", entry.to_json()));
+            }
+            try!(w.flush());
+            try!(w.get_ref().sync_all());
+        }
+        try!(fs::rename(&tmp, &path));
+
+        let journal = Journal {
+            file: try!(OpenOptions::new().append(true).open(&path)),
+            games: entries.iter().filter(|e| match **e {
+                Entry::Reserve { .. } => false,
+                _ => true,
+            }).map(|e| e.game().to_string()).collect(),
+        };
+        Ok((journal, entries))
+    }
+
+    /// Write entries to the end of the journal and sync them to disk
+    pub fn append(&mut self, entries: &[Entry]) -> QuoridorResult<()>
+    {
+        if entries.is_empty() {
+            return Ok(())
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&format!("{}\n", entry.to_json()));
+            match *entry {
+                Entry::End { ref game } => self.games.remove(game),
+                ref e => self.games.insert(e.game().to_string()),
+            };
+        }
+        try!(self.file.write_all(lines.as_bytes()));
+        try!(self.file.sync_data());
+        Ok(())
+    }
+
+    /// Journal the change to the game with id `id` since it had `len`
+    /// domain events; it now has version `version`.  A game is first
+    /// journaled when it is created, or for the default game when
+    /// somebody joins it.
+    pub fn record(&mut self, id: &str, version: u64, len: usize, after: &Game) -> QuoridorResult<()>
+    {
+        let mut entries = vec![];
+        let fresh = !self.games.contains(id);
+        if fresh {
+            entries.push(Entry::Create { game: id.to_string(), options: after.options.clone() });
+        }
+
+        let since = &after.stream.events()[len..];
+        let joined = |name: &str| since.iter().any(|e| match *e {
+            DomainEvent::PlayerJoined { name: ref n, .. } => n == name,
+            _ => false,
+        });
+        let mut players: Vec<_> = after.players.values().collect();
+        players.sort_by_key(|p| p.id);
+        for p in players.iter().filter(|p| fresh || joined(&p.name)) {
+            entries.push(Entry::Join {
+                game: id.to_string(),
+                name: p.name.clone(),
+                key_hash: p.key_hash.clone(),
+                account: p.account,
+                seat: Some(p.id),
+            });
+        }
+
+        for event in since {
+            match *event {
+                DomainEvent::TakenBack { seat, .. } =>
+                    entries.push(Entry::Takeback { game: id.to_string(), player: players[seat as usize].name.clone() }),
+                // The player now in the seat of the one who swapped
+                DomainEvent::SidesSwapped { with, .. } =>
+                    entries.push(Entry::Swap { game: id.to_string(), player: players[with as usize].name.clone() }),
+                _ => (),
+            }
+        }
+
+        let first = if fresh { 0 } else { cmp::min(after.history_len(len), after.history.len()) };
+        let first_seat = after.first_seat() as usize;
+        for ply in first..after.history.len() {
+            let last = ply + 1 == after.history.len();
+            entries.push(Entry::Turn {
+                game: id.to_string(),
+                version: version,
+                player: players[(first_seat + ply) % players.len()].name.clone(),
+                turn: after.history[ply].clone(),
+                time: now_millis(),
+                clocks: after.clock.as_ref().and_then(|c| if last { Some(c.remaining.clone()) } else { None }),
+            });
+        }
+
+        if after.state == GameState::GameOver {
+            entries.push(Entry::End { game: id.to_string() });
+        }
+        self.append(&entries)
+    }
+
+    /// Journal that a game was deleted
+    pub fn remove(&mut self, id: &str) -> QuoridorResult<()>
+    {
+        match self.games.contains(id) {
+            true => self.append(&[Entry::End { game: id.to_string() }]),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Rebuild the games described by journal entries in the registry,
+/// replacing any games with the same ids, and return their ids.  The
+/// games' events are published again as they are replayed.
+pub fn recover(registry: &Registry, entries: &[Entry]) -> QuoridorResult<Vec<String>>
+{
+    let mut ids = vec![];
+    for entry in entries {
+        match *entry {
+            Entry::Create { ref game, ref options } => {
+                registry.insert(game.clone(), Game::with_options(options.clone()));
+                ids.push(game.clone());
+                continue
+            },
+            Entry::Reserve { ref game } => {
+                registry.reserve(game);
+                continue
+            },
+            _ => (),
+        }
+        let game_entry = try!(registry.get(entry.game()).ok_or_else(|| {
+            QuoridorError::ParseError(format!("Journal entry for game {} before it was created", entry.game()))
+        }));
+        let mut game = game_entry.game.write().unwrap();
+        let len = game.stream.len();
+        match *entry {
+            Entry::Join { ref name, ref key_hash, account, seat, .. } => {
+                try!(match seat {
+                    Some(seat) => game.seat_player(name.clone(), key_hash.clone(), seat),
+                    None => game.add_player_with_hash(name.clone(), key_hash.clone()),
+                });
+                game.players.get_mut(name).unwrap().account = account;
+            },
+            Entry::Turn { ref player, ref turn, ref clocks, .. } => {
+                let player = try!(game.players.get(player).cloned().ok_or(QuoridorError::PlayerNotFound));
+                if game.state != GameState::Started(player.id) {
+                    return Err(QuoridorError::TurnError(format!("Journaled turn out of order for {}", player.name)))
+                }
+                try!(turn.take(&mut game, &player));
+                try!(game.increment_turn());
+                if let (Some(clock), &Some(ref remaining)) = (game.clock.as_mut(), clocks) {
+                    clock.remaining = remaining.clone();
+                    if let Some(log) = clock.log.last_mut() {
+                        *log = remaining[player.id as usize];
+                    }
+                }
+            },
+            Entry::Takeback { ref player, .. } => {
+                let seat = try!(game.players.get(player).map(|p| p.id).ok_or(QuoridorError::PlayerNotFound));
+                try!(game.take_back(seat));
+            },
+            Entry::Swap { ref player, .. } => {
+                try!(game.swap_sides(player));
+            },
+            _ => (),
+        }
+        try!(game_entry.publish_changes(len, &game));
+    }
+    Ok(ids)
+}
@@ -24,12 +24,17 @@
 #[macro_use]
 extern crate log;
 extern crate num;
+extern crate rand;
 extern crate env_logger;
 extern crate rustc_serialize;
 extern crate router;
 extern crate iron;
 extern crate mount;
 extern crate staticfile;
+extern crate ws;
+extern crate crypto;
+#[macro_use]
+extern crate rusqlite;
 
 pub mod player;
 pub mod constants;
@@ -40,3 +45,29 @@ pub mod quoridor;
 pub mod turn;
 pub mod adjacency_matrix;
 pub mod errors;
+pub mod solver;
+pub mod tablebase;
+pub mod smallboard;
+pub mod record;
+pub mod book;
+pub mod symmetry;
+pub mod evaluator;
+pub mod selfplay;
+pub mod tuning;
+pub mod registry;
+pub mod lobby;
+pub mod clock;
+pub mod events;
+pub mod domain;
+pub mod result;
+pub mod series;
+pub mod setup;
+pub mod websocket;
+pub mod sse;
+pub mod spectators;
+pub mod auth;
+pub mod accounts;
+pub mod rating;
+pub mod tournament;
+pub mod storage;
+pub mod journal;
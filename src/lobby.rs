@@ -0,0 +1,296 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Lobby and matchmaking
+//!
+//! Players waiting in the lobby queue are paired automatically, first
+//! come first served, except that two players who both give a rating
+//! are only paired if their ratings are within the lobby's rating
+//! band.  Players can also post challenges for somebody else to accept;
+//! private challenges are left out of listings and accepted with a
+//! join code.  Matched players get a game id, seat and, when the lobby
+//! has sessions, the bearer token to play the game with.  Players come
+//! in already identified, by `accounts::identify`, so that their keys
+//! are never hashed while the lobby is locked.
+
+use accounts::Accounts;
+use auth::{self, Sessions};
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::Game;
+use rand::{self, Rng};
+use registry::{Entrant, Registry};
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Where a matched player plays
+#[derive(Debug, Clone, PartialEq)]
+pub struct Seat {
+    pub game: String,
+    pub seat: u8,
+    /// Session token for the game
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct LobbyPlayer {
+    name: String,
+    key_hash: String,
+    account: Option<u64>,
+    rating: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    pub id: String,
+    pub name: String,
+    /// Join code of a private challenge
+    pub code: Option<String>,
+    key_hash: String,
+    account: Option<u64>,
+}
+
+pub struct Lobby {
+    /// Largest rating difference between paired players, if any
+    pub rating_band: Option<f64>,
+    /// Accounts of the server, if it has any
+    pub accounts: Option<Arc<Mutex<Accounts>>>,
+    /// Sessions of the server, to hand matched players a token
+    pub sessions: Option<Arc<Mutex<Sessions>>>,
+    queue: Vec<LobbyPlayer>,
+    challenges: Vec<Challenge>,
+    matched: HashMap<String, (String, Seat)>,
+    next_challenge: u64,
+}
+
+impl Seat {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("status".to_string(), "matched".to_json());
+        d.insert("game".to_string(), self.game.to_json());
+        d.insert("seat".to_string(), self.seat.to_json());
+        if let Some(ref token) = self.token {
+            d.insert("token".to_string(), token.to_json());
+        }
+        Json::Object(d)
+    }
+}
+
+impl Challenge {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_json());
+        d.insert("name".to_string(), self.name.to_json());
+        d.insert("code".to_string(), self.code.to_json());
+        Json::Object(d)
+    }
+}
+
+impl Lobby {
+    pub fn new() -> Lobby
+    {
+        Lobby {
+            rating_band: Some(200.0),
+            accounts: None,
+            sessions: None,
+            queue: vec![],
+            challenges: vec![],
+            matched: HashMap::new(),
+            next_challenge: 1,
+        }
+    }
+
+    /// Create a game between two players and record their seats
+    fn start_game(&mut self, registry: &Registry, a: LobbyPlayer, b: LobbyPlayer) -> QuoridorResult<Seat>
+    {
+        let players: Vec<_> = [&a, &b].iter().map(|p| Entrant {
+            name: p.name.clone(),
+            key_hash: p.key_hash.clone(),
+            account: p.account,
+        }).collect();
+        let id = try!(registry.create_with_players(Game::new(), &players));
+
+        let token = |name: &str| self.sessions.as_ref().map(|sessions| {
+            sessions.lock().unwrap().create(id.clone(), name.to_string(), Instant::now()).token
+        });
+        let seat_a = Seat { game: id.clone(), seat: 0, token: token(&a.name) };
+        let seat_b = Seat { game: id.clone(), seat: 1, token: token(&b.name) };
+        self.matched.insert(a.name, (a.key_hash, seat_a));
+        self.matched.insert(b.name, (b.key_hash, seat_b.clone()));
+        Ok(seat_b)
+    }
+
+    /// Rating of a player's account, if they have one
+    fn account_rating(&self, entrant: &Entrant) -> Option<f64>
+    {
+        match (&self.accounts, entrant.account) {
+            (&Some(ref accounts), Some(id)) => accounts.lock().unwrap().get(id).map(|a| a.rating.rating),
+            _ => None,
+        }
+    }
+
+    /// Is `name` already waiting in the queue or on a challenge
+    fn is_waiting(&self, name: &str) -> bool
+    {
+        self.queue.iter().any(|p| p.name == name) || self.challenges.iter().any(|c| c.name == name)
+    }
+
+    /// Does `key` belong to `name` where they are waiting
+    fn check_key(&self, name: &str, key: &str) -> QuoridorResult<()>
+    {
+        if self.queue.iter().any(|p| p.name == name && !auth::verify_key(key, &p.key_hash))
+            || self.challenges.iter().any(|c| c.name == name && !auth::verify_key(key, &c.key_hash)) {
+            return Err(QuoridorError::Unauthorized)
+        }
+        Ok(())
+    }
+
+    /// Join the queue, returning the seat straight away if somebody
+    /// suitable is already waiting.  Players with an account are
+    /// paired by their account's rating.
+    pub fn join(&mut self, registry: &Registry, entrant: Entrant, rating: Option<f64>) -> QuoridorResult<Option<Seat>>
+    {
+        let rating = self.account_rating(&entrant).or(rating);
+        let player = LobbyPlayer { name: entrant.name, key_hash: entrant.key_hash, account: entrant.account, rating: rating };
+        if self.is_waiting(&player.name) {
+            return Err(QuoridorError::RegistrationError(format!("{} is already waiting", player.name)))
+        }
+        self.matched.remove(&player.name);
+
+        let band = self.rating_band;
+        let opponent = self.queue.iter().position(|p| match (band, p.rating, player.rating) {
+            (Some(band), Some(a), Some(b)) => (a - b).abs() <= band,
+            _ => true,
+        });
+        match opponent {
+            Some(i) => {
+                let opponent = self.queue.remove(i);
+                self.start_game(registry, opponent, player).map(Some)
+            },
+            None => {
+                self.queue.push(player);
+                Ok(None)
+            },
+        }
+    }
+
+    /// Leave the queue and withdraw any challenges
+    pub fn leave(&mut self, name: &str, key: &str) -> QuoridorResult<()>
+    {
+        if !self.is_waiting(name) {
+            return Err(QuoridorError::PlayerNotFound)
+        }
+        try!(self.check_key(name, key));
+        self.queue.retain(|p| p.name != name);
+        self.challenges.retain(|c| c.name != name);
+        Ok(())
+    }
+
+    /// The seat `name` was given, or None if they are still waiting
+    pub fn status(&self, name: &str, key: &str) -> QuoridorResult<Option<Seat>>
+    {
+        match self.matched.get(name) {
+            Some(&(ref hash, _)) if !auth::verify_key(key, hash) => Err(QuoridorError::Unauthorized),
+            Some(&(_, ref seat)) => Ok(Some(seat.clone())),
+            None if self.is_waiting(name) => self.check_key(name, key).map(|_| None),
+            None => Err(QuoridorError::PlayerNotFound),
+        }
+    }
+
+    /// Post a challenge for somebody else to accept.  Private
+    /// challenges get a join code and are left out of listings.
+    pub fn challenge(&mut self, entrant: Entrant, private: bool) -> QuoridorResult<Challenge>
+    {
+        let Entrant { name, key_hash, account } = entrant;
+        if self.is_waiting(&name) {
+            return Err(QuoridorError::RegistrationError(format!("{} is already waiting", name)))
+        }
+        self.matched.remove(&name);
+
+        let code = match private {
+            true => Some(rand::thread_rng().gen_ascii_chars().take(8).collect()),
+            false => None,
+        };
+        let challenge = Challenge {
+            id: self.next_challenge.to_string(),
+            name: name,
+            code: code,
+            key_hash: key_hash,
+            account: account,
+        };
+        self.next_challenge += 1;
+        self.challenges.push(challenge.clone());
+        Ok(challenge)
+    }
+
+    /// Public challenges waiting to be accepted
+    pub fn open_challenges(&self) -> Vec<&Challenge>
+    {
+        self.challenges.iter().filter(|c| c.code.is_none()).collect()
+    }
+
+    /// Accept a public challenge by id
+    pub fn accept(&mut self, registry: &Registry, id: &str, entrant: Entrant) -> QuoridorResult<Seat>
+    {
+        let i = self.challenges.iter().position(|c| c.id == id && c.code.is_none());
+        self.take_challenge(registry, i, entrant)
+    }
+
+    /// Accept a private challenge by its join code
+    pub fn accept_code(&mut self, registry: &Registry, code: &str, entrant: Entrant) -> QuoridorResult<Seat>
+    {
+        let i = self.challenges.iter().position(|c| c.code.as_ref().map_or(false, |c| c == code));
+        self.take_challenge(registry, i, entrant)
+    }
+
+    fn take_challenge(&mut self, registry: &Registry, i: Option<usize>, entrant: Entrant) -> QuoridorResult<Seat>
+    {
+        let Entrant { name, key_hash, account } = entrant;
+        let i = match i {
+            Some(i) => i,
+            None => return Err(QuoridorError::RegistrationError("No such challenge".into())),
+        };
+        if self.challenges[i].name == name {
+            return Err(QuoridorError::RegistrationError("You can't accept your own challenge".into()))
+        }
+        if self.is_waiting(&name) {
+            return Err(QuoridorError::RegistrationError(format!("{} is already waiting", name)))
+        }
+
+        let c = self.challenges.remove(i);
+        let challenger = LobbyPlayer { name: c.name, key_hash: c.key_hash, account: c.account, rating: None };
+        let player = LobbyPlayer { name: name, key_hash: key_hash, account: account, rating: None };
+        self.start_game(registry, challenger, player)
+    }
+
+    /// Players waiting in the queue and the open challenges
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("waiting".to_string(), self.queue.iter().map(|p| p.name.clone()).collect::<Vec<_>>().to_json());
+        d.insert("challenges".to_string(), Json::Array(self.open_challenges().iter().map(|c| c.to_json()).collect()));
+        Json::Object(d)
+    }
+}
@@ -1,11 +1,19 @@
 extern crate quoridor;
 extern crate env_logger;
+extern crate rand;
+extern crate iron;
+extern crate rustc_serialize;
 
 use std::env;
+use std::path::{Path, PathBuf};
 
 mod tests;
 use quoridor::quoridor::Game;
-use quoridor::server::listen;
+use quoridor::rating::RatingSystem;
+use quoridor::server::{listen, ServerOptions};
+use quoridor::smallboard;
+use quoridor::tuning;
+use rand::{SeedableRng, StdRng};
 
 fn main() {
     env_logger::init().unwrap();
@@ -14,6 +22,64 @@ fn main() {
         println!("Missing required argument 'server': <host:port>");
         return
     }
+    if args[1] == "tune" {
+        tune(&args[2..]);
+        return
+    }
+    if args[1] == "tablebase" {
+        small_tablebase(&args[2..]);
+        return
+    }
+
+    let mut options = ServerOptions::default();
+    // Optionally push game events over WebSocket: <host:port>
+    options.websocket_host = args.get(2).cloned();
+    if let Ok(dir) = env::var("QUORIDOR_DATA") {
+        options.data_dir = PathBuf::from(dir);
+    }
+    // Lets whoever has it delete any game
+    options.admin_key = env::var("QUORIDOR_ADMIN_KEY").ok();
+    // Rate accounts with glicko2 (the default), elo or elo:<k>
+    if let Ok(name) = env::var("QUORIDOR_RATING") {
+        match RatingSystem::from_name(&name) {
+            Some(system) => options.rating_system = system,
+            None => {
+                println!("Unknown rating system '{}'", name);
+                return
+            }
+        }
+    }
+    listen(args[1].clone(), options, Game::new());
+}
+
+/// tune <games> <samples.csv> <weights.json> [seed]
+fn tune(args: &[String]) {
+    let games = match args.get(0).and_then(|g| g.parse::<usize>().ok()) {
+        Some(games) if args.len() >= 3 => games,
+        _ => {
+            println!("Usage: tune <games> <samples.csv> <weights.json> [seed]");
+            return
+        }
+    };
+    let seed = args.get(3).and_then(|s| s.parse::<usize>().ok()).unwrap_or(0);
+    let mut rng = StdRng::from_seed(&[seed][..]);
+    match tuning::run(games, &mut rng, Path::new(&args[1]), Path::new(&args[2])) {
+        Ok(evaluator) => println!("{}", evaluator.to_json()),
+        Err(e) => println!("Tuning failed: {:?}", e),
+    }
+}
 
-    listen(args[1].clone(), Game::new());
+/// tablebase <size> <walls> <dir>
+fn small_tablebase(args: &[String]) {
+    let (size, walls) = match (args.get(0).and_then(|s| s.parse::<usize>().ok()), args.get(1).and_then(|w| w.parse::<u8>().ok())) {
+        (Some(size), Some(walls)) if args.len() >= 3 => (size, walls),
+        _ => {
+            println!("Usage: tablebase <size> <walls> <dir>");
+            return
+        }
+    };
+    match smallboard::load_or_generate(Some(&PathBuf::from(&args[2])), size, walls, smallboard::MAX_POSITIONS) {
+        Ok(tablebase) => println!("Solved {} positions", tablebase.len()),
+        Err(e) => println!("Unable to solve the board: {}", e),
+    }
 }
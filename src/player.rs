@@ -25,25 +25,42 @@
 use rustc_serialize::json::Json;
 use rustc_serialize::json::ToJson;
 use std::collections::BTreeMap;
+use auth;
 use board::Point;
 use constants::N;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Player {
     pub p: Point,
-    pub key: String,
+    /// Salted hash of the player's key
+    pub key_hash: String,
     pub id: u8,
     pub walls: u8,
     pub name: String,
+    /// Id of the player's account, if they aren't a guest
+    pub account: Option<u64>,
 }
 
 
 impl Player {
+    /// Is `p` on the edge of the board this player is racing towards
+    pub fn is_goal(&self, p: Point) -> bool {
+        match self.id {
+            0 => p.y == N - 1,
+            1 => p.y == 0,
+            2 => p.x == N - 1,
+            3 => p.x == 0,
+            _ => false,
+        }
+    }
+
+    /// Is `key` this player's key
+    pub fn check_key(&self, key: &str) -> bool {
+        auth::verify_key(key, &self.key_hash)
+    }
+
     pub fn has_won(&self) -> bool {
-        (self.id != 1 && self.p.y < 0)
-            || (self.id != 2 && self.p.x < 0)
-            || (self.id != 0 && self.p.y >= N)
-            || (self.id != 3 && self.p.y >= N)
+        self.is_goal(self.p)
     }
 
     pub fn to_json(&self) -> Json {
@@ -52,6 +69,9 @@ impl Player {
         d.insert("id".to_string(), self.id.to_json());
         d.insert("walls".to_string(), self.walls.to_json());
         d.insert("name".to_string(), self.name.to_json());
+        if let Some(account) = self.account {
+            d.insert("account".to_string(), account.to_json());
+        }
         Json::Object(d)
     }
 }
@@ -23,28 +23,213 @@
 //! Quoridor game logic
 
 use adjacency_matrix::AdjacencyMatrix;
+use auth;
 use board::{Point, Wall, Orientation, Direction};
+use clock::{Clock, TimeControl};
 use constants::{N, MAX_DIST};
+use domain::{DomainEvent, EventStream};
 use errors::{QuoridorError, QuoridorResult};
 use player::Player;
+use rand::{self, Rng};
+use result::{GameResult, Reason};
+use setup::{Setup, default_start};
+use turn::Turn;
 use rustc_serialize::json::Json;
 use rustc_serialize::json::ToJson;
 use std::cmp;
 use std::collections::{HashMap, HashSet, BTreeMap};
 use std::fmt;
+use std::time::Instant;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GameState {
     Setup,
     GameOver,
     Started(u8),
 }
 
-#[derive(Debug)]
+/// Whether players may take back a move once their opponent agrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Takebacks {
+    Forbidden,
+    /// One takeback for each player per game
+    Allowed,
+    /// As many as the opponent agrees to, for casual games
+    Unlimited,
+}
+
+/// How players who don't choose a seat are given one, and with it
+/// whether they move first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seating {
+    /// In the order they join
+    InOrder,
+    /// At random, so the first to join doesn't always move first
+    Random,
+}
+
+/// Settings chosen when a game is created
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameOptions {
+    /// Walls each player starts with.  Four player games get half.
+    pub walls: u8,
+    /// Clocks for the players, if the game is timed
+    pub time_control: Option<TimeControl>,
+    /// Seconds spectators are kept behind the live game
+    pub spectator_delay: u64,
+    /// Handicaps or a custom starting position
+    pub setup: Option<Setup>,
+    pub takebacks: Takebacks,
+    /// Times the same position may come up before the game is drawn
+    pub repetitions: Option<usize>,
+    /// Turns after which the game is drawn
+    pub max_plies: Option<usize>,
+    pub seating: Seating,
+    /// Whether the second player may swap sides with the first after
+    /// the first move
+    pub pie_rule: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct Game {
     pub walls: HashSet<Wall>,
     pub players: HashMap<String, Player>,
     pub state: GameState,
+    pub history: Vec<Turn>,
+    pub options: GameOptions,
+    pub clock: Option<Clock>,
+    /// Events the rest of the game is folded from
+    pub stream: EventStream,
+    /// How the game ended, once it is over
+    pub result: Option<GameResult>,
+    /// Seat of the player offering a draw, until it is answered
+    pub draw_offer: Option<u8>,
+    /// Seat of the player asking to take back a move, until it is
+    /// answered
+    pub takeback_request: Option<u8>,
+    /// `position_hash` at the start of each turn
+    pub positions: Vec<u64>,
+}
+
+impl Default for GameOptions {
+    fn default() -> GameOptions
+    {
+        GameOptions {
+            walls: 10,
+            time_control: None,
+            spectator_delay: 0,
+            setup: None,
+            takebacks: Takebacks::Forbidden,
+            repetitions: None,
+            max_plies: None,
+            seating: Seating::InOrder,
+            pie_rule: false,
+        }
+    }
+}
+
+impl Takebacks {
+    /// Name of the setting in JSON
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Takebacks::Forbidden => "forbidden",
+            Takebacks::Allowed => "allowed",
+            Takebacks::Unlimited => "unlimited",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Takebacks>
+    {
+        match name {
+            "forbidden" => Some(Takebacks::Forbidden),
+            "allowed" => Some(Takebacks::Allowed),
+            "unlimited" => Some(Takebacks::Unlimited),
+            _ => None,
+        }
+    }
+}
+
+impl Seating {
+    /// Name of the setting in JSON
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Seating::InOrder => "in_order",
+            Seating::Random => "random",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Seating>
+    {
+        match name {
+            "in_order" => Some(Seating::InOrder),
+            "random" => Some(Seating::Random),
+            _ => None,
+        }
+    }
+}
+
+impl GameOptions {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("walls".to_string(), self.walls.to_json());
+        d.insert("time_control".to_string(), self.time_control.as_ref().map_or(Json::Null, |c| c.to_json()));
+        d.insert("spectator_delay".to_string(), self.spectator_delay.to_json());
+        d.insert("setup".to_string(), self.setup.as_ref().map_or(Json::Null, |s| s.to_json()));
+        d.insert("takebacks".to_string(), self.takebacks.name().to_string().to_json());
+        d.insert("repetitions".to_string(), self.repetitions.to_json());
+        d.insert("max_plies".to_string(), self.max_plies.to_json());
+        d.insert("seating".to_string(), self.seating.name().to_string().to_json());
+        d.insert("pie_rule".to_string(), self.pie_rule.to_json());
+        Json::Object(d)
+    }
+
+    /// Parse options written by `to_json`.  Takebacks are forbidden,
+    /// players are seated in order and there is no pie rule if not
+    /// given.
+    pub fn from_json(json: &Json) -> Option<GameOptions>
+    {
+        let takebacks = match json.find("takebacks").map(|t| t.as_string().and_then(Takebacks::parse)) {
+            Some(Some(takebacks)) => takebacks,
+            Some(None) => return None,
+            None => Takebacks::Forbidden,
+        };
+        let seating = match json.find("seating").map(|s| s.as_string().and_then(Seating::parse)) {
+            Some(Some(seating)) => seating,
+            Some(None) => return None,
+            None => Seating::InOrder,
+        };
+        let time_control = match json.find("time_control") {
+            Some(&Json::Null) | None => None,
+            Some(control) => match TimeControl::from_json(control) {
+                Some(control) => Some(control),
+                None => return None,
+            },
+        };
+        let setup = match json.find("setup") {
+            Some(&Json::Null) | None => None,
+            Some(setup) => match Setup::from_json(setup) {
+                Some(setup) => Some(setup),
+                None => return None,
+            },
+        };
+        match (json.find("walls").and_then(|w| w.as_u64()), json.find("spectator_delay").and_then(|d| d.as_u64())) {
+            (Some(walls), Some(delay)) => Some(GameOptions {
+                walls: walls as u8,
+                time_control: time_control,
+                spectator_delay: delay,
+                setup: setup,
+                takebacks: takebacks,
+                repetitions: json.find("repetitions").and_then(|r| r.as_u64()).map(|r| r as usize),
+                max_plies: json.find("max_plies").and_then(|m| m.as_u64()).map(|m| m as usize),
+                seating: seating,
+                pie_rule: json.find("pie_rule").and_then(|p| p.as_boolean()).unwrap_or(false),
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl AdjacencyMatrix for Game {
@@ -60,11 +245,26 @@ impl AdjacencyMatrix for Game {
 impl Game {
     /// Create a default game
     pub fn new() -> Game
+    {
+        Game::with_options(GameOptions::default())
+    }
+
+    /// Create a game with the given settings, with any walls its setup
+    /// puts on the board
+    pub fn with_options(options: GameOptions) -> Game
     {
         Game {
             players: HashMap::new(),
-            walls: HashSet::new(),
+            walls: options.setup.as_ref().map_or(HashSet::new(), |s| s.board.iter().cloned().collect()),
             state: GameState::Setup,
+            history: vec![],
+            options: options,
+            clock: None,
+            stream: EventStream::default(),
+            result: None,
+            draw_offer: None,
+            takeback_request: None,
+            positions: vec![],
         }
     }
 
@@ -153,12 +353,10 @@ impl Game {
             return Err(QuoridorError::PlayerNotFound)
         }
 
-        let start = self.players[&name].p;
+        let (seat, start) = (self.players[&name].id, self.players[&name].p);
         let _ = try!(self.describe_adj(start, pos));
 
-        if let Some(p) = self.players.get_mut(&name) {
-            p.p = pos;
-        }
+        self.record(DomainEvent::PawnMoved { seat: seat, from: start, to: pos });
         return Ok(format!("Moved player to {:?}", &self.players[&name].p));
     }
 
@@ -230,11 +428,44 @@ impl Game {
         Err(QuoridorError::PlayerNotFound)
     }
 
+    /// Return every position a pawn at `p` could legally move to,
+    /// including jumps
+    pub fn valid_moves(&self, p: Point) -> Vec<Point>
+    {
+        let mut moves = vec![];
+        for dy in -2..3 {
+            for dx in -2..3 {
+                let q = point!(p.x + dx, p.y + dy);
+                if self.describe_adj(p, q).is_ok() {
+                    moves.push(q);
+                }
+            }
+        }
+        moves
+    }
+
     /// Place a wall from intersection a->b
     pub fn add_wall(&mut self, wall: &Wall) -> QuoridorResult<String>
     {
         let _ = try!(self.is_valid_wall(wall));
-        self.walls.insert(*wall);
+        self.record(DomainEvent::WallPlaced { seat: None, wall: *wall });
+        Ok("Added wall.".into())
+    }
+
+    /// Have player `name` place a wall from their hand
+    pub fn place_wall<S>(&mut self, name: S, wall: &Wall) -> QuoridorResult<String>
+        where S: Into<String>
+    {
+        let name = name.into();
+
+        let seat = match self.players.get(&name) {
+            None => return Err(QuoridorError::PlayerNotFound),
+            Some(p) if p.walls == 0 => return Err(QuoridorError::InvalidWall("No walls left to place".into())),
+            Some(p) => p.id,
+        };
+
+        let _ = try!(self.is_valid_wall(wall));
+        self.record(DomainEvent::WallPlaced { seat: Some(seat), wall: *wall });
         Ok("Added wall.".into())
     }
 
@@ -244,35 +475,101 @@ impl Game {
         self.add_wall(&wall)
     }
 
+    /// Seat that moves first, seat 0 unless the setup says otherwise
+    pub fn first_seat(&self) -> u8
+    {
+        self.options.setup.as_ref().map_or(0, |s| s.first)
+    }
+
     /// Starts the game, assigns wall chips, sets the turn
     pub fn start_game(&mut self) {
-        let turn = 0;
-        self.state = GameState::Started(turn);
-        if self.players.len() == 4 {
-            for (_, p) in self.players.iter_mut() {
-                p.walls = 5
+        let turn = self.first_seat();
+        self.record(DomainEvent::GameStarted { first: turn });
+        if let Some(ref control) = self.options.time_control {
+            let mut clock = Clock::new(control.clone(), self.players.len());
+            clock.start(turn, Instant::now());
+            self.clock = Some(clock);
+        }
+    }
+
+    /// End the game if the player to move has run out of time,
+    /// returning their seat
+    pub fn check_clock(&mut self, now: Instant) -> Option<u8>
+    {
+        if let GameState::Started(_) = self.state {
+            if let Some(seat) = self.clock.as_mut().and_then(|c| c.check(now)) {
+                let winner = self.winner();
+                self.record(DomainEvent::TimedOut { seat: seat });
+                self.record(DomainEvent::GameEnded { result: GameResult { winner: winner, reason: Reason::Timeout } });
+                return Some(seat)
             }
         }
+        None
     }
 
     /// Increment the turn counter
     pub fn increment_turn(&mut self) -> QuoridorResult<()> {
-        self.state = match self.state {
-            GameState::Started(turn) => {
-                if self.winner().is_some() {
-                    GameState::GameOver
-                } else {
-                    GameState::Started((turn + 1) % (self.players.len() as u8))
-                }
-            },
+        let turn = match self.state {
+            GameState::Started(turn) => turn,
             GameState::Setup => return Err(QuoridorError::TurnError("Game not started".into())),
             GameState::GameOver => return Err(QuoridorError::TurnError("Game is over".into())),
         };
+        let next = (turn + 1) % (self.players.len() as u8);
+        let in_time = match self.clock {
+            Some(ref mut clock) => clock.punch(next, Instant::now()),
+            None => true,
+        };
+        if !in_time {
+            self.record(DomainEvent::TimedOut { seat: turn });
+        }
+        let winner = self.winner();
+        if !in_time || winner.is_some() {
+            let reason = if in_time { Reason::Goal } else { Reason::Timeout };
+            self.end(GameResult { winner: winner, reason: reason });
+        } else {
+            self.record(DomainEvent::TurnEnded { next: next });
+            if let Some(reason) = self.draw_rule() {
+                self.end(GameResult::draw(reason));
+            }
+        }
         Ok(())
     }
 
+    /// Times the current position has come up
+    pub fn repetitions(&self) -> usize
+    {
+        match self.positions.last() {
+            Some(&last) => self.positions.iter().filter(|&&h| h == last).count(),
+            None => 0,
+        }
+    }
+
+    /// The draw rule the game has come under, if any
+    fn draw_rule(&self) -> Option<Reason>
+    {
+        match self.options.repetitions {
+            Some(limit) if self.repetitions() >= limit => return Some(Reason::Repetition),
+            _ => (),
+        }
+        match self.options.max_plies {
+            Some(max) if self.history.len() >= max => Some(Reason::MoveLimit),
+            _ => None,
+        }
+    }
+
+    /// Id of the player who won.  Running out of time loses a two
+    /// player game; a four player game ends with no winner.
     pub fn winner(&self) -> Option<u8>
     {
+        if let Some(ref result) = self.result {
+            return result.winner
+        }
+        if let Some(seat) = self.clock.as_ref().and_then(|c| c.flagged) {
+            return match self.players.len() {
+                2 => Some(1 - seat),
+                _ => None,
+            }
+        }
         for (_, p) in self.players.iter() {
             if p.has_won() {
                 return Some(p.id)
@@ -281,6 +578,206 @@ impl Game {
         None
     }
 
+    /// Seat of player `name` in a game that is under way
+    fn playing_seat(&self, name: &str) -> QuoridorResult<u8>
+    {
+        match self.state {
+            GameState::Setup => return Err(QuoridorError::TurnError("Game not started".into())),
+            GameState::GameOver => return Err(QuoridorError::TurnError("Game is over".into())),
+            GameState::Started(_) => (),
+        }
+        self.players.get(name).map(|p| p.id).ok_or(QuoridorError::PlayerNotFound)
+    }
+
+    /// End the game with `result`, stopping the clock
+    fn end(&mut self, result: GameResult)
+    {
+        if let Some(ref mut clock) = self.clock {
+            clock.stop();
+        }
+        self.record(DomainEvent::GameEnded { result: result });
+    }
+
+    /// Player `name` resigns.  In a two player game their opponent
+    /// wins; a four player game ends with no winner.
+    pub fn resign(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let seat = try!(self.playing_seat(name));
+        let winner = match self.players.len() {
+            2 => Some(1 - seat),
+            _ => None,
+        };
+        self.record(DomainEvent::Resigned { seat: seat });
+        self.end(GameResult { winner: winner, reason: Reason::Resignation });
+        Ok(format!("{} resigned", name))
+    }
+
+    /// Player `name` offers a draw.  The offer stands until another
+    /// player accepts or declines it, or plays on.
+    pub fn offer_draw(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let seat = try!(self.playing_seat(name));
+        match self.draw_offer {
+            Some(offer) if offer == seat => Err(QuoridorError::TurnError("Draw already offered".into())),
+            Some(_) => Err(QuoridorError::TurnError("A draw has been offered to you".into())),
+            None => {
+                self.record(DomainEvent::DrawOffered { seat: seat });
+                Ok(format!("{} offered a draw", name))
+            },
+        }
+    }
+
+    /// Seat of player `name`, who is answering another player's offer
+    /// of a draw
+    fn answering_seat(&self, name: &str) -> QuoridorResult<u8>
+    {
+        let seat = try!(self.playing_seat(name));
+        match self.draw_offer {
+            Some(offer) if offer != seat => Ok(seat),
+            _ => Err(QuoridorError::TurnError("No draw has been offered to you".into())),
+        }
+    }
+
+    /// Player `name` accepts the draw offered to them, ending the game
+    pub fn accept_draw(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let _ = try!(self.answering_seat(name));
+        self.end(GameResult::draw(Reason::Agreement));
+        Ok("Game drawn by agreement".into())
+    }
+
+    /// Player `name` declines the draw offered to them
+    pub fn decline_draw(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let seat = try!(self.answering_seat(name));
+        self.record(DomainEvent::DrawDeclined { seat: seat });
+        Ok(format!("{} declined the draw", name))
+    }
+
+    /// Index of the event of the last turn taken by `seat`
+    fn last_turn_of(&self, seat: u8) -> Option<usize>
+    {
+        let events = self.stream.events();
+        self.stream.in_effect(events.len()).into_iter().rev()
+            .find(|&i| events[i].turn().is_some() && events[i].seat() == Some(seat))
+    }
+
+    /// Moves `seat` has taken back so far
+    pub fn takebacks_used(&self, seat: u8) -> usize
+    {
+        self.stream.events().iter().filter(|e| match **e {
+            DomainEvent::TakenBack { seat: s, .. } => s == seat,
+            _ => false,
+        }).count()
+    }
+
+    /// Player `name` asks to take back their last move, and any move
+    /// made since.  The request stands until another player accepts or
+    /// declines it, or plays on.
+    pub fn request_takeback(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let seat = try!(self.playing_seat(name));
+        match self.options.takebacks {
+            Takebacks::Forbidden => return Err(QuoridorError::TurnError("Takebacks are not allowed in this game".into())),
+            Takebacks::Allowed if self.takebacks_used(seat) > 0 =>
+                return Err(QuoridorError::TurnError("No takebacks left".into())),
+            _ => (),
+        }
+        if self.last_turn_of(seat).is_none() {
+            return Err(QuoridorError::TurnError("No move to take back".into()))
+        }
+        if self.takeback_request.is_some() {
+            return Err(QuoridorError::TurnError("A takeback has already been requested".into()))
+        }
+        self.record(DomainEvent::TakebackRequested { seat: seat });
+        Ok(format!("{} asked to take back a move", name))
+    }
+
+    /// Seat of player `name`, who is answering another player's
+    /// request to take back a move, and the seat that asked
+    fn answering_takeback(&self, name: &str) -> QuoridorResult<(u8, u8)>
+    {
+        let seat = try!(self.playing_seat(name));
+        match self.takeback_request {
+            Some(requester) if requester != seat => Ok((seat, requester)),
+            _ => Err(QuoridorError::TurnError("No takeback has been requested of you".into())),
+        }
+    }
+
+    /// Player `name` agrees to the takeback asked of them, and the game
+    /// goes back to before the last move of the player who asked
+    pub fn accept_takeback(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let (_, requester) = try!(self.answering_takeback(name));
+        try!(self.take_back(requester));
+        Ok("Move taken back".into())
+    }
+
+    /// Player `name` declines the takeback asked of them
+    pub fn decline_takeback(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let (seat, _) = try!(self.answering_takeback(name));
+        self.record(DomainEvent::TakebackDeclined { seat: seat });
+        Ok(format!("{} declined the takeback", name))
+    }
+
+    /// Go back to before the last move `seat` took, with their clock
+    /// running.  Time already spent isn't given back.
+    pub fn take_back(&mut self, seat: u8) -> QuoridorResult<()>
+    {
+        let to = match (self.state.clone(), self.last_turn_of(seat)) {
+            (GameState::Started(_), Some(to)) => to,
+            (GameState::Started(_), None) => return Err(QuoridorError::TurnError("No move to take back".into())),
+            _ => return Err(QuoridorError::TurnError("Game is not under way".into())),
+        };
+        self.record(DomainEvent::TakenBack { seat: seat, to: to });
+        if let (GameState::Started(next), Some(clock)) = (self.state.clone(), self.clock.as_mut()) {
+            clock.rewind(self.history.len(), next, Instant::now());
+        }
+        Ok(())
+    }
+
+    /// End a game that is under way without a winner
+    pub fn abandon(&mut self) -> QuoridorResult<()>
+    {
+        match self.state {
+            GameState::Started(_) => Ok(self.end(GameResult::draw(Reason::Abandonment))),
+            _ => Err(QuoridorError::TurnError("Game is not under way".into())),
+        }
+    }
+
+    /// Whether the player in `seat` may swap sides under the pie rule:
+    /// only the second player, straight after the first move
+    pub fn may_swap(&self, seat: u8) -> bool
+    {
+        let swapped = self.stream.events().iter().any(|e| match *e {
+            DomainEvent::SidesSwapped { .. } => true,
+            _ => false,
+        });
+        self.options.pie_rule && self.players.len() == 2 && seat != self.first_seat()
+            && self.state == GameState::Started(seat) && self.history.len() == 1 && !swapped
+    }
+
+    /// Player `name`, second to move, takes the side of the first
+    /// player and the first move with it.  The first player takes the
+    /// second seat and moves next.
+    pub fn swap_sides(&mut self, name: &str) -> QuoridorResult<String>
+    {
+        let seat = try!(self.playing_seat(name));
+        if !self.options.pie_rule {
+            return Err(QuoridorError::TurnError("The pie rule isn't played in this game".into()))
+        }
+        if !self.may_swap(seat) {
+            return Err(QuoridorError::TurnError("Sides can only be swapped by the second player after the first move".into()))
+        }
+        let first = self.first_seat();
+        self.record(DomainEvent::SidesSwapped { seat: seat, with: first });
+        if let Some(ref mut clock) = self.clock {
+            clock.swap(seat, first, Instant::now());
+        }
+        Ok(format!("{} swapped sides", name))
+    }
+
     /// Adds a player given a name, and a password `key`
     ///
     /// # Arguments
@@ -292,9 +789,37 @@ impl Game {
     pub fn add_player<S>(&mut self, name: S, key: S) -> QuoridorResult<String>
         where S: Into<String>
     {
-        let name = name.into();
-        let key = key.into();
+        let key_hash = auth::hash_key(&key.into());
+        self.add_player_with_hash(name.into(), key_hash)
+    }
+
+    /// Adds a player whose key has already been hashed by
+    /// `auth::hash_key`
+    pub fn add_player_with_hash(&mut self, name: String, key_hash: String) -> QuoridorResult<String>
+    {
+        self.add_player_to_seat(name, key_hash, None)
+    }
+
+    /// Adds a player to the seat they chose, or to one given by the
+    /// game's seating if they didn't.  Seat 0 moves first unless the
+    /// game's setup says otherwise.
+    pub fn add_player_to_seat(&mut self, name: String, key_hash: String, seat: Option<u8>) -> QuoridorResult<String>
+    {
+        let free: Vec<u8> = (0..2).filter(|&s| !self.players.values().any(|p| p.id == s)).collect();
+        let seat = match (seat, self.options.seating) {
+            (Some(_), Seating::Random) =>
+                return Err(QuoridorError::RegistrationError("Seats are drawn at random in this game".into())),
+            (Some(seat), Seating::InOrder) => seat,
+            (None, Seating::InOrder) => free.first().cloned().unwrap_or(2),
+            (None, Seating::Random) => rand::thread_rng().choose(&free).cloned().unwrap_or(2),
+        };
+        self.seat_player(name, key_hash, seat)
+    }
 
+    /// Adds a player to `seat` whatever the game's seating, as when
+    /// the game is replayed
+    pub fn seat_player(&mut self, name: String, key_hash: String, seat: u8) -> QuoridorResult<String>
+    {
         match self.state {
             GameState::Started(_) => return Err(QuoridorError::TurnError("Game already started".into())),
             _ => (),
@@ -304,26 +829,24 @@ impl Game {
             return Err(QuoridorError::RegistrationError(format!("Player {} already registered.", name.clone())))
         } else if self.players.len() >= 2 {
             return Err(QuoridorError::RegistrationError(format!("Attempt to register 3rd player.")))
+        } else if seat >= 2 {
+            return Err(QuoridorError::RegistrationError(format!("There is no seat {}", seat)))
+        } else if self.players.values().any(|p| p.id == seat) {
+            return Err(QuoridorError::RegistrationError(format!("Seat {} is taken", seat)))
         }
 
-        let starting_positions = [
-            point!(N / 2, 0),
-            point!(N / 2, N - 1),
-            point!(0, N / 2),
-            point!(N - 1, N / 2)
-        ];
-
-        // Create and add the player
-        let i = self.players.len();
-        let player = Player {
-            p: starting_positions[i],
-            key: key,
-            id: i as u8,
-            walls: 10,
+        // Create and add the player, where the setup says if there is
+        // one
+        let setup = self.options.setup.as_ref();
+        let start = setup.map_or(default_start(seat), |s| s.start(seat));
+        let walls = setup.and_then(|s| s.walls(seat)).unwrap_or(self.options.walls);
+        self.record(DomainEvent::PlayerJoined {
+            seat: seat,
             name: name.clone(),
-        };
-
-        self.players.insert(name.clone(), player);
+            key_hash: key_hash,
+            start: start,
+            walls: walls,
+        });
 
         // If we have enough players, start the game
         if self.players.len() == 2 {
@@ -363,6 +886,37 @@ impl Game {
         Ok(())
     }
 
+    /// A hash of the position (pawns, walls, walls in hand and player
+    /// to move) that is stable across runs and platforms
+    pub fn position_hash(&self) -> u64
+    {
+        let mut players: Vec<&Player> = self.players.values().collect();
+        players.sort_by_key(|p| p.id);
+        let mut walls: Vec<&Wall> = self.walls.iter().collect();
+        walls.sort();
+
+        let mut bytes = vec![];
+        for p in players {
+            bytes.extend_from_slice(&[p.id, p.p.x as u8, p.p.y as u8, p.walls]);
+        }
+        bytes.push(0xff);
+        for w in walls {
+            let orientation = match w.orientation {
+                Orientation::Horizontal => 0,
+                Orientation::Vertical => 1,
+            };
+            bytes.extend_from_slice(&[w.x as u8, w.y as u8, orientation]);
+        }
+        bytes.push(match self.state {
+            GameState::Started(turn) => turn,
+            GameState::Setup => 0xfe,
+            GameState::GameOver => 0xfd,
+        });
+
+        // 64 bit FNV-1a
+        bytes.iter().fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+    }
+
     /// Return the game state as JSON
     pub fn to_json(&self) -> Json {
         let mut d = BTreeMap::new();
@@ -380,6 +934,13 @@ impl Game {
             players.push(p.to_json())
         }
         d.insert("players".into(), players.to_json());
+        if let Some(ref clock) = self.clock {
+            d.insert("clock".into(), clock.to_json(Instant::now()));
+        }
+        d.insert("result".into(), self.result.map_or(Json::Null, |r| r.to_json()));
+        d.insert("draw_offer".into(), self.draw_offer.to_json());
+        d.insert("takeback_request".into(), self.takeback_request.to_json());
+        d.insert("repetitions".into(), self.repetitions().to_json());
         Json::Object(d)
     }
 }
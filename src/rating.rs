@@ -0,0 +1,187 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Player ratings
+//!
+//! Ratings are updated after every rated game, treating each game as a
+//! rating period of its own.  Glicko-2 tracks how sure we are of a
+//! rating (the deviation) and how erratic the player is (the
+//! volatility); Elo only moves the rating.
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+/// Converts between the Glicko and Glicko-2 scales
+const GLICKO2_SCALE: f64 = 173.7178;
+
+/// Constrains how much the volatility changes
+const TAU: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RatingSystem {
+    Glicko2,
+    /// Elo with the given K factor
+    Elo(f64),
+}
+
+impl Default for Rating {
+    fn default() -> Rating
+    {
+        Rating {
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+        }
+    }
+}
+
+impl Rating {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("rating".to_string(), self.rating.to_json());
+        d.insert("deviation".to_string(), self.deviation.to_json());
+        d.insert("volatility".to_string(), self.volatility.to_json());
+        Json::Object(d)
+    }
+
+    pub fn from_json(json: &Json) -> Option<Rating>
+    {
+        let field = |name| json.find(name).and_then(|v| v.as_f64());
+        match (field("rating"), field("deviation"), field("volatility")) {
+            (Some(rating), Some(deviation), Some(volatility)) => Some(Rating {
+                rating: rating,
+                deviation: deviation,
+                volatility: volatility,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl RatingSystem {
+    /// Parse `glicko2`, `elo` (K factor 32) or `elo:<k>`
+    pub fn from_name(name: &str) -> Option<RatingSystem>
+    {
+        match name {
+            "glicko2" => Some(RatingSystem::Glicko2),
+            "elo" => Some(RatingSystem::Elo(32.0)),
+            _ if name.starts_with("elo:") => name[4..].parse().ok()
+                .and_then(|k: f64| if k > 0.0 { Some(RatingSystem::Elo(k)) } else { None }),
+            _ => None,
+        }
+    }
+
+    /// The rating of `player` after scoring `score` (1 for a win, 0.5
+    /// for a draw, 0 for a loss) against `opponent`
+    pub fn update(&self, player: &Rating, opponent: &Rating, score: f64) -> Rating
+    {
+        match *self {
+            RatingSystem::Glicko2 => glicko2(player, &[(*opponent, score)]),
+            RatingSystem::Elo(k) => Rating { rating: elo(player.rating, opponent.rating, score, k), ..*player },
+        }
+    }
+}
+
+/// Elo rating of a player rated `a` after scoring `score` against a
+/// player rated `b`
+pub fn elo(a: f64, b: f64, score: f64, k: f64) -> f64
+{
+    let expected = 1.0 / (1.0 + 10f64.powf((b - a) / 400.0));
+    a + k * (score - expected)
+}
+
+/// Glicko-2 rating of a player after a rating period with `results`,
+/// pairs of opponent rating and score
+pub fn glicko2(player: &Rating, results: &[(Rating, f64)]) -> Rating
+{
+    let mu = (player.rating - 1500.0) / GLICKO2_SCALE;
+    let phi = player.deviation / GLICKO2_SCALE;
+    let sigma = player.volatility;
+
+    // A player who didn't play only grows less certain
+    if results.is_empty() {
+        let phi = (phi * phi + sigma * sigma).sqrt();
+        return Rating { deviation: phi * GLICKO2_SCALE, ..*player }
+    }
+
+    let g = |phi: f64| 1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt();
+    let mut v_inv = 0.0;
+    let mut improvement = 0.0;
+    for &(ref opponent, score) in results {
+        let mu_j = (opponent.rating - 1500.0) / GLICKO2_SCALE;
+        let g_j = g(opponent.deviation / GLICKO2_SCALE);
+        let e = 1.0 / (1.0 + (-g_j * (mu - mu_j)).exp());
+        v_inv += g_j * g_j * e * (1.0 - e);
+        improvement += g_j * (score - e);
+    }
+    let v = 1.0 / v_inv;
+    let delta = v * improvement;
+
+    // New volatility, by the Illinois algorithm
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+    };
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+    let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+    while (big_b - big_a).abs() > 0.000001 {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+        if f_c * f_b < 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+        big_b = big_c;
+        f_b = f_c;
+    }
+    let sigma = (big_a / 2.0).exp();
+
+    let phi_star = (phi * phi + sigma * sigma).sqrt();
+    let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+    let mu = mu + phi * phi * improvement;
+    Rating {
+        rating: mu * GLICKO2_SCALE + 1500.0,
+        deviation: phi * GLICKO2_SCALE,
+        volatility: sigma,
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Game records
+//!
+//! A record lists the players in seat order, every turn taken, the
+//! winner and why the game ended, along with the options the game was
+//! created with, which is enough to replay it from the start.  Timed
+//! games also record the clock of each player after their turn.
+
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameOptions, GameState};
+use result::Reason;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use turn::Turn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    pub players: Vec<String>,
+    pub turns: Vec<Turn>,
+    pub winner: Option<u8>,
+    /// Why the game ended, once it has
+    pub reason: Option<Reason>,
+    /// Milliseconds the mover had left after each turn
+    pub clocks: Vec<u64>,
+    /// Options the game was created with, its setup included
+    pub options: GameOptions,
+}
+
+impl GameRecord {
+    /// Create a record of the game so far
+    pub fn from_game(game: &Game) -> GameRecord
+    {
+        let mut players: Vec<_> = game.players.values().collect();
+        players.sort_by_key(|p| p.id);
+        GameRecord {
+            players: players.iter().map(|p| p.name.clone()).collect(),
+            turns: game.history.clone(),
+            winner: game.winner(),
+            reason: game.result.map(|r| r.reason),
+            clocks: game.clock.as_ref().map_or(vec![], |c| c.log.clone()),
+            options: game.options.clone(),
+        }
+    }
+
+    /// Replay the record, returning the position before each turn
+    /// followed by the final position
+    pub fn positions(&self) -> QuoridorResult<Vec<Game>>
+    {
+        let mut game = Game::with_options(self.options.clone());
+        for name in self.players.iter() {
+            try!(game.add_player(name.clone(), String::new()));
+        }
+
+        let mut positions = vec![];
+        for turn in self.turns.iter() {
+            positions.push(game.clone());
+            let player = match game.state {
+                GameState::Started(id) => game.players.values().find(|p| p.id == id).unwrap().clone(),
+                _ => return Err(QuoridorError::TurnError("Record continues after the game ended".into())),
+            };
+            try!(turn.take(&mut game, &player));
+            try!(game.increment_turn());
+        }
+        positions.push(game);
+        Ok(positions)
+    }
+
+    /// Return the record as JSON
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("players".to_string(), self.players.to_json());
+        d.insert("turns".to_string(), Json::Array(self.turns.iter().map(|t| t.to_json()).collect()));
+        d.insert("winner".to_string(), self.winner.to_json());
+        d.insert("reason".to_string(), self.reason.map(|r| r.name().to_string()).to_json());
+        d.insert("clocks".to_string(), self.clocks.to_json());
+        d.insert("options".to_string(), self.options.to_json());
+        Json::Object(d)
+    }
+
+    /// Parse a record written by `to_json`
+    pub fn from_json(json: &Json) -> QuoridorResult<GameRecord>
+    {
+        let invalid = || QuoridorError::ParseError(format!("Unable to parse game record {}", json));
+
+        let players = try!(json.find("players").and_then(|p| p.as_array()).ok_or_else(&invalid));
+        let players = try!(players.iter().map(|p| p.as_string().map(String::from)).collect::<Option<Vec<_>>>().ok_or_else(&invalid));
+
+        let mut turns = vec![];
+        for turn in try!(json.find("turns").and_then(|t| t.as_array()).ok_or_else(&invalid)) {
+            turns.push(try!(Turn::from_json(turn)));
+        }
+
+        let winner = json.find("winner").and_then(|w| w.as_u64()).map(|w| w as u8);
+        let reason = json.find("reason").and_then(|r| r.as_string()).and_then(Reason::parse);
+        let clocks = match json.find("clocks").and_then(|c| c.as_array()) {
+            Some(clocks) => try!(clocks.iter().map(|c| c.as_u64()).collect::<Option<Vec<_>>>().ok_or_else(&invalid)),
+            None => vec![],
+        };
+        let options = match json.find("options") {
+            Some(options) => try!(GameOptions::from_json(options).ok_or_else(&invalid)),
+            None => GameOptions::default(),
+        };
+
+        Ok(GameRecord {
+            players: players,
+            turns: turns,
+            winner: winner,
+            reason: reason,
+            clocks: clocks,
+            options: options,
+        })
+    }
+
+    /// Read a JSON record
+    pub fn read<R: Read>(r: &mut R) -> QuoridorResult<GameRecord>
+    {
+        match Json::from_reader(r) {
+            Ok(json) => GameRecord::from_json(&json),
+            Err(e) => Err(QuoridorError::ParseError(e.to_string())),
+        }
+    }
+
+    /// Write the record as JSON
+    pub fn write<W: Write>(&self, w: &mut W) -> QuoridorResult<()>
+    {
+        try!(write!(w, "{}", self.to_json()));
+        Ok(())
+    }
+}
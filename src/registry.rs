@@ -0,0 +1,372 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Registry of the games hosted by a server
+//!
+//! Every game has its own lock and its own activity notification, so
+//! players in one game never wait on another.
+
+use auth;
+use errors::QuoridorResult;
+use events::{self, Event, EventLog, GameEvent};
+use quoridor::{Game, GameState};
+use spectators::Spectators;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Id of the game served by the routes without a game id
+pub const DEFAULT_GAME: &'static str = "default";
+
+/// Called with the id of every game that ends, and the game
+pub type GameOverHook = Box<Fn(&str, &Game) + Send + Sync>;
+
+/// Called with the id and new version of a game, how many domain
+/// events it had before a change and the game after it, before anybody
+/// hears of the change.  An error undoes the change.
+pub type ChangeHook = Box<Fn(&str, u64, usize, &Game) -> QuoridorResult<()> + Send + Sync>;
+
+#[derive(Default)]
+struct Hooks {
+    change: Vec<ChangeHook>,
+    game_over: Vec<GameOverHook>,
+}
+
+/// A player seated in a game when it is created
+#[derive(Debug, Clone)]
+pub struct Entrant {
+    pub name: String,
+    pub key_hash: String,
+    pub account: Option<u64>,
+}
+
+impl Entrant {
+    /// A player without an account.  Hashing the key is slow, so do it
+    /// before locking anything.
+    pub fn guest(name: &str, key: &str) -> Entrant
+    {
+        Entrant { name: name.to_string(), key_hash: auth::hash_key(key), account: None }
+    }
+
+    /// Seat the player in `game`, in `seat` if they chose one
+    pub fn join(&self, game: &mut Game, seat: Option<u8>) -> QuoridorResult<String>
+    {
+        let ret = try!(game.add_player_to_seat(self.name.clone(), self.key_hash.clone(), seat));
+        game.players.get_mut(&self.name).unwrap().account = self.account;
+        Ok(ret)
+    }
+}
+
+pub struct GameEntry {
+    pub id: String,
+    pub game: RwLock<Game>,
+    pub spectators: Mutex<Spectators>,
+    activity: (Mutex<EventLog>, Condvar),
+    hooks: Arc<RwLock<Hooks>>,
+}
+
+impl GameEntry {
+    pub fn new<S: Into<String>>(id: S, game: Game) -> GameEntry
+    {
+        GameEntry::with_hooks(id.into(), game, Arc::new(RwLock::new(Hooks::default())))
+    }
+
+    fn with_hooks(id: String, game: Game, hooks: Arc<RwLock<Hooks>>) -> GameEntry
+    {
+        GameEntry {
+            id: id,
+            hooks: hooks,
+            spectators: Mutex::new(Spectators::new(game.to_json())),
+            game: RwLock::new(game),
+            activity: (Mutex::new(EventLog::new()), Condvar::new()),
+        }
+    }
+
+    /// Record events and wake everybody waiting for activity
+    pub fn publish(&self, events: Vec<GameEvent>)
+    {
+        if events.is_empty() {
+            return
+        }
+        let &(ref lock, ref cvar) = &self.activity;
+        let mut log = lock.lock().unwrap();
+        for event in events {
+            log.push(event);
+        }
+        cvar.notify_all();
+    }
+
+    /// Run the change hooks for the change to the game since it had
+    /// `len` domain events, then publish the events describing it and
+    /// run the game over hooks if it ended.  If a change hook fails
+    /// nothing is published, and the caller must undo the change.
+    pub fn publish_changes(&self, len: usize, after: &Game) -> QuoridorResult<()>
+    {
+        let events = events::diff(len, after);
+        if events.is_empty() {
+            return Ok(())
+        }
+        {
+            let hooks = self.hooks.read().unwrap();
+            let version = self.version() + events.len() as u64;
+            for hook in hooks.change.iter() {
+                try!(hook(&self.id, version, len, after));
+            }
+            if after.stream.ended_after(len) {
+                for hook in hooks.game_over.iter() {
+                    hook(&self.id, after);
+                }
+            }
+        }
+        let delay = Duration::from_secs(after.options.spectator_delay);
+        self.spectators.lock().unwrap().record(after.to_json(), delay, Instant::now());
+        self.publish(events);
+        Ok(())
+    }
+
+    /// How far spectators are kept behind the live game
+    pub fn spectator_delay(&self) -> Duration
+    {
+        Duration::from_secs(self.game.read().unwrap().options.spectator_delay)
+    }
+
+    /// May somebody watch the live game rather than the delayed
+    /// spectator view?  Only players may when spectators are delayed,
+    /// given as the name their session token is for.
+    pub fn may_watch_live(&self, player: Option<&str>) -> bool
+    {
+        let game = self.game.read().unwrap();
+        game.options.spectator_delay == 0 || player.map_or(false, |name| game.players.contains_key(name))
+    }
+
+    /// Public state of the game as spectators see it
+    pub fn spectator_state(&self) -> Json
+    {
+        let delay = self.spectator_delay();
+        self.spectators.lock().unwrap().state(delay, Instant::now())
+    }
+
+    /// Events numbered after `seq`
+    pub fn events_since(&self, seq: u64) -> Vec<Event>
+    {
+        self.activity.0.lock().unwrap().since(seq)
+    }
+
+    /// Wait up to `timeout` for events numbered after `seq`.  Returns
+    /// None once the game has been removed.
+    pub fn wait_for_events(&self, seq: u64, timeout: Duration) -> Option<Vec<Event>>
+    {
+        let deadline = Instant::now() + timeout;
+        let &(ref lock, ref cvar) = &self.activity;
+        let mut log = lock.lock().unwrap();
+        while log.last_seq() <= seq && !log.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                break
+            }
+            log = cvar.wait_timeout(log, deadline - now).unwrap().0;
+        }
+        match log.closed {
+            true => None,
+            false => Some(log.since(seq)),
+        }
+    }
+
+    /// Stop waiting clients once the game is gone
+    fn close(&self)
+    {
+        let &(ref lock, ref cvar) = &self.activity;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+    }
+
+    /// Version of the game, counting every event so far
+    pub fn version(&self) -> u64
+    {
+        self.activity.0.lock().unwrap().last_seq()
+    }
+
+    /// Short description of the game for listings
+    pub fn summary(&self, id: &str) -> Json
+    {
+        let game = self.game.read().unwrap();
+        let mut players: Vec<_> = game.players.values().collect();
+        players.sort_by_key(|p| p.id);
+
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), id.to_json());
+        d.insert("players".to_string(), players.iter().map(|p| p.name.clone()).collect::<Vec<_>>().to_json());
+        d.insert("state".to_string(), match game.state {
+            GameState::Setup => "setup",
+            GameState::Started(_) => "started",
+            GameState::GameOver => "over",
+        }.to_json());
+        Json::Object(d)
+    }
+}
+
+pub struct Registry {
+    games: RwLock<HashMap<String, Arc<GameEntry>>>,
+    next_id: Mutex<u64>,
+    hooks: Arc<RwLock<Hooks>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry
+    {
+        Registry {
+            games: RwLock::new(HashMap::new()),
+            next_id: Mutex::new(1),
+            hooks: Arc::new(RwLock::new(Hooks::default())),
+        }
+    }
+
+    /// Run `hook` whenever a game in the registry ends
+    pub fn on_game_over(&self, hook: GameOverHook)
+    {
+        self.hooks.write().unwrap().game_over.push(hook);
+    }
+
+    /// Run `hook` after every change to a game in the registry
+    pub fn on_change(&self, hook: ChangeHook)
+    {
+        self.hooks.write().unwrap().change.push(hook);
+    }
+
+    /// Add a game under a fixed id, replacing any game with that id.
+    /// New games are given ids after any numeric id added this way.
+    pub fn insert<S: Into<String>>(&self, id: S, game: Game) -> Arc<GameEntry>
+    {
+        let id = id.into();
+        self.reserve(&id);
+        let entry = Arc::new(GameEntry::with_hooks(id.clone(), game, self.hooks.clone()));
+        self.games.write().unwrap().insert(id, entry.clone());
+        entry
+    }
+
+    /// Never give a new game the id `id`, or any numeric id before it,
+    /// such as the id of a finished game still referred to elsewhere
+    pub fn reserve(&self, id: &str)
+    {
+        if let Ok(n) = id.parse::<u64>() {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id = ::std::cmp::max(*next_id, n + 1);
+        }
+    }
+
+    /// Add a game under a new id, returning the id.  The change hooks
+    /// see the new game before anybody is given its id.
+    pub fn create(&self, game: Game) -> QuoridorResult<String>
+    {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            (*next_id - 1).to_string()
+        };
+        let entry = self.insert(id.clone(), game);
+        let created = {
+            let game = entry.game.read().unwrap();
+            let hooks = self.hooks.read().unwrap();
+            hooks.change.iter().map(|hook| hook(&id, entry.version(), game.stream.len(), &game)).collect::<QuoridorResult<Vec<_>>>()
+        };
+        if let Err(e) = created {
+            self.remove(&id);
+            return Err(e)
+        }
+        Ok(id)
+    }
+
+    /// Add a game with its players already seated, in order, returning
+    /// the id
+    pub fn create_with_players(&self, game: Game, players: &[Entrant]) -> QuoridorResult<String>
+    {
+        let id = try!(self.create(game));
+        let entry = self.get(&id).unwrap();
+        let mut game = entry.game.write().unwrap();
+        let len = game.stream.len();
+        let seated = players.iter().map(|p| p.join(&mut game, None)).collect::<QuoridorResult<Vec<_>>>();
+        if let Err(e) = seated.and_then(|_| entry.publish_changes(len, &game)) {
+            drop(game);
+            self.remove(&id);
+            return Err(e)
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<GameEntry>>
+    {
+        self.games.read().unwrap().get(id).cloned()
+    }
+
+    /// Remove a game, waking anybody waiting on it
+    pub fn remove(&self, id: &str) -> Option<Arc<GameEntry>>
+    {
+        let entry = self.games.write().unwrap().remove(id);
+        if let Some(ref entry) = entry {
+            entry.close();
+        }
+        entry
+    }
+
+    /// Ids of every game, in order of creation
+    pub fn ids(&self) -> Vec<String>
+    {
+        let mut ids: Vec<String> = self.games.read().unwrap().keys().cloned().collect();
+        ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(0), id.clone()));
+        ids
+    }
+
+    /// End every game whose player to move has run out of time,
+    /// waking anybody waiting on it.  Returns the ids of those games.
+    pub fn check_clocks(&self, now: Instant) -> Vec<String>
+    {
+        let mut flagged = vec![];
+        for id in self.ids() {
+            if let Some(entry) = self.get(&id) {
+                let running = entry.game.read().unwrap().clock.as_ref().map_or(false, |c| c.running().is_some());
+                if !running {
+                    continue
+                }
+                let mut game = entry.game.write().unwrap();
+                let (len, clock) = (game.stream.len(), game.clock.clone());
+                if game.check_clock(now).is_some() {
+                    // Tried again on the next check if it can't be saved
+                    if let Err(e) = entry.publish_changes(len, &game) {
+                        println!("Unable to end game {} on time: {}", id, e);
+                        game.rewind(len);
+                        game.clock = clock;
+                        continue
+                    }
+                    flagged.push(id);
+                }
+            }
+        }
+        flagged
+    }
+
+    /// Summaries of every game
+    pub fn to_json(&self) -> Json
+    {
+        Json::Array(self.ids().iter().filter_map(|id| self.get(id).map(|e| e.summary(id))).collect())
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! How a game ended
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+
+/// Why a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// A pawn reached its goal
+    Goal,
+    Resignation,
+    /// A player ran out of time
+    Timeout,
+    /// The players agreed to a draw
+    Agreement,
+    /// The game was stopped before it finished
+    Abandonment,
+    /// The same position came up too many times
+    Repetition,
+    /// The game reached its limit of turns
+    MoveLimit,
+}
+
+/// The winner of a finished game, if any, and why it ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameResult {
+    pub winner: Option<u8>,
+    pub reason: Reason,
+}
+
+impl Reason {
+    /// Name of the reason in JSON
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Reason::Goal => "goal",
+            Reason::Resignation => "resignation",
+            Reason::Timeout => "timeout",
+            Reason::Agreement => "agreement",
+            Reason::Abandonment => "abandonment",
+            Reason::Repetition => "repetition",
+            Reason::MoveLimit => "move_limit",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Reason>
+    {
+        match name {
+            "goal" => Some(Reason::Goal),
+            "resignation" => Some(Reason::Resignation),
+            "timeout" => Some(Reason::Timeout),
+            "agreement" => Some(Reason::Agreement),
+            "abandonment" => Some(Reason::Abandonment),
+            "repetition" => Some(Reason::Repetition),
+            "move_limit" => Some(Reason::MoveLimit),
+            _ => None,
+        }
+    }
+}
+
+impl GameResult {
+    pub fn win(seat: u8, reason: Reason) -> GameResult
+    {
+        GameResult { winner: Some(seat), reason: reason }
+    }
+
+    /// A game that ended without a winner
+    pub fn draw(reason: Reason) -> GameResult
+    {
+        GameResult { winner: None, reason: reason }
+    }
+
+    /// "win" or "draw"
+    pub fn outcome(&self) -> &'static str
+    {
+        match self.winner {
+            Some(_) => "win",
+            None => "draw",
+        }
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("outcome".to_string(), self.outcome().to_string().to_json());
+        d.insert("winner".to_string(), self.winner.to_json());
+        d.insert("reason".to_string(), self.reason.name().to_string().to_json());
+        Json::Object(d)
+    }
+
+    /// Parse a result written by `to_json`
+    pub fn from_json(json: &Json) -> Option<GameResult>
+    {
+        let reason = match json.find("reason").and_then(|r| r.as_string()).and_then(Reason::parse) {
+            Some(reason) => reason,
+            None => return None,
+        };
+        Some(GameResult {
+            winner: json.find("winner").and_then(|w| w.as_u64()).map(|w| w as u8),
+            reason: reason,
+        })
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Self-play data generation
+//!
+//! Plays games between two copies of a greedy one-ply engine, which
+//! plays from an opening book while the position is in it and plays
+//! perfectly once every wall has been placed, with seeded randomness
+//! so that runs can be repeated, and records the
+//! features of every position together with the final result for the
+//! player to move.  Samples are written as CSV with a header line.
+
+use board::Wall;
+use book::OpeningBook;
+use evaluator::{self, Evaluator, Features, FEATURES, FEATURE_NAMES};
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use rand::Rng;
+use record::GameRecord;
+use std::cmp;
+use std::io::{BufRead, Write};
+use std::sync::Mutex;
+use tablebase::Tablebases;
+use turn::Turn;
+
+/// Features of a position and how the game ended for the player to
+/// move: 1 for a win, 0 for a loss and 0.5 for a draw
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub features: Features,
+    pub result: f64,
+}
+
+/// Turns worth considering for player `id`: every pawn move, and the
+/// walls that cut their opponent's shortest path
+pub fn candidate_turns(game: &Game, id: u8) -> Vec<Turn>
+{
+    let me = match game.players.values().find(|p| p.id == id) {
+        Some(p) => p,
+        None => return vec![],
+    };
+    let mut turns: Vec<Turn> = game.valid_moves(me.p).into_iter()
+        .filter_map(|q| Turn::between(me.p, q))
+        .collect();
+
+    if me.walls > 0 {
+        let mut walls = vec![];
+        for them in game.players.values().filter(|p| p.id != id) {
+            let path = evaluator::shortest_path(game, them);
+            for step in path.windows(2) {
+                let (a, b) = (step[0], step[1]);
+                if a.x == b.x {
+                    let y = cmp::max(a.y, b.y);
+                    walls.push(Wall::horizontal(a.x, y));
+                    walls.push(Wall::horizontal(a.x + 1, y));
+                } else {
+                    let x = cmp::max(a.x, b.x);
+                    walls.push(Wall::vertical(x, a.y));
+                    walls.push(Wall::vertical(x, a.y + 1));
+                }
+            }
+        }
+        walls.sort();
+        walls.dedup();
+        turns.extend(walls.into_iter().map(Turn::PlaceWall));
+    }
+    turns
+}
+
+/// The candidate turn that leaves the position scoring best for the
+/// player to move, or None if they have no legal turn
+pub fn best_turn(game: &Game, evaluator: &Evaluator) -> QuoridorResult<Option<Turn>>
+{
+    let id = match game.state {
+        GameState::Started(id) => id,
+        _ => return Err(QuoridorError::TurnError("Game is not in progress".into())),
+    };
+    let player = game.players.values().find(|p| p.id == id).unwrap().clone();
+
+    let mut best: Option<(Turn, f64)> = None;
+    for turn in candidate_turns(game, id) {
+        let mut g = game.clone();
+        if turn.take(&mut g, &player).is_err() {
+            continue;
+        }
+        if g.players[&player.name].has_won() {
+            return Ok(Some(turn));
+        }
+        let score = try!(evaluator.evaluate(&g, id));
+        if best.map_or(true, |(_, s)| score > s) {
+            best = Some((turn, score));
+        }
+    }
+    Ok(best.map(|(turn, _)| turn))
+}
+
+/// Plays games between two copies of the same engine
+pub struct SelfPlay {
+    pub evaluator: Evaluator,
+    /// Chance of playing a random candidate turn instead of the best
+    pub epsilon: f64,
+    /// Games still going after this many plies are drawn
+    pub max_plies: usize,
+    /// Book to play the opening from, if any
+    pub book: Option<OpeningBook>,
+    /// Play the race exactly once every wall has been placed
+    pub solve_endgames: bool,
+    tablebases: Mutex<Tablebases>,
+}
+
+impl SelfPlay {
+    pub fn new(evaluator: Evaluator) -> SelfPlay
+    {
+        SelfPlay {
+            evaluator: evaluator,
+            epsilon: 0.1,
+            max_plies: 200,
+            book: None,
+            solve_endgames: true,
+            tablebases: Mutex::new(Tablebases::new(None)),
+        }
+    }
+
+    /// The turn the engine plays: a book turn while the position is in
+    /// the book, the solver's move once every wall has been placed, or
+    /// else a random candidate `epsilon` of the time and the best
+    /// candidate the rest
+    pub fn choose_turn<R: Rng>(&self, game: &Game, rng: &mut R) -> QuoridorResult<Option<Turn>>
+    {
+        let id = match game.state {
+            GameState::Started(id) => id,
+            _ => return Err(QuoridorError::TurnError("Game is not in progress".into())),
+        };
+        let player = game.players.values().find(|p| p.id == id).unwrap().clone();
+
+        if let Some(turn) = self.book.as_ref().and_then(|book| book.choose(game, rng)) {
+            if turn.take(&mut game.clone(), &player).is_ok() {
+                return Ok(Some(turn))
+            }
+        }
+
+        if self.solve_endgames && game.players.values().all(|p| p.walls == 0) {
+            let solver = try!(self.tablebases.lock().unwrap().get(game));
+            if let Some(turn) = try!(solver.best_move(game)).and_then(|to| Turn::between(player.p, to)) {
+                return Ok(Some(turn))
+            }
+        }
+
+        if rng.gen::<f64>() < self.epsilon {
+            let mut candidates = candidate_turns(game, id);
+            while !candidates.is_empty() {
+                let candidate = candidates.swap_remove(rng.gen_range(0, candidates.len()));
+                if candidate.take(&mut game.clone(), &player).is_ok() {
+                    return Ok(Some(candidate))
+                }
+            }
+            return Ok(None)
+        }
+        best_turn(game, &self.evaluator)
+    }
+
+    /// Play one game, returning its record and a sample per position
+    pub fn play<R: Rng>(&self, rng: &mut R) -> QuoridorResult<(GameRecord, Vec<Sample>)>
+    {
+        let mut game = Game::new();
+        try!(game.add_player("Player 1", ""));
+        try!(game.add_player("Player 2", ""));
+
+        let mut positions = vec![];
+        while positions.len() < self.max_plies {
+            let id = match game.state {
+                GameState::Started(id) => id,
+                _ => break,
+            };
+            let player = game.players.values().find(|p| p.id == id).unwrap().clone();
+            positions.push((id, try!(Features::compute(&game, id))));
+
+            match try!(self.choose_turn(&game, rng)) {
+                Some(turn) => try!(turn.take(&mut game, &player)),
+                None => return Err(QuoridorError::TurnError(format!("{} has no legal turn", player.name))),
+            };
+            try!(game.increment_turn());
+        }
+
+        let record = GameRecord::from_game(&game);
+        let samples = positions.into_iter().map(|(id, features)| Sample {
+            features: features,
+            result: match record.winner {
+                Some(w) if w == id => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            },
+        }).collect();
+        Ok((record, samples))
+    }
+
+    /// Play `games` games, writing every sample to `w`.  Returns the
+    /// number of samples written.
+    pub fn generate<R: Rng, W: Write>(&self, games: usize, rng: &mut R, w: &mut W) -> QuoridorResult<usize>
+    {
+        try!(write_header(w));
+        let mut count = 0;
+        for _ in 0..games {
+            let (_, samples) = try!(self.play(rng));
+            try!(write_samples(&samples, w));
+            count += samples.len();
+        }
+        Ok(count)
+    }
+}
+
+/// Write the CSV header line
+pub fn write_header<W: Write>(w: &mut W) -> QuoridorResult<()>
+{
+    try!(writeln!(w, "{},result", FEATURE_NAMES.join(",")));
+    Ok(())
+}
+
+/// Write samples as CSV lines
+pub fn write_samples<W: Write>(samples: &[Sample], w: &mut W) -> QuoridorResult<()>
+{
+    for sample in samples {
+        let values: Vec<String> = sample.features.0.iter().map(|f| f.to_string()).collect();
+        try!(writeln!(w, "{},{}", values.join(","), sample.result));
+    }
+    Ok(())
+}
+
+/// Read samples written by `write_header` and `write_samples`
+pub fn read_samples<R: BufRead>(r: R) -> QuoridorResult<Vec<Sample>>
+{
+    let mut samples = vec![];
+    for line in r.lines().skip(1) {
+        let line = try!(line);
+        let values: Vec<f64> = try!(line.split(',').map(|v| v.trim().parse::<f64>())
+                                    .collect::<Result<_, _>>()
+                                    .map_err(|_| QuoridorError::ParseError(format!("Invalid sample {}", line))));
+        if values.len() != FEATURES + 1 {
+            return Err(QuoridorError::ParseError(format!("Invalid sample {}", line)))
+        }
+        let mut features = [0.0; FEATURES];
+        features.copy_from_slice(&values[..FEATURES]);
+        samples.push(Sample { features: Features(features), result: values[FEATURES] });
+    }
+    Ok(samples)
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Series of games played as rematches
+//!
+//! A rematch of a finished game seats the same players again with the
+//! seats turned one place, so in a two player game they swap edges and
+//! the other player moves first, unless seats are drawn at random.  The
+//! new game has the same options.
+//! Games linked by rematches form a series with a running score: a win
+//! is worth a point and a draw half a point to each player.
+
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use registry::{Entrant, Registry};
+use result::GameResult;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+
+pub struct Series {
+    pub id: u64,
+    /// Names of the players, in their seats for the first game
+    pub players: Vec<String>,
+    /// Ids of the games in the order they were played
+    pub games: Vec<String>,
+    /// Names of the players of each game in seat order, as they ended
+    /// up once the game is over
+    seats: Vec<Vec<String>>,
+    /// Results of the games that have finished
+    results: Vec<Option<GameResult>>,
+}
+
+/// Every series, and the series each game is part of
+pub struct Rematches {
+    series: BTreeMap<u64, Series>,
+    games: HashMap<String, u64>,
+    next_id: u64,
+}
+
+/// Names of the players of `game` in seat order
+fn seated(game: &Game) -> Vec<String>
+{
+    let mut players: Vec<_> = game.players.values().collect();
+    players.sort_by_key(|p| p.id);
+    players.iter().map(|p| p.name.clone()).collect()
+}
+
+impl Series {
+    /// Name of the player in `seat` of game number `game`
+    fn player(&self, game: usize, seat: u8) -> &str
+    {
+        &self.seats[game][seat as usize]
+    }
+
+    /// Points of each player, in the order of `players`
+    pub fn score(&self) -> Vec<f64>
+    {
+        let mut score = vec![0.0; self.players.len()];
+        for (game, result) in self.results.iter().enumerate() {
+            match *result {
+                Some(GameResult { winner: Some(seat), .. }) => {
+                    let name = self.player(game, seat);
+                    let i = self.players.iter().position(|p| p == name).unwrap();
+                    score[i] += 1.0;
+                },
+                Some(GameResult { winner: None, .. }) => {
+                    for s in score.iter_mut() {
+                        *s += 0.5;
+                    }
+                },
+                None => (),
+            }
+        }
+        score
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let games: Vec<Json> = self.games.iter().zip(self.results.iter()).enumerate().map(|(i, (id, result))| {
+            let mut d = BTreeMap::new();
+            d.insert("id".to_string(), id.to_json());
+            d.insert("players".to_string(), (0..self.players.len() as u8).map(|s| self.player(i, s).to_string()).collect::<Vec<_>>().to_json());
+            d.insert("result".to_string(), result.map_or(Json::Null, |r| r.to_json()));
+            Json::Object(d)
+        }).collect();
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_string().to_json());
+        d.insert("players".to_string(), self.players.to_json());
+        d.insert("games".to_string(), Json::Array(games));
+        d.insert("score".to_string(), self.score().to_json());
+        Json::Object(d)
+    }
+}
+
+impl Rematches {
+    pub fn new() -> Rematches
+    {
+        Rematches {
+            series: BTreeMap::new(),
+            games: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Series>
+    {
+        id.parse().ok().and_then(|id| self.series.get(&id))
+    }
+
+    /// The series game `id` is part of
+    pub fn of_game(&self, id: &str) -> Option<&Series>
+    {
+        self.games.get(id).and_then(|s| self.series.get(s))
+    }
+
+    /// Count a finished game in the score of its series, if it is in one
+    pub fn record_result(&mut self, id: &str, game: &Game)
+    {
+        let series = match self.games.get(id) {
+            Some(series) => self.series.get_mut(series).unwrap(),
+            None => return,
+        };
+        if let (Some(i), Some(result)) = (series.games.iter().position(|g| g == id), game.result) {
+            series.seats[i] = seated(game);
+            series.results[i] = Some(result);
+        }
+    }
+
+    /// Create a rematch of `game`, the finished game with id `id`, in
+    /// `registry`, returning the id of the new game.  Asking again for
+    /// a rematch of the same game returns the same new game.
+    pub fn rematch(&mut self, registry: &Registry, id: &str, game: &Game) -> QuoridorResult<String>
+    {
+        if game.state != GameState::GameOver {
+            return Err(QuoridorError::TurnError("Game is not over".into()))
+        }
+        let mut players: Vec<_> = game.players.values().collect();
+        players.sort_by_key(|p| p.id);
+
+        let series_id = match self.games.get(id) {
+            Some(&series_id) => series_id,
+            None => {
+                let series_id = self.next_id;
+                self.next_id += 1;
+                self.series.insert(series_id, Series {
+                    id: series_id,
+                    players: seated(game),
+                    games: vec![id.to_string()],
+                    seats: vec![seated(game)],
+                    results: vec![None],
+                });
+                self.games.insert(id.to_string(), series_id);
+                series_id
+            },
+        };
+        self.record_result(id, game);
+
+        let series = self.series.get_mut(&series_id).unwrap();
+        let i = series.games.iter().position(|g| g == id).unwrap();
+        if let Some(next) = series.games.get(i + 1) {
+            return Ok(next.clone())
+        }
+
+        let mut entrants: Vec<_> = players.iter().map(|p| {
+            Entrant { name: p.name.clone(), key_hash: p.key_hash.clone(), account: p.account }
+        }).collect();
+        entrants.rotate_left(1);
+        let mut options = game.options.clone();
+        options.setup = options.setup.map(|setup| setup.swapped());
+        let next = try!(registry.create_with_players(Game::with_options(options), &entrants));
+        series.games.push(next.clone());
+        series.seats.push(seated(&registry.get(&next).unwrap().game.read().unwrap()));
+        series.results.push(None);
+        self.games.insert(next.clone(), series_id);
+        Ok(next)
+    }
+}
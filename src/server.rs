@@ -24,85 +24,258 @@
 
 use router::Router;
 use iron::status;
-use rustc_serialize::json::ToJson;
+use rustc_serialize::json::{Json, ToJson};
 use rustc_serialize::json;
+use std::cmp;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::sync::Condvar;
-use std::sync::RwLock;
 use std::io::Read;
 use iron::prelude::*;
 use mount::Mount;
 use staticfile::Static;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 use board::{Wall, Point};
-use quoridor::{Game, GameState};
+use quoridor::{Game, GameOptions, GameState, Seating, Takebacks};
+use clock::TimeControl;
+use smallboard::{self, SmallTablebases};
+use tablebase::{self, Tablebases};
+use record::GameRecord;
+use registry::{Registry, GameEntry, DEFAULT_GAME};
+use lobby::Lobby;
+use tournament::{Format, Tournaments};
+use series::Rematches;
+use storage::{Recorder, SqliteStorage};
+use journal::{self, Journal};
+use websocket;
+use events;
+use sse::{EventStream, SpectatorStream, StreamLimit, StreamSlot};
+use accounts::{self, Accounts};
+use rating::RatingSystem;
+use auth::{is_admin, Authenticate, AuthenticatedPlayer, Sessions, TOKEN_LIFETIME};
+use iron::headers::{Authorization, Bearer};
+use iron::headers::{CacheControl, CacheDirective, ContentType};
+use iron::response::WriteBody;
+use errors::QuoridorResult;
+use setup::Setup;
 
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
-struct PlayerRegistrationRequest {
+struct CreateGameRequest {
+    walls: Option<u8>,
+    base: Option<u64>,
+    increment: Option<u64>,
+    move_limit: Option<u64>,
+    spectator_delay: Option<u64>,
+    takebacks: Option<String>,
+    repetitions: Option<usize>,
+    max_plies: Option<usize>,
+    seating: Option<String>,
+    pie_rule: Option<bool>,
+    setup: Option<SetupRequest>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct SetupRequest {
+    walls: Option<Vec<u8>>,
+    starts: Option<Vec<[i32; 2]>>,
+    board: Option<Vec<SetupWallRequest>>,
+    first: Option<u8>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct SetupWallRequest {
+    p1: [i32; 2],
+    p2: [i32; 2],
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct SmallAnalysisRequest {
+    size: usize,
+    walls: u8,
+    pawns: [[i32; 2]; 2],
+    in_hand: [u8; 2],
+    board: Vec<SetupWallRequest>,
+    turn: u8,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct TournamentRequest {
+    name: String,
+    format: String,
+    rounds: Option<usize>,
+    walls: Option<u8>,
+    base: Option<u64>,
+    increment: Option<u64>,
+    move_limit: Option<u64>,
+    spectator_delay: Option<u64>,
+    repetitions: Option<usize>,
+    max_plies: Option<usize>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct AccountRequest {
     name: String,
     key: String,
+    bot: Option<bool>,
 }
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
-struct PlayerMoveToRequest {
+struct SpectatorRequest {
+    name: String,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct PlayerRegistrationRequest {
     name: String,
     key: String,
+    /// Seat the player chooses when joining
+    seat: Option<u8>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct PlayerMoveToRequest {
     position: [i32; 2],
 }
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
 struct PlayerMoveRequest {
-    name: String,
-    key: String,
     direction: String,
 }
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
 struct WallRequest {
-    name: String,
-    key: String,
     p1: [i32; 2],
     p2: [i32; 2],
 }
 
-macro_rules! register_post_route {
-    ($router: expr, $route: expr, $handler: expr, $game: expr) => {
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct LobbyJoinRequest {
+    name: String,
+    key: String,
+    rating: Option<f64>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct ChallengeRequest {
+    name: String,
+    key: String,
+    private: Option<bool>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug)]
+struct JoinCodeRequest {
+    name: String,
+    key: String,
+    code: String,
+}
+
+/// The id of the game a request is for: the `:id` route parameter, or
+/// the default game for routes without one
+fn game_id(request: &Request) -> String {
+    request.extensions.get::<Router>()
+        .and_then(|params| params.find("id"))
+        .unwrap_or(DEFAULT_GAME)
+        .to_string()
+}
+
+/// Log a request by its method and path only: its query string and
+/// headers may carry credentials
+fn log_request(request: &Request) {
+    println!("{} /{}", request.method, request.url.path().join("/"));
+}
+
+/// Value of a query string parameter
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    request.url.query().and_then(|query| {
+        query.split('&')
+             .filter_map(|kv| {
+                 let mut kv = kv.splitn(2, '=');
+                 match (kv.next(), kv.next()) {
+                     (Some(k), Some(v)) if k == name => Some(v.to_string()),
+                     _ => None,
+                 }
+             })
+             .next()
+    })
+}
+
+/// Look up the game a request is for, responding 404 if there is none
+macro_rules! find_game {
+    ($registry: expr, $request: expr) => {
+        {
+            let id = game_id($request);
+            match $registry.get(&id) {
+                Some(entry) => entry,
+                None => return Ok(Response::with(
+                    (status::NotFound, format!("Game {} not found", id)))),
+            }
+        }
+    };
+}
+
+/// Look up a game to watch live, responding 403 to anybody but its
+/// players if spectators are delayed
+macro_rules! find_live_game {
+    ($registry: expr, $request: expr) => {
         {
-            let temp = $game.clone();
-            $router.post($route, move |r: &mut Request|
-                        $handler(r, &mut temp.write().unwrap()));
+            let entry = find_game!($registry, $request);
+            let player = match $request.extensions.get::<AuthenticatedPlayer>() {
+                Some(session) if session.game == game_id($request) => Some(session.name.clone()),
+                _ => None,
+            };
+            if !entry.may_watch_live(player.as_ref().map(|n| &n[..])) {
+                return Ok(Response::with(
+                    (status::Forbidden, "Spectators of this game are delayed, watch /spectate instead")))
+            }
+            entry
+        }
+    };
+}
+
+/// Change a game with its lock held, then journal the change and tell
+/// everybody waiting what changed, or undo it if it can't be journaled
+macro_rules! change_game {
+    ($entry: expr, $game: ident => $change: expr) => {
+        {
+            let mut $game = $entry.game.write().unwrap();
+            let (len, clock) = ($game.stream.len(), $game.clock.clone());
+            let ret = $change;
+
+            if let Err(e) = $entry.publish_changes(len, &$game) {
+                $game.rewind(len);
+                $game.clock = clock;
+                return Ok(Response::with((status::InternalServerError, format!("Unable to save the change: {}", e))))
+            }
+            ret
         }
     };
 }
 
 macro_rules! register_turn_route {
-    ($router: expr, $cond: expr, $route: expr, $handler: expr, $game: expr) => {
+    ($router: expr, $route: expr, $handler: expr, $registry: expr $(, $arg: ident)*) => {
         {
-            let game_clone = $game.clone();
-            let cond_clone = $cond.clone();
+            let registry = $registry.clone();
+            $(let $arg = $arg.clone();)*
             $router.post($route, move |r: &mut Request| {
-                let ret = $handler(r, &mut game_clone.write().unwrap());
-
-                // If turn successful, notify all waiting for the next turn
-                if ret.as_ref().unwrap().status.unwrap() == status::Ok {
-                    let &(ref lock, ref cvar) = &*cond_clone;
-                    lock.lock().ok();
-                    cvar.notify_all();
-                }
-                ret
+                let entry = find_game!(registry, r);
+                change_game!(entry, game => $handler(r, &mut game $(, &$arg)*))
             });
         }
     };
 }
 
 macro_rules! register_get_route {
-    ($router: expr, $route: expr, $handler: expr, $game: expr) => {
+    ($router: expr, $route: expr, $handler: expr, $registry: expr) => {
         {
-            let temp = $game.clone();
-            $router.get($route, move |r: &mut Request| $handler(r, &mut temp.read().unwrap()));
-
+            let registry = $registry.clone();
+            $router.get($route, move |r: &mut Request| {
+                let entry = find_live_game!(registry, r);
+                let ret = $handler(r, &entry.game.read().unwrap());
+                ret
+            });
         }
     };
 }
@@ -126,52 +299,74 @@ macro_rules! parse_payload {
     };
 }
 
-macro_rules! try_call {
-    ($game: expr, $call: expr) => {
+macro_rules! take_turn {
+    ($request: expr, $game: expr, $player: ident => $call: expr) => {
         {
+            $game.check_clock(Instant::now());
+            let $player = check_player!($request, $game);
             match $call {
-                Ok(_) => Ok(Response::with(
-                    (status::Ok, $game.to_json().to_string()))),
+                Ok(_) => {
+                    let _ = $game.increment_turn();
+                    Ok(Response::with((status::Ok, $game.to_json().to_string())))
+                },
                 Err(e) => Ok(Response::with((status::BadRequest, e.to_string())))
             }
         }
     };
 }
 
-
-macro_rules! take_turn {
-    ($game: expr, $name: expr, $key:expr, $call: expr) => {
+/// Have a player do something that isn't a turn, such as resigning,
+/// whoever is to move
+macro_rules! player_action {
+    ($request: expr, $game: expr, $player: ident => $call: expr) => {
         {
-            check_player!($game, $name, $key);
-            let ret = try_call!($game, $call);
-            if ret.as_ref().unwrap().status.unwrap() == status::Ok {
-                let _ = $game.increment_turn();
+            $game.check_clock(Instant::now());
+            let $player = match authenticated_player!($request, $game) {
+                Ok(name) => name,
+                Err(response) => return Ok(response),
+            };
+            match $call {
+                Ok(_) => Ok(Response::with((status::Ok, $game.to_json().to_string()))),
+                Err(e) => Ok(Response::with((status::BadRequest, e.to_string())))
             }
-            ret
         }
     };
 }
 
+/// Resolve the player taking a turn, from the session the `Authenticate`
+/// middleware found for the request, and check that it is their turn
 macro_rules! check_player {
-    ($game: expr, $name: expr, $key: expr) => {
+    ($request: expr, $game: expr) => {
         {
-            if !$game.players.contains_key(&$name) {
-                return Ok(Response::with(
-                    (status::BadRequest, "Player not found")))
-            }
-            if $game.players[&$name].key != $key {
-                return Ok(Response::with(
-                    (status::BadRequest, "Unauthorized move.")))
-            }
+            let name = match authenticated_player!($request, $game) {
+                Ok(name) => name,
+                Err(response) => return Ok(response),
+            };
             match $game.state {
                 GameState::Setup => return Ok(Response::with((status::BadRequest, "Waiting on other players."))),
                 GameState::GameOver => return Ok(Response::with((status::BadRequest, "The game is over!"))),
                 GameState::Started(turn) => {
-                    if $game.players[&$name].id as u8 != turn {
+                    if $game.players[&name].id as u8 != turn {
                         return Ok(Response::with((status::BadRequest, "Not your turn.")))
                     }
                 }
             }
+            name
+        }
+    };
+}
+
+/// The player of the game whose bearer token the request carries.
+/// Keys are only ever exchanged for a token, by registering or logging
+/// in.
+macro_rules! authenticated_player {
+    ($request: expr, $game: expr) => {
+        match $request.extensions.get::<AuthenticatedPlayer>() {
+            Some(session) if session.game == game_id($request) && $game.players.contains_key(&session.name) => {
+                Ok(session.name.clone())
+            },
+            Some(_) => Err(Response::with((status::Unauthorized, "Token is not for this game"))),
+            None => Err(Response::with((status::Unauthorized, "Send the bearer token given when you registered"))),
         }
     };
 }
@@ -181,94 +376,982 @@ macro_rules! check_player {
 /// ********************************************************************
 
 fn get_status(request: &mut Request, game: &Game) -> IronResult<Response> {
-    println!("{:?}", request);
+    log_request(request);
     let payload = game.to_json().to_string();
     Ok(Response::with((status::Ok, payload)))
 }
 
 
 fn get_ascii(request: &mut Request, game: &Game) -> IronResult<Response> {
-    println!("{:?}", request);
+    log_request(request);
     let payload = game.to_string();
     Ok(Response::with((status::Ok, payload)))
 }
 
 
-fn register_player(request: &mut Request, game: &mut Game) -> IronResult<Response> {
-    println!("{:?}", request);
+fn get_record(request: &mut Request, game: &Game) -> IronResult<Response> {
+    log_request(request);
+    let payload = GameRecord::from_game(game).to_json().to_string();
+    Ok(Response::with((status::Ok, payload)))
+}
+
+
+/// The game JSON with a new session token for player `name`
+fn session_response(request: &Request, game: &Game, name: &str, sessions: &Mutex<Sessions>) -> Response {
+    let session = sessions.lock().unwrap().create(game_id(request), name.to_string(), Instant::now());
+    let mut payload = game.to_json();
+    if let Json::Object(ref mut d) = payload {
+        d.insert("token".to_string(), session.token.to_json());
+        d.insert("expires_in".to_string(), sessions.lock().unwrap().lifetime.as_secs().to_json());
+    }
+    Response::with((status::Ok, payload.to_string()))
+}
+
+fn register_player(request: &mut Request, entry: &GameEntry, sessions: &Mutex<Sessions>,
+                   accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: PlayerRegistrationRequest = parse_payload!(request);
+    // The key is checked or hashed before locking the game, as that is
+    // slow and would hold up everybody else in the game
+    let entrant = match accounts::identify(accounts, &data.name, &data.key) {
+        Ok(entrant) => entrant,
+        Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+    };
+    change_game!(entry, game => match entrant.join(&mut game, data.seat) {
+        Ok(_) => Ok(session_response(request, &game, &data.name, sessions)),
+        Err(e) => Ok(Response::with((status::BadRequest, e.to_string()))),
+    })
+}
+
+fn login(request: &mut Request, game: &Game, sessions: &Mutex<Sessions>) -> IronResult<Response> {
+    log_request(request);
     let data: PlayerRegistrationRequest = parse_payload!(request);
-    try_call!(game, game.add_player(data.name, data.key))
+    match game.players.get(&data.name) {
+        Some(player) if player.check_key(&data.key) => Ok(session_response(request, game, &data.name, sessions)),
+        _ => Ok(Response::with((status::Unauthorized, "Unknown name or key"))),
+    }
+}
+
+fn logout(request: &mut Request, sessions: &Mutex<Sessions>) -> IronResult<Response> {
+    log_request(request);
+    match request.headers.get::<Authorization<Bearer>>() {
+        Some(header) if sessions.lock().unwrap().revoke(&header.token) => {
+            Ok(Response::with((status::Ok, "Logged out")))
+        },
+        _ => Ok(Response::with((status::BadRequest, "Send the bearer token to revoke"))),
+    }
 }
 
 fn move_player_to(request: &mut Request, game: &mut Game) -> IronResult<Response> {
-    println!("{:?}", request);
+    log_request(request);
     let data: PlayerMoveToRequest = parse_payload!(request);
-    take_turn!(game,
-               data.name,
-               data.key,
-               game.move_player_to(data.name, point!(data.position[0], data.position[1])))
+    take_turn!(request, game, name => game.move_player_to(name, point!(data.position[0], data.position[1])))
 }
 
 fn move_player(request: &mut Request, game: &mut Game) -> IronResult<Response> {
-    println!("{:?}", request);
+    log_request(request);
     let data: PlayerMoveRequest = parse_payload!(request);
-    take_turn!(game,
-               data.name,
-               data.key,
-               game.move_player(data.name, data.direction))
+    take_turn!(request, game, name => game.move_player(name, data.direction))
 }
 
 fn place_wall(request: &mut Request, game: &mut Game) -> IronResult<Response> {
-    println!("{:?}", request);
+    log_request(request);
     let data: WallRequest = parse_payload!(request);
     let a = (data.p1[0], data.p1[1]);
     let b = (data.p2[0], data.p2[1]);
     let wall = Wall::from_tuples(a, b);
     match wall {
-        Ok(w) => take_turn!(game, data.name, data.key, game.add_wall(&w)),
+        Ok(w) => take_turn!(request, game, name => game.place_wall(name, &w)),
         Err(e) => Ok(Response::with((status::BadRequest, e.to_string())))
     }
 }
 
-fn wait_for_activity(request: &mut Request,
-                     lock: &Mutex<bool>,
-                     cvar: &Condvar)
-                     -> IronResult<Response> {
-    println!("{:?}", request);
-    let taken = lock.lock().unwrap();
-    let _ = cvar.wait(taken).unwrap();
-    Ok(Response::with((status::Ok, "A turn was taken")))
+fn wait_for_activity(request: &mut Request, entry: &GameEntry) -> IronResult<Response> {
+    log_request(request);
+    let since = query_param(request, "since").and_then(|v| v.parse().ok()).unwrap_or_else(|| entry.version());
+    let timeout = query_param(request, "timeout").and_then(|t| t.parse().ok()).unwrap_or(30);
+    let timeout = cmp::min(timeout, MAX_WAIT);
+    if entry.wait_for_events(since, Duration::from_secs(timeout)).is_none() {
+        return Ok(Response::with((status::NotFound, "The game was removed")))
+    }
+
+    // The game lock is held while events are published, so the events
+    // read under it match the state
+    let game = entry.game.read().unwrap();
+    let events = entry.events_since(since);
+    let turns = events::turns_since(&events, &game);
+
+    let mut d = BTreeMap::new();
+    d.insert("version".to_string(), entry.version().to_json());
+    d.insert("turns".to_string(), Json::Array(turns.iter().map(|t| t.to_json()).collect()));
+    d.insert("state".to_string(), game.to_json());
+    Ok(Response::with((status::Ok, Json::Object(d).to_string())))
+}
+
+/// Longest a `wait_for_activity` call holds a worker, in seconds
+const MAX_WAIT: u64 = 60;
+
+/// Request handling threads of the server
+const WORKERS: usize = 64;
+
+/// Most event streams open at once, so that they leave workers free
+/// for turns
+const MAX_STREAMS: usize = WORKERS / 2;
+
+/// Take a slot for a new event stream, or respond that the server has
+/// too many open
+macro_rules! stream_slot {
+    ($streams: expr) => {
+        match StreamLimit::acquire(&$streams) {
+            Some(slot) => slot,
+            None => return Ok(Response::with((status::ServiceUnavailable, "Too many event streams open, try again later"))),
+        }
+    };
+}
+
+fn stream_events(request: &mut Request, entry: Arc<GameEntry>, slot: StreamSlot) -> IronResult<Response> {
+    log_request(request);
+    let last_event_id = request.headers.get_raw("Last-Event-ID")
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8_lossy(value).trim().parse().ok());
+    let since = last_event_id
+        .or_else(|| query_param(request, "since").and_then(|v| v.parse().ok()))
+        .unwrap_or(0);
+
+    let stream = EventStream { entry: entry, seq: since, delay: Duration::from_secs(0), slot: slot };
+    Ok(event_stream_response(Box::new(stream)))
+}
+
+fn event_stream_response(body: Box<WriteBody>) -> Response {
+    let mut response = Response::with((status::Ok, body));
+    response.headers.set(ContentType("text/event-stream".parse().unwrap()));
+    response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+    response
+}
+
+fn spectate(request: &mut Request, entry: Arc<GameEntry>, slot: StreamSlot) -> IronResult<Response> {
+    log_request(request);
+    let delay = entry.spectator_delay();
+    let stream = EventStream { entry: entry, seq: 0, delay: delay, slot: slot };
+    let name = query_param(request, "name");
+    Ok(event_stream_response(Box::new(SpectatorStream { stream: stream, name: name })))
+}
+
+fn get_spectator_state(request: &mut Request, entry: &GameEntry) -> IronResult<Response> {
+    log_request(request);
+    Ok(Response::with((status::Ok, entry.spectator_state().to_string())))
+}
+
+fn get_spectators(request: &mut Request, entry: &GameEntry) -> IronResult<Response> {
+    log_request(request);
+    let mut payload = entry.spectators.lock().unwrap().to_json();
+    if let Json::Object(ref mut d) = payload {
+        d.insert("delay".to_string(), entry.spectator_delay().as_secs().to_json());
+    }
+    Ok(Response::with((status::Ok, payload.to_string())))
+}
+
+fn join_spectators(request: &mut Request, entry: &GameEntry) -> IronResult<Response> {
+    log_request(request);
+    let data: SpectatorRequest = parse_payload!(request);
+    let mut spectators = entry.spectators.lock().unwrap();
+    spectators.join(Some(&data.name));
+    Ok(Response::with((status::Ok, spectators.to_json().to_string())))
+}
+
+fn leave_spectators(request: &mut Request, entry: &GameEntry) -> IronResult<Response> {
+    log_request(request);
+    let data: SpectatorRequest = parse_payload!(request);
+    let mut spectators = entry.spectators.lock().unwrap();
+    spectators.leave(Some(&data.name));
+    Ok(Response::with((status::Ok, spectators.to_json().to_string())))
+}
+
+fn create_account(request: &mut Request, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: AccountRequest = parse_payload!(request);
+    match accounts.lock().unwrap().register(&data.name, &data.key, data.bot.unwrap_or(false)) {
+        Ok(account) => Ok(Response::with((status::Ok, account.to_json(false).to_string()))),
+        Err(e) => Ok(Response::with((status::BadRequest, e.to_string()))),
+    }
+}
+
+fn get_account(request: &mut Request, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    match accounts.lock().unwrap().find(&name) {
+        Some(account) => Ok(Response::with((status::Ok, account.to_json(false).to_string()))),
+        None => Ok(Response::with((status::NotFound, format!("Account {} not found", name)))),
+    }
+}
+
+fn get_leaderboard(request: &mut Request, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let bots = query_param(request, "bots").and_then(|b| b.parse().ok());
+    let limit = query_param(request, "limit").and_then(|l| l.parse().ok()).unwrap_or(100);
+    let accounts = accounts.lock().unwrap();
+    let rows = accounts.leaderboard(bots).iter().take(limit).enumerate().map(|(i, account)| {
+        let mut d = BTreeMap::new();
+        d.insert("rank".to_string(), (i + 1).to_json());
+        d.insert("name".to_string(), account.name.to_json());
+        d.insert("bot".to_string(), account.bot.to_json());
+        d.insert("rating".to_string(), account.rating.rating.round().to_json());
+        d.insert("deviation".to_string(), account.rating.deviation.round().to_json());
+        d.insert("games".to_string(), account.stats.games.to_json());
+        Json::Object(d)
+    }).collect();
+    Ok(Response::with((status::Ok, Json::Array(rows).to_string())))
+}
+
+fn get_rating(request: &mut Request, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    match accounts.lock().unwrap().find(&name) {
+        Some(account) => {
+            let mut payload = account.rating.to_json();
+            if let Json::Object(ref mut d) = payload {
+                d.insert("name".to_string(), account.name.to_json());
+                d.insert("history".to_string(), account.history_json());
+            }
+            Ok(Response::with((status::Ok, payload.to_string())))
+        },
+        None => Ok(Response::with((status::NotFound, format!("Player {} not found", name)))),
+    }
+}
+
+fn list_games(request: &mut Request, registry: &Registry) -> IronResult<Response> {
+    log_request(request);
+    Ok(Response::with((status::Ok, registry.to_json().to_string())))
+}
+
+/// Options of a new game, from the fields of a request
+fn game_options(walls: Option<u8>, base: Option<u64>, increment: Option<u64>,
+                move_limit: Option<u64>, spectator_delay: Option<u64>) -> GameOptions {
+    let mut options = GameOptions::default();
+    if let Some(walls) = walls {
+        options.walls = walls;
+    }
+    if base.is_some() || move_limit.is_some() {
+        options.time_control = Some(TimeControl {
+            base: base,
+            increment: increment.unwrap_or(0),
+            move_limit: move_limit,
+        });
+    }
+    options.spectator_delay = spectator_delay.unwrap_or(0);
+    options
+}
+
+/// The setup asked for in a request to create a game, once it has been
+/// checked
+fn game_setup(data: &SetupRequest) -> QuoridorResult<Setup> {
+    let mut board = vec![];
+    for w in data.board.as_ref().map_or(&[][..], |b| &b[..]) {
+        board.push(try!(Wall::from_tuples((w.p1[0], w.p1[1]), (w.p2[0], w.p2[1]))));
+    }
+    let setup = Setup {
+        walls: data.walls.clone(),
+        starts: data.starts.as_ref().map(|s| s.iter().map(|p| Point { x: p[0], y: p[1] }).collect()),
+        board: board,
+        first: data.first.unwrap_or(0),
+    };
+    try!(setup.validate());
+    Ok(setup)
+}
+
+fn resign(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.resign(&name))
+}
+
+fn offer_draw(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.offer_draw(&name))
+}
+
+fn accept_draw(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.accept_draw(&name))
+}
+
+fn decline_draw(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.decline_draw(&name))
+}
+
+fn request_takeback(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.request_takeback(&name))
+}
+
+fn accept_takeback(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.accept_takeback(&name))
+}
+
+fn decline_takeback(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.decline_takeback(&name))
+}
+
+fn swap_sides(request: &mut Request, game: &mut Game) -> IronResult<Response> {
+    log_request(request);
+    player_action!(request, game, name => game.swap_sides(&name))
+}
+
+/// Start a rematch of a finished game, or find the one already started
+fn rematch(request: &mut Request, registry: &Registry, rematches: &Mutex<Rematches>) -> IronResult<Response> {
+    log_request(request);
+    let entry = find_game!(registry, request);
+    let game = entry.game.read().unwrap().clone();
+    if let Err(response) = authenticated_player!(request, game) {
+        return Ok(response)
+    }
+    let id = game_id(request);
+    let mut rematches = rematches.lock().unwrap();
+    json_response(rematches.rematch(registry, &id, &game).map(|next| {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), next.to_json());
+        d.insert("series".to_string(), rematches.of_game(&id).unwrap().to_json());
+        Json::Object(d)
+    }))
+}
+
+fn get_series(request: &mut Request, rematches: &Rematches) -> IronResult<Response> {
+    log_request(request);
+    let id = request.extensions.get::<Router>().unwrap().find("id").unwrap_or("").to_string();
+    match rematches.get(&id) {
+        Some(series) => Ok(Response::with((status::Ok, series.to_json().to_string()))),
+        None => Ok(Response::with((status::NotFound, format!("Series {} not found", id)))),
+    }
+}
+
+fn create_game(request: &mut Request, registry: &Registry) -> IronResult<Response> {
+    log_request(request);
+    let data: CreateGameRequest = parse_payload!(request);
+    let mut options = game_options(data.walls, data.base, data.increment, data.move_limit, data.spectator_delay);
+    if let Some(ref takebacks) = data.takebacks {
+        match Takebacks::parse(takebacks) {
+            Some(takebacks) => options.takebacks = takebacks,
+            None => return Ok(Response::with((status::BadRequest, format!("Unknown takebacks setting {}", takebacks)))),
+        }
+    }
+    if let Some(ref seating) = data.seating {
+        match Seating::parse(seating) {
+            Some(seating) => options.seating = seating,
+            None => return Ok(Response::with((status::BadRequest, format!("Unknown seating {}", seating)))),
+        }
+    }
+    options.repetitions = data.repetitions;
+    options.max_plies = data.max_plies;
+    options.pie_rule = data.pie_rule.unwrap_or(false);
+    if let Some(ref setup) = data.setup {
+        match game_setup(setup) {
+            Ok(setup) => options.setup = Some(setup),
+            Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+        }
+    }
+    let id = match registry.create(Game::with_options(options)) {
+        Ok(id) => id,
+        Err(e) => return Ok(Response::with((status::InternalServerError, format!("Unable to save the game: {}", e)))),
+    };
+    let payload = registry.get(&id).unwrap().summary(&id).to_string();
+    Ok(Response::with((status::Ok, payload)))
+}
+
+/// Delete a game, for one of its players or an admin
+fn delete_game(request: &mut Request, registry: &Registry, journal: &Mutex<Journal>,
+               admin_key: Option<&str>) -> IronResult<Response> {
+    log_request(request);
+    let id = game_id(request);
+    if id == DEFAULT_GAME {
+        return Ok(Response::with((status::Forbidden, "The default game can't be deleted")))
+    }
+    if let Some(entry) = registry.get(&id) {
+        let player = match request.extensions.get::<AuthenticatedPlayer>() {
+            Some(session) if session.game == id && entry.game.read().unwrap().players.contains_key(&session.name) => {
+                Some(session.name.clone())
+            },
+            _ => None,
+        };
+        if player.is_none() && !is_admin(request, admin_key) {
+            return Ok(Response::with((status::Unauthorized, "Only a player of the game or an admin can delete it")))
+        }
+
+        // A player who deletes a game part way through resigns it, so
+        // that a lost game can't be escaped unrated; an admin's
+        // deletion is recorded as abandoned
+        let mut game = entry.game.write().unwrap();
+        let (len, clock) = (game.stream.len(), game.clock.clone());
+        let ended = match player {
+            Some(ref name) if game.state != GameState::Setup => game.resign(name).is_ok(),
+            _ => game.abandon().is_ok(),
+        };
+        if ended {
+            if let Err(e) = entry.publish_changes(len, &game) {
+                game.rewind(len);
+                game.clock = clock;
+                return Ok(Response::with((status::InternalServerError, format!("Unable to save the change: {}", e))))
+            }
+        }
+    }
+    match registry.remove(&id) {
+        Some(_) => {
+            if let Err(e) = journal.lock().unwrap().remove(&id) {
+                println!("Unable to journal game {}: {}", id, e);
+            }
+            Ok(Response::with((status::Ok, format!("Deleted game {}", id))))
+        },
+        None => Ok(Response::with((status::NotFound, format!("Game {} not found", id)))),
+    }
+}
+
+/// Respond with the JSON of a lobby or tournament call, or why it failed
+fn json_response(result: QuoridorResult<Json>) -> IronResult<Response> {
+    match result {
+        Ok(payload) => Ok(Response::with((status::Ok, payload.to_string()))),
+        Err(e) => Ok(Response::with((status::BadRequest, e.to_string()))),
+    }
+}
+
+/// JSON for a lobby player who may not have been matched yet
+fn waiting_or_matched(seat: Option<::lobby::Seat>) -> Json {
+    match seat {
+        Some(seat) => seat.to_json(),
+        None => {
+            let mut d = BTreeMap::new();
+            d.insert("status".to_string(), "waiting".to_json());
+            Json::Object(d)
+        },
+    }
+}
+
+fn get_lobby(request: &mut Request, lobby: &Lobby) -> IronResult<Response> {
+    log_request(request);
+    Ok(Response::with((status::Ok, lobby.to_json().to_string())))
+}
+
+fn join_lobby(request: &mut Request, lobby: &Mutex<Lobby>, registry: &Registry, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: LobbyJoinRequest = parse_payload!(request);
+    let entrant = accounts::identify(accounts, &data.name, &data.key);
+    json_response(entrant.and_then(|e| lobby.lock().unwrap().join(registry, e, data.rating)).map(waiting_or_matched))
+}
+
+fn leave_lobby(request: &mut Request, lobby: &mut Lobby) -> IronResult<Response> {
+    log_request(request);
+    let data: PlayerRegistrationRequest = parse_payload!(request);
+    json_response(lobby.leave(&data.name, &data.key).map(|_| waiting_or_matched(None)))
+}
+
+fn lobby_status(request: &mut Request, lobby: &Lobby) -> IronResult<Response> {
+    log_request(request);
+    let data: PlayerRegistrationRequest = parse_payload!(request);
+    json_response(lobby.status(&data.name, &data.key).map(waiting_or_matched))
+}
+
+fn post_challenge(request: &mut Request, lobby: &Mutex<Lobby>, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: ChallengeRequest = parse_payload!(request);
+    let private = data.private.unwrap_or(false);
+    let entrant = accounts::identify(accounts, &data.name, &data.key);
+    json_response(entrant.and_then(|e| lobby.lock().unwrap().challenge(e, private)).map(|c| c.to_json()))
+}
+
+fn accept_challenge(request: &mut Request, lobby: &Mutex<Lobby>, registry: &Registry,
+                    accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let id = request.extensions.get::<Router>().unwrap().find("id").unwrap_or("").to_string();
+    let data: PlayerRegistrationRequest = parse_payload!(request);
+    let entrant = accounts::identify(accounts, &data.name, &data.key);
+    json_response(entrant.and_then(|e| lobby.lock().unwrap().accept(registry, &id, e)).map(|s| s.to_json()))
+}
+
+fn join_private(request: &mut Request, lobby: &Mutex<Lobby>, registry: &Registry,
+                accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: JoinCodeRequest = parse_payload!(request);
+    let entrant = accounts::identify(accounts, &data.name, &data.key);
+    json_response(entrant.and_then(|e| lobby.lock().unwrap().accept_code(registry, &data.code, e)).map(|s| s.to_json()))
+}
+
+fn list_tournaments(request: &mut Request, tournaments: &Tournaments) -> IronResult<Response> {
+    log_request(request);
+    Ok(Response::with((status::Ok, tournaments.to_json().to_string())))
+}
+
+/// Create a tournament, for an admin
+fn create_tournament(request: &mut Request, tournaments: &mut Tournaments,
+                     admin_key: Option<&str>) -> IronResult<Response> {
+    log_request(request);
+    if !is_admin(request, admin_key) {
+        return Ok(Response::with((status::Unauthorized, "Only an admin can create a tournament")))
+    }
+    let data: TournamentRequest = parse_payload!(request);
+    let format = match Format::parse(&data.format, data.rounds) {
+        Ok(format) => format,
+        Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+    };
+    let mut options = game_options(data.walls, data.base, data.increment, data.move_limit, data.spectator_delay);
+    options.repetitions = data.repetitions;
+    options.max_plies = data.max_plies;
+    let id = tournaments.create(data.name, format, options);
+    json_response(tournaments.save().map(|_| tournaments.get(&id).unwrap().to_json()))
+}
+
+/// Look up the tournament a request is for, responding 404 if there is none
+macro_rules! find_tournament {
+    ($tournaments: expr, $request: expr) => {
+        {
+            let id = $request.extensions.get::<Router>().unwrap().find("id").unwrap_or("").to_string();
+            match $tournaments.get_mut(&id) {
+                Some(tournament) => tournament,
+                None => return Ok(Response::with(
+                    (status::NotFound, format!("Tournament {} not found", id)))),
+            }
+        }
+    };
+}
+
+fn get_tournament(request: &mut Request, tournaments: &mut Tournaments) -> IronResult<Response> {
+    log_request(request);
+    let tournament = find_tournament!(tournaments, request);
+    let payload = match request.url.path().last() {
+        Some(&"standings") => tournament.standings_json(),
+        Some(&"pairings") => tournament.pairings_json(),
+        _ => tournament.to_json(),
+    };
+    Ok(Response::with((status::Ok, payload.to_string())))
+}
+
+fn join_tournament(request: &mut Request, tournaments: &Mutex<Tournaments>, accounts: &Mutex<Accounts>) -> IronResult<Response> {
+    log_request(request);
+    let data: PlayerRegistrationRequest = parse_payload!(request);
+    // Identified before locking the tournaments, as checking or
+    // hashing the key is slow
+    let entrant = match accounts::identify(accounts, &data.name, &data.key) {
+        Ok(entrant) => entrant,
+        Err(e) => return json_response(Err(e)),
+    };
+    let mut tournaments = tournaments.lock().unwrap();
+    let entered = {
+        let tournament = find_tournament!(tournaments, request);
+        tournament.join(entrant).map(|_| tournament.summary())
+    };
+    json_response(entered.and_then(|json| tournaments.save().map(|_| json)))
+}
+
+/// Start a tournament, for an admin
+fn start_tournament(request: &mut Request, tournaments: &mut Tournaments, registry: &Registry,
+                    admin_key: Option<&str>) -> IronResult<Response> {
+    log_request(request);
+    if !is_admin(request, admin_key) {
+        return Ok(Response::with((status::Unauthorized, "Only an admin can start a tournament")))
+    }
+    let started = {
+        let tournament = find_tournament!(tournaments, request);
+        tournament.start(registry).map(|_| tournament.to_json())
+    };
+    json_response(started.and_then(|json| tournaments.save().map(|_| json)))
+}
+
+fn list_history(request: &mut Request, recorder: &Recorder) -> IronResult<Response> {
+    log_request(request);
+    let player = query_param(request, "player");
+    let limit = query_param(request, "limit").and_then(|l| l.parse().ok()).unwrap_or(100);
+    match recorder.storage.games(player.as_ref().map(|p| &p[..]), limit) {
+        Ok(games) => Ok(Response::with((status::Ok, Json::Array(games.iter().map(|g| g.to_json()).collect()).to_string()))),
+        Err(e) => Ok(Response::with((status::InternalServerError, e.to_string()))),
+    }
+}
+
+fn get_history(request: &mut Request, recorder: &Recorder) -> IronResult<Response> {
+    log_request(request);
+    let id = request.extensions.get::<Router>().unwrap().find("id").unwrap_or("").to_string();
+    match recorder.storage.record(id.parse().unwrap_or(-1)) {
+        // Games still being played are only shown live, to their players
+        // or after any spectator delay
+        Ok(Some(ref record)) if record.reason.is_none() => {
+            Ok(Response::with((status::Forbidden, format!("Game {} is still being played", id))))
+        },
+        Ok(Some(record)) => Ok(Response::with((status::Ok, record.to_json().to_string()))),
+        Ok(None) => Ok(Response::with((status::NotFound, format!("Game {} not found", id)))),
+        Err(e) => Ok(Response::with((status::InternalServerError, e.to_string()))),
+    }
+}
+
+fn get_analysis(request: &mut Request, game: &Game, tablebases: &Arc<Mutex<Tablebases>>) -> IronResult<Response> {
+    log_request(request);
+    let solver = match tablebase::get_or_build(tablebases, game) {
+        Ok(Some(solver)) => solver,
+        Ok(None) => return Ok(Response::with((status::Accepted, "Solving the endgame, try again shortly"))),
+        Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+    };
+    match (solver.probe(game), solver.best_move(game)) {
+        (Ok(outcome), Ok(best)) => {
+            let mut d = BTreeMap::new();
+            d.insert("outcome".to_string(), outcome.to_json());
+            d.insert("best_move".to_string(), best.map(|p| vec![p.x, p.y]).to_json());
+            Ok(Response::with((status::Ok, Json::Object(d).to_string())))
+        },
+        (Err(e), _) | (_, Err(e)) => Ok(Response::with((status::BadRequest, e.to_string()))),
+    }
+}
+
+fn get_small_analysis(request: &mut Request, tablebases: &Arc<Mutex<SmallTablebases>>) -> IronResult<Response> {
+    log_request(request);
+    let data: SmallAnalysisRequest = parse_payload!(request);
+    let mut walls = vec![];
+    for w in &data.board {
+        match Wall::from_tuples((w.p1[0], w.p1[1]), (w.p2[0], w.p2[1])) {
+            Ok(wall) => walls.push(wall),
+            Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+        }
+    }
+    let position = smallboard::Position {
+        pawns: [Point { x: data.pawns[0][0], y: data.pawns[0][1] }, Point { x: data.pawns[1][0], y: data.pawns[1][1] }],
+        in_hand: data.in_hand,
+        walls: walls,
+        turn: data.turn,
+    };
+
+    let tablebase = match smallboard::get_or_build(tablebases, data.size, data.walls) {
+        Ok(Some(tablebase)) => tablebase,
+        Ok(None) => return Ok(Response::with((status::Accepted, "Solving the board, try again shortly"))),
+        Err(e) => return Ok(Response::with((status::BadRequest, e.to_string()))),
+    };
+    match (tablebase.outcome(&position), tablebase.best_turn(&position)) {
+        (Ok(outcome), Ok(best)) => {
+            let mut d = BTreeMap::new();
+            d.insert("outcome".to_string(), outcome.to_json());
+            d.insert("best_turn".to_string(), best.map(|t| t.to_json()).to_json());
+            Ok(Response::with((status::Ok, Json::Object(d).to_string())))
+        },
+        (Err(e), _) | (_, Err(e)) => Ok(Response::with((status::BadRequest, e.to_string()))),
+    }
+}
+
+/// Settings for `listen`
+pub struct ServerOptions {
+    /// Address to push game events over WebSocket on, if any
+    pub websocket_host: Option<String>,
+    /// Directory of the data kept between runs: accounts, tournaments,
+    /// the game database and the journal
+    pub data_dir: PathBuf,
+    /// Key sent in the `X-Admin-Key` header to manage any game, if
+    /// there is an admin
+    pub admin_key: Option<String>,
+    /// How account ratings are updated after a rated game
+    pub rating_system: RatingSystem,
 }
 
-pub fn listen(host: String, _game: Game) {
-    let cond = Arc::new((Mutex::new(false), Condvar::new()));
-    let game = Arc::new(RwLock::new(_game));
+impl Default for ServerOptions {
+    fn default() -> ServerOptions
+    {
+        ServerOptions {
+            websocket_host: None,
+            data_dir: PathBuf::from("data"),
+            admin_key: None,
+            rating_system: RatingSystem::Glicko2,
+        }
+    }
+}
+
+/// Serve the API on `host`
+pub fn listen(host: String, options: ServerOptions, _game: Game) {
+    let registry = Arc::new(Registry::new());
+    registry.insert(DEFAULT_GAME, _game);
+
+    // Rebuild the games that were in progress when the server stopped
+    let (journal, entries) = Journal::open(options.data_dir.join("journal.log")).expect("Unable to open the journal");
+    let recovered = journal::recover(&registry, &entries).expect("Unable to recover games from the journal");
+    if !recovered.is_empty() {
+        println!("Recovered games {}", recovered.join(", "));
+    }
+    let journal = Arc::new(Mutex::new(journal));
+    {
+        // Journal every change before anybody hears of it; a change
+        // that can't be journaled is undone
+        let journal = journal.clone();
+        registry.on_change(Box::new(move |id: &str, version: u64, len: usize, after: &Game| {
+            journal.lock().unwrap().record(id, version, len, after)
+        }));
+    }
+
+    let mut accounts = Accounts::open(options.data_dir.join("accounts.json")).expect("Unable to load accounts");
+    accounts.rating_system = options.rating_system;
+    let accounts = Arc::new(Mutex::new(accounts));
+    {
+        // Count every finished game in the players' account stats
+        let accounts = accounts.clone();
+        registry.on_game_over(Box::new(move |_: &str, game: &Game| {
+            if let Err(e) = accounts.lock().unwrap().record_result(game) {
+                println!("Unable to save account stats: {}", e);
+            }
+        }));
+    }
+
+    let storage = SqliteStorage::open(options.data_dir.join("games.db")).expect("Unable to open the game database");
+    let recorder = Arc::new(Mutex::new(Recorder::new(Box::new(storage))));
+    {
+        // Save every turn as it is taken
+        let recorder = recorder.clone();
+        registry.on_change(Box::new(move |id: &str, _: u64, len: usize, after: &Game| {
+            if let Err(e) = recorder.lock().unwrap().record(id, len, after) {
+                println!("Unable to save game {}: {}", id, e);
+            }
+            Ok(())
+        }));
+    }
+
+    let tournaments = Tournaments::open(options.data_dir.join("tournaments.json")).expect("Unable to load tournaments");
+    // Finished tournament games are no longer in the registry, but
+    // their ids mustn't be given to new games
+    for id in tournaments.game_ids() {
+        registry.reserve(&id);
+    }
+    let tournaments = Arc::new(Mutex::new(tournaments));
+    {
+        // Start the next round of a tournament once its games are over
+        let (tournaments, registry_ref) = (tournaments.clone(), Arc::downgrade(&registry));
+        registry.on_game_over(Box::new(move |id: &str, game: &Game| {
+            if let Some(registry) = registry_ref.upgrade() {
+                if let Err(e) = tournaments.lock().unwrap().record_result(&registry, id, game) {
+                    println!("Unable to start the next tournament round: {}", e);
+                }
+            }
+        }));
+    }
+
+    let rematches = Arc::new(Mutex::new(Rematches::new()));
+    {
+        // Keep the score of series of rematches
+        let rematches = rematches.clone();
+        registry.on_game_over(Box::new(move |id: &str, game: &Game| {
+            rematches.lock().unwrap().record_result(id, game);
+        }));
+    }
+
+    let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(TOKEN_LIFETIME))));
+    if let Some(websocket_host) = options.websocket_host {
+        let (registry, sessions) = (registry.clone(), sessions.clone());
+        thread::spawn(move || websocket::listen(websocket_host, registry, sessions));
+    }
+    let tablebases = Arc::new(Mutex::new(Tablebases::new(Some(options.data_dir.join("tablebases")))));
+    let small_tablebases = Arc::new(Mutex::new(SmallTablebases::new(Some(options.data_dir.join("tablebases")))));
+    let streams = StreamLimit::new(MAX_STREAMS);
     let mut router = Router::new();
 
-    // POST
-    register_turn_route!(router, cond, "/register_player", register_player, game);
-    register_turn_route!(router, cond, "/move_player_to", move_player_to, game);
-    register_turn_route!(router, cond, "/move_player", move_player, game);
-    register_turn_route!(router, cond, "/place_wall", place_wall, game);
+    {
+        // Players lose on time even if they never call back
+        let registry = registry.clone();
+        thread::spawn(move || loop {
+            registry.check_clocks(Instant::now());
+            thread::sleep(Duration::from_millis(100));
+        });
+    }
+
+    // Every game route is served for the default game, and under
+    // /games/:id for any game in the registry
+    for prefix in &["", "/games/:id"] {
+        let route = |path: &str| format!("{}{}", prefix, path);
 
-    // GET
-    register_get_route!(router, "/state", get_status, game);
-    register_get_route!(router, "/ascii", get_ascii, game);
+        // POST
+        {
+            let (registry, sessions, accounts) = (registry.clone(), sessions.clone(), accounts.clone());
+            router.post(&route("/register_player"), move |r: &mut Request| {
+                let entry = find_game!(registry, r);
+                register_player(r, &entry, &sessions, &accounts)
+            });
+        }
+        register_turn_route!(router, &route("/move_player_to"), move_player_to, registry);
+        register_turn_route!(router, &route("/move_player"), move_player, registry);
+        register_turn_route!(router, &route("/place_wall"), place_wall, registry);
+        register_turn_route!(router, &route("/resign"), resign, registry);
+        register_turn_route!(router, &route("/offer_draw"), offer_draw, registry);
+        register_turn_route!(router, &route("/accept_draw"), accept_draw, registry);
+        register_turn_route!(router, &route("/decline_draw"), decline_draw, registry);
+        register_turn_route!(router, &route("/request_takeback"), request_takeback, registry);
+        register_turn_route!(router, &route("/accept_takeback"), accept_takeback, registry);
+        register_turn_route!(router, &route("/decline_takeback"), decline_takeback, registry);
+        register_turn_route!(router, &route("/swap_sides"), swap_sides, registry);
+        {
+            let (registry, rematches) = (registry.clone(), rematches.clone());
+            router.post(&route("/rematch"), move |r: &mut Request| rematch(r, &registry, &rematches));
+        }
+
+        // GET
+        register_get_route!(router, &route("/state"), get_status, registry);
+        register_get_route!(router, &route("/ascii"), get_ascii, registry);
+        register_get_route!(router, &route("/record"), get_record, registry);
+
+        {
+            // Long poll for changes after a version of the game
+            let registry = registry.clone();
+            router.get(&route("/wait_for_activity"), move |r: &mut Request| {
+                let entry = find_live_game!(registry, r);
+                wait_for_activity(r, &entry)
+            });
+        }
+
+        {
+            // Swap a name and key for a new token, or revoke a token
+            let (registry, sessions_clone) = (registry.clone(), sessions.clone());
+            router.post(&route("/login"), move |r: &mut Request| {
+                let entry = find_game!(registry, r);
+                let ret = login(r, &entry.game.read().unwrap(), &sessions_clone);
+                ret
+            });
+            let sessions_clone = sessions.clone();
+            router.post(&route("/logout"), move |r: &mut Request| logout(r, &sessions_clone));
+        }
+
+        {
+            // Stream every event of the game
+            let (registry, streams) = (registry.clone(), streams.clone());
+            router.get(&route("/events"), move |r: &mut Request| {
+                let entry = find_live_game!(registry, r);
+                stream_events(r, entry, stream_slot!(streams))
+            });
+        }
+
+        {
+            // Spectators, who see the game behind any spectator delay
+            let (registry_clone, streams) = (registry.clone(), streams.clone());
+            router.get(&route("/spectate"), move |r: &mut Request| {
+                let entry = find_game!(registry_clone, r);
+                spectate(r, entry, stream_slot!(streams))
+            });
+            let registry_clone = registry.clone();
+            router.get(&route("/spectate/state"), move |r: &mut Request| {
+                let entry = find_game!(registry_clone, r);
+                get_spectator_state(r, &entry)
+            });
+            let registry_clone = registry.clone();
+            router.get(&route("/spectators"), move |r: &mut Request| {
+                let entry = find_game!(registry_clone, r);
+                get_spectators(r, &entry)
+            });
+            let registry_clone = registry.clone();
+            router.post(&route("/spectators"), move |r: &mut Request| {
+                let entry = find_game!(registry_clone, r);
+                join_spectators(r, &entry)
+            });
+            let registry_clone = registry.clone();
+            router.post(&route("/spectators/leave"), move |r: &mut Request| {
+                let entry = find_game!(registry_clone, r);
+                leave_spectators(r, &entry)
+            });
+        }
+
+        {
+            // Probe the endgame tablebase for the current board
+            let registry = registry.clone();
+            let tablebases = tablebases.clone();
+            router.get(&route("/analysis"), move |r: &mut Request| {
+                let entry = find_live_game!(registry, r);
+                let game = entry.game.read().unwrap().clone();
+                get_analysis(r, &game, &tablebases)
+            });
+        }
+    }
 
     {
-        // Special case to wait for next turn
-        let cond_clone = cond.clone();
-        router.get("/wait_for_activity", move |r: &mut Request| {
-            let &(ref lock, ref cvar) = &*cond_clone;
-            wait_for_activity(r, lock, cvar)
+        // Game registry
+        let registry_clone = registry.clone();
+        router.get("/games", move |r: &mut Request| list_games(r, &registry_clone));
+        let registry_clone = registry.clone();
+        router.post("/games", move |r: &mut Request| create_game(r, &registry_clone));
+        let (registry_clone, journal_clone, admin_key) = (registry.clone(), journal.clone(), options.admin_key.clone());
+        router.delete("/games/:id", move |r: &mut Request| {
+            delete_game(r, &registry_clone, &journal_clone, admin_key.as_ref().map(|k| &k[..]))
         });
     }
 
+    // Solve positions on small boards, walls in hand included
+    router.post("/analysis/small", move |r: &mut Request| get_small_analysis(r, &small_tablebases));
+
+    {
+        // Player accounts
+        let accounts_clone = accounts.clone();
+        router.post("/accounts", move |r: &mut Request| create_account(r, &accounts_clone));
+        let accounts_clone = accounts.clone();
+        router.get("/accounts/:name", move |r: &mut Request| get_account(r, &accounts_clone));
+        let accounts_clone = accounts.clone();
+        router.get("/leaderboard", move |r: &mut Request| get_leaderboard(r, &accounts_clone));
+        let accounts_clone = accounts.clone();
+        router.get("/players/:name/rating", move |r: &mut Request| get_rating(r, &accounts_clone));
+    }
+
+    {
+        // Lobby and matchmaking
+        let mut lobby = Lobby::new();
+        lobby.accounts = Some(accounts.clone());
+        lobby.sessions = Some(sessions.clone());
+        let lobby = Arc::new(Mutex::new(lobby));
+        let lobby_clone = lobby.clone();
+        router.get("/lobby", move |r: &mut Request| get_lobby(r, &lobby_clone.lock().unwrap()));
+        let (lobby_clone, registry_clone, accounts_clone) = (lobby.clone(), registry.clone(), accounts.clone());
+        router.post("/lobby/join", move |r: &mut Request| join_lobby(r, &lobby_clone, &registry_clone, &accounts_clone));
+        let lobby_clone = lobby.clone();
+        router.post("/lobby/leave", move |r: &mut Request| leave_lobby(r, &mut lobby_clone.lock().unwrap()));
+        let lobby_clone = lobby.clone();
+        router.post("/lobby/status", move |r: &mut Request| lobby_status(r, &lobby_clone.lock().unwrap()));
+        let (lobby_clone, accounts_clone) = (lobby.clone(), accounts.clone());
+        router.post("/lobby/challenges", move |r: &mut Request| post_challenge(r, &lobby_clone, &accounts_clone));
+        let (lobby_clone, registry_clone, accounts_clone) = (lobby.clone(), registry.clone(), accounts.clone());
+        router.post("/lobby/challenges/:id/accept", move |r: &mut Request| {
+            accept_challenge(r, &lobby_clone, &registry_clone, &accounts_clone)
+        });
+        let (lobby_clone, registry_clone, accounts_clone) = (lobby.clone(), registry.clone(), accounts.clone());
+        router.post("/lobby/join_code", move |r: &mut Request| {
+            join_private(r, &lobby_clone, &registry_clone, &accounts_clone)
+        });
+    }
+
+    {
+        // Tournaments
+        let tournaments_clone = tournaments.clone();
+        router.get("/tournaments", move |r: &mut Request| list_tournaments(r, &tournaments_clone.lock().unwrap()));
+        let (tournaments_clone, admin_key) = (tournaments.clone(), options.admin_key.clone());
+        router.post("/tournaments", move |r: &mut Request| {
+            create_tournament(r, &mut tournaments_clone.lock().unwrap(), admin_key.as_ref().map(|k| &k[..]))
+        });
+        for route in &["/tournaments/:id", "/tournaments/:id/standings", "/tournaments/:id/pairings"] {
+            let tournaments_clone = tournaments.clone();
+            router.get(*route, move |r: &mut Request| get_tournament(r, &mut tournaments_clone.lock().unwrap()));
+        }
+        let (tournaments_clone, accounts_clone) = (tournaments.clone(), accounts.clone());
+        router.post("/tournaments/:id/join", move |r: &mut Request| {
+            join_tournament(r, &tournaments_clone, &accounts_clone)
+        });
+        let (tournaments_clone, registry_clone, admin_key) = (tournaments.clone(), registry.clone(), options.admin_key.clone());
+        router.post("/tournaments/:id/start", move |r: &mut Request| {
+            start_tournament(r, &mut tournaments_clone.lock().unwrap(), &registry_clone, admin_key.as_ref().map(|k| &k[..]))
+        });
+    }
+
+    let rematches_clone = rematches.clone();
+    router.get("/series/:id", move |r: &mut Request| get_series(r, &rematches_clone.lock().unwrap()));
+
+    {
+        // Games played so far
+        let recorder_clone = recorder.clone();
+        router.get("/history", move |r: &mut Request| list_history(r, &recorder_clone.lock().unwrap()));
+        let recorder_clone = recorder.clone();
+        router.get("/history/:id", move |r: &mut Request| get_history(r, &recorder_clone.lock().unwrap()));
+    }
+
     let mut mount = Mount::new();
     mount.mount("/api", router)
          .mount("/game", Static::new(Path::new("static")));
 
+    let mut chain = Chain::new(mount);
+    chain.link_before(Authenticate { sessions: sessions });
+
     println!("Listening on {} ...", host);
-    Iron::new(mount).http(&*host).unwrap();
+    let mut server = Iron::new(chain);
+    server.threads = WORKERS;
+    server.http(&*host).unwrap();
 
 }
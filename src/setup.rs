@@ -0,0 +1,219 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Handicaps and custom starting positions
+//!
+//! A game can be set up with a different number of walls for each
+//! seat, other starting squares, walls already on the board and either
+//! seat to move first, so that juniors can be paired with stronger
+//! players or bots.
+
+use board::{Point, Wall};
+use constants::N;
+use errors::{QuoridorError, QuoridorResult};
+use player::Player;
+use quoridor::Game;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use symmetry::ROTATE_180;
+
+/// How a game starts, in place of the usual position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setup {
+    /// Walls each seat starts with, in place of the game's `walls`
+    pub walls: Option<Vec<u8>>,
+    /// Square each seat starts on
+    pub starts: Option<Vec<Point>>,
+    /// Walls on the board before the first move
+    pub board: Vec<Wall>,
+    /// Seat that moves first
+    pub first: u8,
+}
+
+/// Seats in a game that can be set up
+const SEATS: usize = 2;
+
+/// The usual starting square of `seat`
+pub fn default_start(seat: u8) -> Point
+{
+    [
+        Point { x: N / 2, y: 0 },
+        Point { x: N / 2, y: N - 1 },
+        Point { x: 0, y: N / 2 },
+        Point { x: N - 1, y: N / 2 },
+    ][seat as usize]
+}
+
+fn point_json(p: Point) -> Json
+{
+    vec![p.x, p.y].to_json()
+}
+
+/// A number that fits in a `u8`
+fn u8_from_json(json: &Json) -> Option<u8>
+{
+    json.as_u64().and_then(|n| match n <= u8::max_value() as u64 {
+        true => Some(n as u8),
+        false => None,
+    })
+}
+
+fn point_from_json(json: &Json) -> Option<Point>
+{
+    match json.as_array().map(|a| a.iter().map(|c| c.as_i64()).collect::<Option<Vec<_>>>()) {
+        Some(Some(ref c)) if c.len() == 2 => Some(Point { x: c[0] as i32, y: c[1] as i32 }),
+        _ => None,
+    }
+}
+
+impl Setup {
+    /// Walls `seat` starts with, if the setup says
+    pub fn walls(&self, seat: u8) -> Option<u8>
+    {
+        self.walls.as_ref().and_then(|w| w.get(seat as usize).cloned())
+    }
+
+    /// Square `seat` starts on
+    pub fn start(&self, seat: u8) -> Point
+    {
+        self.starts.as_ref().and_then(|s| s.get(seat as usize).cloned()).unwrap_or_else(|| default_start(seat))
+    }
+
+    /// Does the setup favour one seat: different walls in hand, or
+    /// starting squares that aren't the same distance from each goal
+    pub fn is_handicap(&self) -> bool
+    {
+        self.walls(0) != self.walls(1) || ROTATE_180.point(self.start(0)) != self.start(1)
+    }
+
+    /// Check the setup can be played: a wall count and a starting
+    /// square for each seat if any, squares on the board, apart and
+    /// short of their goal, and walls that fit together and leave every
+    /// player a path to their goal
+    pub fn validate(&self) -> QuoridorResult<()>
+    {
+        let invalid = |s: &str| Err(QuoridorError::SetupError(s.into()));
+        if self.walls.as_ref().map_or(false, |w| w.len() != SEATS) {
+            return invalid("Give walls for each of the two seats")
+        }
+        if self.starts.as_ref().map_or(false, |s| s.len() != SEATS) {
+            return invalid("Give a starting square for each of the two seats")
+        }
+        if self.first as usize >= SEATS {
+            return invalid("There is no such seat to move first")
+        }
+
+        let mut game = Game::new();
+        for seat in 0..SEATS as u8 {
+            let p = self.start(seat);
+            if !p.inbounds() {
+                return invalid("Starting square off the board")
+            }
+            if game.get_player_at_position(p).is_ok() {
+                return invalid("Players can't start on the same square")
+            }
+            let player = Player { p: p, key_hash: String::new(), id: seat, walls: 0, name: seat.to_string(), account: None };
+            if player.p.y == [N - 1, 0][seat as usize] {
+                return invalid("A player can't start on their goal")
+            }
+            game.players.insert(player.name.clone(), player);
+        }
+        for wall in self.board.iter() {
+            try!(game.is_valid_wall(wall).map_err(|e| QuoridorError::SetupError(e.to_string())));
+            game.walls.insert(*wall);
+        }
+        Ok(())
+    }
+
+    /// The same setup with the players' seats swapped, as in a
+    /// rematch: each player keeps their walls, and the board is turned
+    /// round so they keep their square too.  The same seat moves first,
+    /// which is now the other player.
+    pub fn swapped(&self) -> Setup
+    {
+        Setup {
+            walls: self.walls.as_ref().map(|w| w.iter().rev().cloned().collect()),
+            starts: self.starts.as_ref().map(|s| s.iter().rev().map(|&p| ROTATE_180.point(p)).collect()),
+            board: self.board.iter().map(|&w| ROTATE_180.wall(w)).collect(),
+            first: self.first,
+        }
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("walls".to_string(), self.walls.to_json());
+        d.insert("starts".to_string(), self.starts.as_ref().map_or(Json::Null, |s| {
+            Json::Array(s.iter().map(|&p| point_json(p)).collect())
+        }));
+        d.insert("board".to_string(), Json::Array(self.board.iter().map(|w| {
+            let (a, b) = w.to_tuples();
+            vec![vec![a.0, a.1], vec![b.0, b.1]].to_json()
+        }).collect()));
+        d.insert("first".to_string(), self.first.to_json());
+        Json::Object(d)
+    }
+
+    /// Parse a setup written by `to_json`
+    pub fn from_json(json: &Json) -> Option<Setup>
+    {
+        let walls = match json.find("walls") {
+            Some(&Json::Null) | None => None,
+            Some(walls) => match walls.as_array().map(|w| w.iter().map(u8_from_json).collect()) {
+                Some(Some(walls)) => Some(walls),
+                _ => return None,
+            },
+        };
+        let starts = match json.find("starts") {
+            Some(&Json::Null) | None => None,
+            Some(starts) => match starts.as_array().map(|s| s.iter().map(point_from_json).collect()) {
+                Some(Some(starts)) => Some(starts),
+                _ => return None,
+            },
+        };
+        let mut board = vec![];
+        for wall in json.find("board").and_then(|b| b.as_array()).map_or(&[][..], |b| &b[..]) {
+            match wall.as_array().map(|w| w.iter().map(point_from_json).collect::<Option<Vec<_>>>()) {
+                Some(Some(ref ends)) if ends.len() == 2 => {
+                    match Wall::from_tuples((ends[0].x, ends[0].y), (ends[1].x, ends[1].y)) {
+                        Ok(wall) => board.push(wall),
+                        Err(_) => return None,
+                    }
+                },
+                _ => return None,
+            }
+        }
+        let first = match json.find("first") {
+            Some(first) => match u8_from_json(first) {
+                Some(first) => first,
+                None => return None,
+            },
+            None => 0,
+        };
+        Some(Setup {
+            walls: walls,
+            starts: starts,
+            board: board,
+            first: first,
+        })
+    }
+}
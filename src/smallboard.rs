@@ -0,0 +1,755 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Tablebases for small boards with walls in hand
+//!
+//! A board only a few squares across has few enough positions to solve
+//! every one of them, walls in hand included.  A small tablebase covers
+//! one board size and number of walls per player: every placement of
+//! the pawns, the walls on the board, the walls left in each hand and
+//! the player to move.  The rules are the crate's, scaled down to the
+//! smaller board: seat 0 races to the last row and seat 1 to the first.
+//!
+//! Walls are never taken back, so wall layouts are solved fullest
+//! first.  The pawn moves within each layout are then solved by
+//! retrograde analysis, starting from the values of the wall
+//! placements out of it, which all lead to layouts already solved.
+//!
+//! Small tablebase files are laid out as:
+//!
+//! * the magic bytes `QSB1`
+//! * the board size and the walls per player, one byte each
+//! * the number of wall layouts as a little endian u32
+//! * every outcome as a little endian u16, encoded as in `tablebase`
+//!   files and in the order of `SmallTablebase::index`
+
+use board::{Orientation, Point, Wall};
+use errors::{QuoridorError, QuoridorResult};
+use solver::Outcome;
+use tablebase;
+use turn::Turn;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const MAGIC: &'static [u8] = b"QSB1";
+
+/// Smallest board size
+pub const MIN_SIZE: usize = 3;
+
+/// Largest board size, so that every wall slot fits in a u64
+pub const MAX_SIZE: usize = 6;
+
+/// Most positions a small tablebase may have
+pub const MAX_POSITIONS: usize = 100_000_000;
+
+/// A position on a small board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// Squares of seat 0's and seat 1's pawns
+    pub pawns: [Point; 2],
+    /// Walls each seat has left to place
+    pub in_hand: [u8; 2],
+    /// Walls on the board
+    pub walls: Vec<Wall>,
+    /// Seat to move
+    pub turn: u8,
+}
+
+/// Squares and wall slots of a small board, with the crate's rules for
+/// moving on it.  Wall layouts are bitmasks of slots and squares are
+/// numbered row by row.
+struct Board {
+    size: i32,
+    /// Slots each slot collides with, itself included
+    collisions: Vec<u64>,
+}
+
+impl Board {
+    fn new(size: usize) -> Board
+    {
+        let mut board = Board {
+            size: size as i32,
+            collisions: vec![],
+        };
+        let collisions = (0..board.slots()).map(|s| {
+            let wall = board.wall(s);
+            let (back, forward) = match wall.orientation {
+                Orientation::Horizontal => (wall.shifted(-1, 0), wall.shifted(1, 0)),
+                Orientation::Vertical => (wall.shifted(0, -1), wall.shifted(0, 1)),
+            };
+            [wall, wall.rotated(), back, forward].iter()
+                .filter_map(|w| board.slot(w))
+                .fold(0, |mask, slot| mask | 1 << slot)
+        }).collect();
+        board.collisions = collisions;
+        board
+    }
+
+    fn squares(&self) -> usize
+    {
+        (self.size * self.size) as usize
+    }
+
+    fn slots(&self) -> usize
+    {
+        ((self.size - 1) * (self.size - 1) * 2) as usize
+    }
+
+    fn square(&self, p: Point) -> usize
+    {
+        (p.y * self.size + p.x) as usize
+    }
+
+    fn point(&self, i: usize) -> Point
+    {
+        point!(i as i32 % self.size, i as i32 / self.size)
+    }
+
+    fn inbounds(&self, p: Point) -> bool
+    {
+        p.x >= 0 && p.x < self.size && p.y >= 0 && p.y < self.size
+    }
+
+    /// Slot of `wall`, if it is on the board
+    fn slot(&self, wall: &Wall) -> Option<usize>
+    {
+        let n = self.size - 1;
+        if wall.x < 1 || wall.x > n || wall.y < 1 || wall.y > n {
+            return None
+        }
+        let o = match wall.orientation {
+            Orientation::Horizontal => 0,
+            Orientation::Vertical => 1,
+        };
+        Some(((o * n + wall.y - 1) * n + wall.x - 1) as usize)
+    }
+
+    fn wall(&self, slot: usize) -> Wall
+    {
+        let n = (self.size - 1) as usize;
+        let (x, y) = ((slot % n + 1) as i32, (slot / n % n + 1) as i32);
+        match slot / (n * n) {
+            0 => Wall::horizontal(x, y),
+            _ => Wall::vertical(x, y),
+        }
+    }
+
+    fn is_goal(&self, seat: usize, p: Point) -> bool
+    {
+        match seat {
+            0 => p.y == self.size - 1,
+            _ => p.y == 0,
+        }
+    }
+
+    fn has_wall(&self, layout: u64, wall: Wall) -> bool
+    {
+        self.slot(&wall).map_or(false, |slot| layout & 1 << slot != 0)
+    }
+
+    /// Is there a wall between neighbouring squares, as
+    /// `Game::has_wall_between`
+    fn has_wall_between(&self, layout: u64, a: Point, b: Point) -> bool
+    {
+        if !a.neighbors(b) {
+            return false
+        }
+        if a.y == b.y {
+            let wall = Wall::vertical(cmp::max(a.x, b.x), a.y);
+            self.has_wall(layout, wall) || self.has_wall(layout, wall.shifted(0, 1))
+        } else {
+            let wall = Wall::horizontal(a.x, cmp::max(a.y, b.y));
+            self.has_wall(layout, wall) || self.has_wall(layout, wall.shifted(1, 0))
+        }
+    }
+
+    /// Can a pawn jump from `a` to `b` over the pawn at `other`, as
+    /// `Game::is_valid_jump`
+    fn is_valid_jump(&self, layout: u64, a: Point, b: Point, other: Point) -> bool
+    {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        if (dx == 0 && dy.abs() == 2) || (dy == 0 && dx.abs() == 2) {
+            let middle = point!(b.x - dx / 2, b.y - dy / 2);
+            return middle == other && !self.has_wall_between(layout, a, middle) &&
+                !self.has_wall_between(layout, middle, b)
+        }
+        if dx.abs() != 1 || dy.abs() != 1 {
+            return false
+        }
+
+        let side = point!(b.x, a.y);
+        if side == other && self.has_wall_between(layout, side, point!(b.x + dx, a.y)) &&
+            !self.has_wall_between(layout, a, side) && !self.has_wall_between(layout, side, b) {
+            return true
+        }
+        let side = point!(a.x, b.y);
+        side == other && self.has_wall_between(layout, side, point!(a.x, b.y + dy)) &&
+            !self.has_wall_between(layout, a, side) && !self.has_wall_between(layout, side, b)
+    }
+
+    /// Every square a pawn at `p` can move to, as `Game::valid_moves`
+    fn valid_moves(&self, layout: u64, p: Point, other: Point) -> Vec<Point>
+    {
+        let mut moves = vec![];
+        for dy in -2..3 {
+            for dx in -2..3 {
+                let q = point!(p.x + dx, p.y + dy);
+                if !self.inbounds(q) || q == p || q == other || self.has_wall_between(layout, p, q) {
+                    continue
+                }
+                if p.neighbors(q) || self.is_valid_jump(layout, p, q, other) {
+                    moves.push(q);
+                }
+            }
+        }
+        moves
+    }
+
+    /// Squares from which `seat` can reach their goal, ignoring pawns
+    fn reachable(&self, layout: u64, seat: usize) -> u64
+    {
+        let mut reached = 0u64;
+        let mut queue = VecDeque::new();
+        for i in 0..self.squares() {
+            if self.is_goal(seat, self.point(i)) {
+                reached |= 1 << i;
+                queue.push_back(self.point(i));
+            }
+        }
+        while let Some(p) = queue.pop_front() {
+            for &q in &[p.north(), p.east(), p.south(), p.west()] {
+                if !self.inbounds(q) || reached & 1 << self.square(q) != 0 || self.has_wall_between(layout, p, q) {
+                    continue
+                }
+                reached |= 1 << self.square(q);
+                queue.push_back(q);
+            }
+        }
+        reached
+    }
+}
+
+/// Solved values of every position on a small board
+pub struct SmallTablebase {
+    board: Board,
+    walls: u8,
+    /// Wall layouts, fullest first
+    layouts: Vec<u64>,
+    /// Index of each layout in `layouts`
+    layout_index: HashMap<u64, usize>,
+    /// Squares from which each seat can reach their goal, per layout
+    reachable: Vec<[u64; 2]>,
+    /// Index of each layout's first position
+    offsets: Vec<usize>,
+    /// Every outcome, encoded as in tablebase files
+    outcomes: Vec<u16>,
+}
+
+/// Queue a solved position `value` plies from the end of the game
+fn push(queue: &mut Vec<Vec<(usize, Outcome)>>, i: usize, value: Outcome)
+{
+    let d = match value {
+        Outcome::Win(d) | Outcome::Loss(d) => d as usize,
+        Outcome::Draw => 0,
+    };
+    if queue.len() <= d {
+        queue.resize(d + 1, vec![]);
+    }
+    queue[d].push((i, value));
+}
+
+impl SmallTablebase {
+    /// Solve every position on a `size` by `size` board where each
+    /// player starts with `walls` walls, failing if there are more
+    /// than `limit` of them
+    pub fn generate(size: usize, walls: u8, limit: usize) -> QuoridorResult<SmallTablebase>
+    {
+        let mut t = try!(SmallTablebase::unsolved(size, walls, limit));
+        let (n, total) = (t.block(), 2 * walls as usize);
+        for l in 0..t.layouts.len() {
+            let placed = t.layouts[l].count_ones() as usize;
+            let (lo, hi) = t.hands(l);
+            for h0 in lo..hi + 1 {
+                let outcomes = t.solve(l, [h0, total - placed - h0]);
+                let start = t.offsets[l] + (h0 - lo) * n;
+                for (i, outcome) in outcomes.into_iter().enumerate() {
+                    t.outcomes[start + i] = tablebase::encode(outcome);
+                }
+            }
+        }
+        Ok(t)
+    }
+
+    /// A tablebase with every layout enumerated but nothing solved
+    fn unsolved(size: usize, walls: u8, limit: usize) -> QuoridorResult<SmallTablebase>
+    {
+        if size < MIN_SIZE || size > MAX_SIZE {
+            return Err(QuoridorError::AnalysisError(format!("Small boards are {} to {} squares across", MIN_SIZE, MAX_SIZE)))
+        }
+        let board = Board::new(size);
+        let n = board.squares() * board.squares() * 2;
+        let too_many = || QuoridorError::AnalysisError(
+            format!("A {}x{} board with {} walls each has more than {} positions", size, size, walls, limit));
+
+        // Every layout of up to both hands' walls with no two walls
+        // colliding.  Each has at least one block of positions, so
+        // stop as soon as there are too many.
+        let most = cmp::min(2 * walls as usize, board.slots());
+        let mut layouts = vec![0u64];
+        let mut stack = vec![(0u64, 0usize)];
+        while let Some((layout, from)) = stack.pop() {
+            if layout.count_ones() as usize == most {
+                continue
+            }
+            for slot in from..board.slots() {
+                if layout & board.collisions[slot] == 0 {
+                    layouts.push(layout | 1 << slot);
+                    stack.push((layout | 1 << slot, slot + 1));
+                }
+            }
+            if layouts.len() > limit / n {
+                return Err(too_many())
+            }
+        }
+        layouts.sort_by_key(|&l| (64 - l.count_ones(), l));
+
+        let mut t = SmallTablebase {
+            walls: walls,
+            layout_index: layouts.iter().enumerate().map(|(i, &l)| (l, i)).collect(),
+            reachable: layouts.iter().map(|&l| [board.reachable(l, 0), board.reachable(l, 1)]).collect(),
+            offsets: vec![],
+            outcomes: vec![],
+            layouts: layouts,
+            board: board,
+        };
+        let mut total = 0;
+        for l in 0..t.layouts.len() {
+            t.offsets.push(total);
+            let (lo, hi) = t.hands(l);
+            total += (hi + 1 - lo) * n;
+            if total > limit {
+                return Err(too_many())
+            }
+        }
+        t.outcomes = vec![0; total];
+        Ok(t)
+    }
+
+    /// Number of positions per layout and split of walls in hand
+    fn block(&self) -> usize
+    {
+        self.board.squares() * self.board.squares() * 2
+    }
+
+    /// Fewest and most walls seat 0 can have in hand with layout `l`
+    /// on the board
+    fn hands(&self, l: usize) -> (usize, usize)
+    {
+        let walls = self.walls as usize;
+        let left = 2 * walls - self.layouts[l].count_ones() as usize;
+        (left.saturating_sub(walls), cmp::min(walls, left))
+    }
+
+    /// Index of a position with layout `l` on the board, seat 0
+    /// holding `h0` walls, the pawns on squares `a` and `b` and seat
+    /// `turn` to move
+    fn index(&self, l: usize, h0: usize, a: usize, b: usize, turn: usize) -> usize
+    {
+        let (lo, _) = self.hands(l);
+        let squares = self.board.squares();
+        self.offsets[l] + (((h0 - lo) * squares + a) * squares + b) * 2 + turn
+    }
+
+    /// Every wall that can be placed on layout `l` with the pawns on
+    /// squares `pawns`, and the layout it leads to
+    fn placements(&self, l: usize, pawns: [usize; 2]) -> Vec<(Wall, usize)>
+    {
+        let layout = self.layouts[l];
+        (0..self.board.slots()).filter(|&slot| layout & self.board.collisions[slot] == 0).filter_map(|slot| {
+            let next = self.layout_index[&(layout | 1 << slot)];
+            let reachable = self.reachable[next];
+            match reachable[0] & 1 << pawns[0] != 0 && reachable[1] & 1 << pawns[1] != 0 {
+                true => Some((self.board.wall(slot), next)),
+                false => None,
+            }
+        }).collect()
+    }
+
+    /// Solve the positions of layout `l` with `in_hand` walls left,
+    /// indexed as in `index`
+    fn solve(&self, l: usize, in_hand: [usize; 2]) -> Vec<Outcome>
+    {
+        let (layout, squares, n) = (self.layouts[l], self.board.squares(), self.block());
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; n];
+        let mut outcomes: Vec<Option<Outcome>> = vec![None; n];
+        // Shortest win and longest loss found so far, and whether a
+        // wall can be placed to draw
+        let mut wins: Vec<Option<u32>> = vec![None; n];
+        let mut losses: Vec<u32> = vec![0; n];
+        let mut draws = vec![false; n];
+        let mut queue = vec![];
+
+        for a in 0..squares {
+            for b in 0..squares {
+                if a == b {
+                    continue;
+                }
+                let pawns = [self.board.point(a), self.board.point(b)];
+                let won = [self.board.is_goal(0, pawns[0]), self.board.is_goal(1, pawns[1])];
+
+                for turn in 0..2 {
+                    let i = (a * squares + b) * 2 + turn;
+                    if won[1 - turn] {
+                        push(&mut queue, i, Outcome::Loss(0));
+                        continue;
+                    } else if won[turn] {
+                        push(&mut queue, i, Outcome::Win(0));
+                        continue;
+                    }
+
+                    successors[i] = self.board.valid_moves(layout, pawns[turn], pawns[1 - turn]).into_iter().map(|q| {
+                        let q = self.board.square(q);
+                        match turn {
+                            0 => (q * squares + b) * 2 + 1,
+                            _ => (a * squares + q) * 2,
+                        }
+                    }).collect();
+
+                    // Placing a wall leads to a layout already solved
+                    if in_hand[turn] == 0 {
+                        continue;
+                    }
+                    let h0 = in_hand[0] - (1 - turn);
+                    let placements = self.placements(l, [a, b]);
+                    for &(_, next) in &placements {
+                        match tablebase::decode(self.outcomes[self.index(next, h0, a, b, 1 - turn)]) {
+                            Outcome::Loss(d) => wins[i] = Some(cmp::min(d + 1, wins[i].unwrap_or(d + 1))),
+                            Outcome::Win(d) => losses[i] = cmp::max(d + 1, losses[i]),
+                            Outcome::Draw => draws[i] = true,
+                        }
+                    }
+                    if let Some(d) = wins[i] {
+                        push(&mut queue, i, Outcome::Win(d));
+                    } else if successors[i].is_empty() && draws[i] {
+                        outcomes[i] = Some(Outcome::Draw);
+                    } else if successors[i].is_empty() && !placements.is_empty() {
+                        push(&mut queue, i, Outcome::Loss(losses[i]));
+                    }
+                }
+            }
+        }
+
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; n];
+        for (i, next) in successors.iter().enumerate() {
+            for &j in next {
+                predecessors[j].push(i);
+            }
+        }
+
+        // Work backwards from the finished games and the wall
+        // placements, nearest the end first, so that a win is
+        // recorded at its shortest distance and a loss at its longest
+        let mut remaining: Vec<usize> = successors.iter().map(|next| next.len()).collect();
+        let mut d = 0;
+        while d < queue.len() {
+            for (j, value) in mem::replace(&mut queue[d], vec![]) {
+                if outcomes[j].is_some() {
+                    continue;
+                }
+                outcomes[j] = Some(value);
+                for &i in &predecessors[j] {
+                    if outcomes[i].is_some() {
+                        continue;
+                    }
+                    match value {
+                        Outcome::Loss(e) => {
+                            if wins[i].map_or(true, |w| e + 1 < w) {
+                                wins[i] = Some(e + 1);
+                                push(&mut queue, i, Outcome::Win(e + 1));
+                            }
+                        },
+                        Outcome::Win(e) => {
+                            remaining[i] -= 1;
+                            losses[i] = cmp::max(e + 1, losses[i]);
+                            if remaining[i] == 0 && wins[i].is_none() {
+                                match draws[i] {
+                                    true => outcomes[i] = Some(Outcome::Draw),
+                                    false => push(&mut queue, i, Outcome::Loss(losses[i])),
+                                }
+                            }
+                        },
+                        Outcome::Draw => (),
+                    }
+                }
+            }
+            d += 1;
+        }
+
+        // Anything left over can be shuffled around forever (or has
+        // no legal turn at all), so nobody can force a win
+        outcomes.into_iter().map(|o| o.unwrap_or(Outcome::Draw)).collect()
+    }
+
+    /// Size of the board
+    pub fn size(&self) -> usize
+    {
+        self.board.size as usize
+    }
+
+    /// Walls each player starts with
+    pub fn walls(&self) -> u8
+    {
+        self.walls
+    }
+
+    /// Number of solved positions
+    pub fn len(&self) -> usize
+    {
+        self.outcomes.len()
+    }
+
+    /// Layout index and pawn squares of `position`, which must be a
+    /// position of this tablebase
+    fn locate(&self, position: &Position) -> QuoridorResult<(usize, [usize; 2])>
+    {
+        let no_such = |why: &str| Err(QuoridorError::AnalysisError(format!("No such position: {}", why)));
+        let (p0, p1) = (position.pawns[0], position.pawns[1]);
+        if !self.board.inbounds(p0) || !self.board.inbounds(p1) || p0 == p1 {
+            return no_such("pawns must be on different squares of the board")
+        }
+        if position.turn > 1 {
+            return no_such("the turn must be seat 0 or 1")
+        }
+
+        let mut layout = 0u64;
+        for wall in &position.walls {
+            match self.board.slot(wall) {
+                Some(slot) if layout & self.board.collisions[slot] == 0 => layout |= 1 << slot,
+                Some(_) => return no_such("walls collide"),
+                None => return no_such("a wall is off the board"),
+            }
+        }
+        let placed = position.walls.len() + position.in_hand[0] as usize + position.in_hand[1] as usize;
+        if position.in_hand.iter().any(|&h| h > self.walls) || placed != 2 * self.walls as usize {
+            return no_such("walls in hand and on the board don't add up")
+        }
+        Ok((self.layout_index[&layout], [self.board.square(p0), self.board.square(p1)]))
+    }
+
+    /// Value of `position` for the player to move
+    pub fn outcome(&self, position: &Position) -> QuoridorResult<Outcome>
+    {
+        let (l, squares) = try!(self.locate(position));
+        let i = self.index(l, position.in_hand[0] as usize, squares[0], squares[1], position.turn as usize);
+        Ok(tablebase::decode(self.outcomes[i]))
+    }
+
+    /// Every legal turn out of `position`, with the position it leads
+    /// to.  There are none once somebody has reached their goal.
+    pub fn turns(&self, position: &Position) -> QuoridorResult<Vec<(Turn, Position)>>
+    {
+        let (l, squares) = try!(self.locate(position));
+        let (seat, pawns) = (position.turn as usize, position.pawns);
+        if self.board.is_goal(0, pawns[0]) || self.board.is_goal(1, pawns[1]) {
+            return Ok(vec![])
+        }
+
+        let mut turns = vec![];
+        for q in self.board.valid_moves(self.layouts[l], pawns[seat], pawns[1 - seat]) {
+            let mut next = position.clone();
+            next.pawns[seat] = q;
+            next.turn = 1 - position.turn;
+            turns.push((Turn::between(pawns[seat], q).unwrap(), next));
+        }
+        if position.in_hand[seat] > 0 {
+            for (wall, _) in self.placements(l, squares) {
+                let mut next = position.clone();
+                next.walls.push(wall);
+                next.in_hand[seat] -= 1;
+                next.turn = 1 - position.turn;
+                turns.push((Turn::PlaceWall(wall), next));
+            }
+        }
+        Ok(turns)
+    }
+
+    /// The turn the player to move should take, or None if they have
+    /// no legal turn
+    pub fn best_turn(&self, position: &Position) -> QuoridorResult<Option<Turn>>
+    {
+        let value = try!(self.outcome(position));
+        let mut best: Option<(Turn, Outcome)> = None;
+        for (turn, next) in try!(self.turns(position)) {
+            let next = try!(self.outcome(&next));
+            let better = match (value, next, best) {
+                (Outcome::Win(_), Outcome::Loss(_), None) => true,
+                (Outcome::Win(_), Outcome::Loss(d), Some((_, Outcome::Loss(e)))) => d < e,
+                (Outcome::Loss(_), Outcome::Win(_), None) => true,
+                (Outcome::Loss(_), Outcome::Win(d), Some((_, Outcome::Win(e)))) => d > e,
+                (Outcome::Draw, Outcome::Draw, None) => true,
+                _ => false,
+            };
+            if better {
+                best = Some((turn, next));
+            }
+        }
+        Ok(best.map(|(turn, _)| turn))
+    }
+}
+
+/// Write a small tablebase in the format described above
+pub fn write<W: Write>(tablebase: &SmallTablebase, w: &mut W) -> QuoridorResult<()>
+{
+    let layouts = tablebase.layouts.len() as u32;
+    try!(w.write_all(MAGIC));
+    try!(w.write_all(&[tablebase.size() as u8, tablebase.walls]));
+    try!(w.write_all(&[layouts as u8, (layouts >> 8) as u8, (layouts >> 16) as u8, (layouts >> 24) as u8]));
+    for &v in &tablebase.outcomes {
+        try!(w.write_all(&[v as u8, (v >> 8) as u8]));
+    }
+    Ok(())
+}
+
+/// Read a small tablebase written by `write`
+pub fn read<R: Read>(r: &mut R) -> QuoridorResult<SmallTablebase>
+{
+    let mut header = [0; 10];
+    try!(r.read_exact(&mut header));
+    if &header[..4] != MAGIC {
+        return Err(QuoridorError::AnalysisError("Not a small tablebase file".into()))
+    }
+
+    let mut t = try!(SmallTablebase::unsolved(header[4] as usize, header[5], MAX_POSITIONS));
+    let layouts = header[6] as usize | (header[7] as usize) << 8 | (header[8] as usize) << 16 | (header[9] as usize) << 24;
+    if layouts != t.layouts.len() {
+        return Err(QuoridorError::ParseError(format!("Expected {} wall layouts, got {}", t.layouts.len(), layouts)))
+    }
+
+    let mut data = vec![0; t.outcomes.len() * 2];
+    try!(r.read_exact(&mut data));
+    t.outcomes = data.chunks(2).map(|b| b[0] as u16 | (b[1] as u16) << 8).collect();
+    Ok(t)
+}
+
+/// Read the tablebase of a board from `dir`, or solve it and write it
+/// there
+pub fn load_or_generate(dir: Option<&PathBuf>, size: usize, walls: u8, limit: usize) -> QuoridorResult<SmallTablebase>
+{
+    let path = match dir {
+        Some(dir) => dir.join(file_name(size, walls)),
+        None => return SmallTablebase::generate(size, walls, limit),
+    };
+
+    if path.exists() {
+        return read(&mut BufReader::new(try!(File::open(&path))));
+    }
+
+    // Written whole and then renamed, as tablebase files are
+    let tablebase = try!(SmallTablebase::generate(size, walls, limit));
+    try!(fs::create_dir_all(path.parent().unwrap()));
+    let tmp = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(try!(File::create(&tmp)));
+        try!(write(&tablebase, &mut w));
+        try!(w.flush());
+        try!(w.get_ref().sync_all());
+    }
+    try!(fs::rename(&tmp, &path));
+    Ok(tablebase)
+}
+
+/// File name of the tablebase of a board
+fn file_name(size: usize, walls: u8) -> String
+{
+    format!("small-{}x{}-{}.qsb", size, size, walls)
+}
+
+/// Most positions of a small tablebase built for the server
+pub const SERVER_POSITIONS: usize = 4_000_000;
+
+/// Small tablebases built on demand and optionally cached in a
+/// directory
+pub struct SmallTablebases {
+    dir: Option<PathBuf>,
+    /// Most positions of a tablebase built on demand
+    pub limit: usize,
+    loaded: HashMap<(usize, u8), Arc<SmallTablebase>>,
+    building: HashSet<(usize, u8)>,
+}
+
+impl SmallTablebases {
+    /// Create a collection, reading and writing tablebase files in
+    /// `dir` if given
+    pub fn new(dir: Option<PathBuf>) -> SmallTablebases
+    {
+        SmallTablebases {
+            dir: dir,
+            limit: SERVER_POSITIONS,
+            loaded: HashMap::new(),
+            building: HashSet::new(),
+        }
+    }
+}
+
+/// Return the tablebase of a board if it is in memory, or else start
+/// building it on a thread of its own and return None, as
+/// `tablebase::get_or_build`.  Fails straight away if the board is
+/// too big to solve and hasn't already been solved into the directory.
+pub fn get_or_build(tablebases: &Arc<Mutex<SmallTablebases>>, size: usize, walls: u8) -> QuoridorResult<Option<Arc<SmallTablebase>>>
+{
+    let key = (size, walls);
+    let (dir, limit) = {
+        let mut t = tablebases.lock().unwrap();
+        if let Some(tablebase) = t.loaded.get(&key) {
+            return Ok(Some(tablebase.clone()))
+        }
+        // Checks the size before spending a thread on it
+        if !t.dir.as_ref().map_or(false, |dir| dir.join(file_name(size, walls)).exists()) {
+            try!(SmallTablebase::unsolved(size, walls, t.limit));
+        }
+        if !t.building.insert(key) {
+            return Ok(None)
+        }
+        (t.dir.clone(), t.limit)
+    };
+
+    let tablebases = tablebases.clone();
+    thread::spawn(move || {
+        let tablebase = load_or_generate(dir.as_ref(), size, walls, limit);
+        let mut t = tablebases.lock().unwrap();
+        t.building.remove(&key);
+        match tablebase {
+            Ok(tablebase) => {
+                t.loaded.insert(key, Arc::new(tablebase));
+            },
+            Err(e) => println!("Unable to build a small tablebase: {}", e),
+        }
+    });
+    Ok(None)
+}
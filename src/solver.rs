@@ -0,0 +1,310 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Exact solver for pawn races once every wall has been placed
+//!
+//! When no player has a wall left in hand the board can no longer
+//! change, so the rest of the game is a race between two pawns
+//! (including jumps and blocking in corridors).  There are few enough
+//! such positions to solve all of them by retrograde analysis, which
+//! makes the solver usable as a perfect-knowledge oracle.
+
+use board::{Point, Wall};
+use constants::N;
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+const SQUARES: usize = (N * N) as usize;
+
+/// Number of positions (pawn placements and player to move) per board
+pub const POSITIONS: usize = SQUARES * SQUARES * 2;
+
+/// Game-theoretic value of a position for the player to move
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Outcome {
+    /// The player to move wins in this many plies
+    Win(u32),
+    /// The player to move loses in this many plies
+    Loss(u32),
+    /// Neither player can force a win
+    Draw,
+}
+
+/// Solved values of every pawn placement for one set of walls
+pub struct EndgameSolver {
+    walls: HashSet<Wall>,
+    outcomes: Vec<Outcome>,
+}
+
+impl Outcome {
+    /// Return the outcome as JSON
+    pub fn to_json(&self) -> Json {
+        let mut d = BTreeMap::new();
+        let (result, distance) = match *self {
+            Outcome::Win(d) => ("win", Some(d)),
+            Outcome::Loss(d) => ("loss", Some(d)),
+            Outcome::Draw => ("draw", None),
+        };
+        d.insert("result".to_string(), result.to_json());
+        d.insert("distance".to_string(), distance.to_json());
+        Json::Object(d)
+    }
+}
+
+fn square(p: Point) -> usize
+{
+    (p.y * N + p.x) as usize
+}
+
+fn square_point(i: usize) -> Point
+{
+    point!(i as i32 % N, i as i32 / N)
+}
+
+fn index(p0: Point, p1: Point, turn: u8) -> usize
+{
+    (square(p0) * SQUARES + square(p1)) * 2 + turn as usize
+}
+
+impl EndgameSolver {
+    /// Check that `game` can be solved: a two player game whose
+    /// players have placed all their walls
+    pub fn check(game: &Game) -> QuoridorResult<()>
+    {
+        if game.players.len() != 2 {
+            return Err(QuoridorError::AnalysisError("Only two player games can be solved".into()))
+        }
+        if game.players.values().any(|p| p.walls > 0) {
+            return Err(QuoridorError::AnalysisError("Players still have walls to place".into()))
+        }
+        Ok(())
+    }
+
+    /// Solve every position reachable on the board of `game`
+    ///
+    /// Fails unless `check` passes.
+    pub fn new(game: &Game) -> QuoridorResult<EndgameSolver>
+    {
+        try!(EndgameSolver::check(game));
+
+        let mut names = [String::new(), String::new()];
+        for (name, player) in game.players.iter() {
+            names[player.id as usize] = name.clone();
+        }
+
+        // Generate the moves out of every position, resolving the
+        // ones where somebody has already reached their goal
+        let n = POSITIONS;
+        let mut scratch = game.clone();
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; n];
+        let mut outcomes: Vec<Option<Outcome>> = vec![None; n];
+        let mut queue = VecDeque::new();
+
+        for a in 0..SQUARES {
+            for b in 0..SQUARES {
+                if a == b {
+                    continue;
+                }
+                let (p0, p1) = (square_point(a), square_point(b));
+                scratch.players.get_mut(&names[0]).unwrap().p = p0;
+                scratch.players.get_mut(&names[1]).unwrap().p = p1;
+                let won = [scratch.players[&names[0]].has_won(), scratch.players[&names[1]].has_won()];
+
+                for turn in 0..2 {
+                    let i = index(p0, p1, turn as u8);
+                    if won[1 - turn] {
+                        outcomes[i] = Some(Outcome::Loss(0));
+                        queue.push_back(i);
+                    } else if won[turn] {
+                        outcomes[i] = Some(Outcome::Win(0));
+                        queue.push_back(i);
+                    } else if turn == 0 {
+                        successors[i] = scratch.valid_moves(p0).into_iter().map(|q| index(q, p1, 1)).collect();
+                    } else {
+                        successors[i] = scratch.valid_moves(p1).into_iter().map(|q| index(p0, q, 0)).collect();
+                    }
+                }
+            }
+        }
+
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; n];
+        for (i, next) in successors.iter().enumerate() {
+            for &j in next {
+                predecessors[j].push(i);
+            }
+        }
+
+        // Work backwards from the finished games.  Positions come off
+        // the queue in order of distance, so a win is recorded at its
+        // shortest distance and a loss at its longest.
+        let mut remaining: Vec<usize> = successors.iter().map(|next| next.len()).collect();
+        while let Some(j) = queue.pop_front() {
+            match outcomes[j] {
+                Some(Outcome::Loss(d)) => {
+                    for &i in &predecessors[j] {
+                        if outcomes[i].is_none() {
+                            outcomes[i] = Some(Outcome::Win(d + 1));
+                            queue.push_back(i);
+                        }
+                    }
+                },
+                Some(Outcome::Win(d)) => {
+                    for &i in &predecessors[j] {
+                        if outcomes[i].is_none() {
+                            remaining[i] -= 1;
+                            if remaining[i] == 0 {
+                                outcomes[i] = Some(Outcome::Loss(d + 1));
+                                queue.push_back(i);
+                            }
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        // Anything left over can be shuffled around forever (or has
+        // no legal move at all), so nobody can force a win
+        Ok(EndgameSolver {
+            walls: game.walls.clone(),
+            outcomes: outcomes.into_iter().map(|o| o.unwrap_or(Outcome::Draw)).collect(),
+        })
+    }
+
+    /// Rebuild a solver from previously computed outcomes, indexed the
+    /// same way as `outcomes()`
+    pub fn from_outcomes(walls: HashSet<Wall>, outcomes: Vec<Outcome>) -> QuoridorResult<EndgameSolver>
+    {
+        if outcomes.len() != POSITIONS {
+            return Err(QuoridorError::AnalysisError(format!("Expected {} outcomes, got {}", POSITIONS, outcomes.len())))
+        }
+        Ok(EndgameSolver {
+            walls: walls,
+            outcomes: outcomes,
+        })
+    }
+
+    /// The walls of the solved board
+    pub fn walls(&self) -> &HashSet<Wall>
+    {
+        &self.walls
+    }
+
+    /// Every solved value, ordered by player 0's square, then player
+    /// 1's square, then the player to move
+    pub fn outcomes(&self) -> &[Outcome]
+    {
+        &self.outcomes
+    }
+
+    /// Value of the position with player 0 at `p0`, player 1 at `p1`
+    /// and player `turn` to move
+    pub fn outcome(&self, p0: Point, p1: Point, turn: u8) -> QuoridorResult<Outcome>
+    {
+        if !p0.inbounds() || !p1.inbounds() || p0 == p1 || turn > 1 {
+            return Err(QuoridorError::AnalysisError(format!("No such position {:?} {:?} {}", p0, p1, turn)))
+        }
+        Ok(self.outcomes[index(p0, p1, turn)])
+    }
+
+    /// Value of the current position of `game` for the player to move
+    pub fn probe(&self, game: &Game) -> QuoridorResult<Outcome>
+    {
+        let (p0, p1, turn) = try!(self.position(game));
+        self.outcome(p0, p1, turn)
+    }
+
+    /// The square the player to move should move to, or None if they
+    /// have no legal move
+    pub fn best_move(&self, game: &Game) -> QuoridorResult<Option<Point>>
+    {
+        let (p0, p1, turn) = try!(self.position(game));
+        let value = try!(self.outcome(p0, p1, turn));
+        let mover = if turn == 0 { p0 } else { p1 };
+
+        let mut best: Option<(Point, Outcome)> = None;
+        for q in game.valid_moves(mover) {
+            let next = match turn {
+                0 => self.outcomes[index(q, p1, 1)],
+                _ => self.outcomes[index(p0, q, 0)],
+            };
+            let better = match (value, next, best) {
+                (Outcome::Win(_), Outcome::Loss(_), None) => true,
+                (Outcome::Win(_), Outcome::Loss(d), Some((_, Outcome::Loss(e)))) => d < e,
+                (Outcome::Loss(_), Outcome::Win(_), None) => true,
+                (Outcome::Loss(_), Outcome::Win(d), Some((_, Outcome::Win(e)))) => d > e,
+                (Outcome::Draw, Outcome::Draw, None) => true,
+                _ => false,
+            };
+            if better {
+                best = Some((q, next));
+            }
+        }
+        Ok(best.map(|(q, _)| q))
+    }
+
+    /// The sequence of squares moved to under optimal play, ending when
+    /// somebody reaches their goal.  Drawn lines are cut off after a
+    /// full lap of the board.
+    pub fn principal_variation(&self, game: &Game) -> QuoridorResult<Vec<Point>>
+    {
+        let mut game = game.clone();
+        let mut line = vec![];
+        while line.len() < SQUARES && game.winner().is_none() {
+            let (_, _, turn) = try!(self.position(&game));
+            match try!(self.best_move(&game)) {
+                Some(q) => {
+                    for player in game.players.values_mut() {
+                        if player.id == turn {
+                            player.p = q;
+                        }
+                    }
+                    game.state = GameState::Started(1 - turn);
+                    line.push(q);
+                },
+                None => break,
+            }
+        }
+        Ok(line)
+    }
+
+    /// Extract the pawns and player to move from a game played on the
+    /// solved board
+    fn position(&self, game: &Game) -> QuoridorResult<(Point, Point, u8)>
+    {
+        if game.walls != self.walls {
+            return Err(QuoridorError::AnalysisError("Position has different walls than the solved board".into()))
+        }
+        let turn = match game.state {
+            GameState::Started(turn) => turn,
+            _ => return Err(QuoridorError::AnalysisError("Game is not in progress".into())),
+        };
+        let a = game.players.values().find(|p| p.id == 0);
+        let b = game.players.values().find(|p| p.id == 1);
+        match (a, b) {
+            (Some(a), Some(b)) if game.players.len() == 2 => Ok((a.p, b.p, turn)),
+            _ => Err(QuoridorError::PlayerNotFound),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Spectators of a game
+//!
+//! Spectators only ever see what any viewer may see: the public board
+//! state and the event feed, never player keys.  A game can delay what
+//! spectators see, so that players in a streamed game can't learn
+//! anything from watching the stream.
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub struct Spectators {
+    /// Open subscriptions of each named spectator
+    viewers: BTreeMap<String, usize>,
+    /// Subscriptions without a name
+    anonymous: usize,
+    /// Public state of the game after each change
+    snapshots: Vec<(Instant, Json)>,
+}
+
+impl Spectators {
+    /// Create a list of spectators for a game whose public state is
+    /// `state`
+    pub fn new(state: Json) -> Spectators
+    {
+        Spectators {
+            viewers: BTreeMap::new(),
+            anonymous: 0,
+            snapshots: vec![(Instant::now(), state)],
+        }
+    }
+
+    /// Subscribe a spectator, by name if given
+    pub fn join(&mut self, name: Option<&str>)
+    {
+        match name {
+            Some(name) => *self.viewers.entry(name.to_string()).or_insert(0) += 1,
+            None => self.anonymous += 1,
+        }
+    }
+
+    /// End a subscription made by `join`
+    pub fn leave(&mut self, name: Option<&str>)
+    {
+        match name {
+            Some(name) => {
+                let remaining = match self.viewers.get_mut(name) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count
+                    },
+                    None => return,
+                };
+                if remaining == 0 {
+                    self.viewers.remove(name);
+                }
+            },
+            None => self.anonymous = self.anonymous.saturating_sub(1),
+        }
+    }
+
+    /// Names of the spectators watching
+    pub fn names(&self) -> Vec<String>
+    {
+        self.viewers.keys().cloned().collect()
+    }
+
+    /// Number of spectators watching, counting each name once
+    pub fn count(&self) -> usize
+    {
+        self.viewers.len() + self.anonymous
+    }
+
+    /// Remember the public state after a change at `now`, forgetting
+    /// states spectators kept `delay` behind will never see again
+    pub fn record(&mut self, state: Json, delay: Duration, now: Instant)
+    {
+        self.snapshots.push((now, state));
+        let shown = self.snapshots.iter().rposition(|&(time, _)| time + delay <= now).unwrap_or(0);
+        self.snapshots.drain(..shown);
+    }
+
+    /// Number of states remembered
+    pub fn snapshots(&self) -> usize
+    {
+        self.snapshots.len()
+    }
+
+    /// Public state of the game as it was `delay` ago
+    pub fn state(&self, delay: Duration, now: Instant) -> Json
+    {
+        self.snapshots.iter()
+            .rev()
+            .find(|&&(time, _)| time + delay <= now)
+            .unwrap_or(&self.snapshots[0])
+            .1.clone()
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("count".to_string(), self.count().to_json());
+        d.insert("viewers".to_string(), self.names().to_json());
+        Json::Object(d)
+    }
+}
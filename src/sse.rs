@@ -0,0 +1,157 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Server-Sent Events stream of game events
+//!
+//! The same events as the WebSocket channel, written as a
+//! `text/event-stream` with each sequence number as the event `id`, so
+//! browsers resume with `Last-Event-ID` after a reconnect.  Spectator
+//! streams hold every event back by the game's spectator delay.
+//!
+//! A stream ends once the game is over.  Each open stream holds one of
+//! the server's workers, so a `StreamLimit` caps how many are open at
+//! once.
+
+use events::{Event, GameEvent};
+use iron::response::WriteBody;
+use registry::GameEntry;
+use std::io::{self, Write};
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Response body streaming the events of a game after `seq`, each
+/// sent `delay` after it happened
+pub struct EventStream {
+    pub entry: Arc<GameEntry>,
+    pub seq: u64,
+    pub delay: Duration,
+    pub slot: StreamSlot,
+}
+
+/// How many event streams are open, and how many may be
+pub struct StreamLimit {
+    open: AtomicUsize,
+    pub max: usize,
+}
+
+/// One open stream of a `StreamLimit`, given back when dropped
+pub struct StreamSlot {
+    limit: Arc<StreamLimit>,
+}
+
+impl StreamLimit {
+    pub fn new(max: usize) -> Arc<StreamLimit>
+    {
+        Arc::new(StreamLimit { open: AtomicUsize::new(0), max: max })
+    }
+
+    /// A slot for a new stream, unless `max` are open already
+    pub fn acquire(limit: &Arc<StreamLimit>) -> Option<StreamSlot>
+    {
+        let mut open = limit.open.load(Ordering::SeqCst);
+        while open < limit.max {
+            match limit.open.compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Some(StreamSlot { limit: limit.clone() }),
+                Err(current) => open = current,
+            }
+        }
+        None
+    }
+
+    pub fn open(&self) -> usize
+    {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self)
+    {
+        self.limit.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Event stream of a spectator, who is counted as watching the game
+/// for as long as the stream is open
+pub struct SpectatorStream {
+    pub stream: EventStream,
+    pub name: Option<String>,
+}
+
+/// Format an event as an SSE message
+pub fn format_event(event: &Event) -> String
+{
+    format!("id: {}\nevent: {}\ndata: {}\n\n", event.seq, event.event.name(), event.to_json())
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut Write) -> io::Result<()>
+    {
+        loop {
+            let events = match self.entry.wait_for_events(self.seq, Duration::from_secs(15)) {
+                Some(events) => events,
+                None => return Ok(()),
+            };
+
+            // Hold back events until they are old enough to send
+            let now = Instant::now();
+            let ready = events.iter().take_while(|e| e.time + self.delay <= now).count();
+            if ready == 0 && !events.is_empty() {
+                let wait = events[0].time + self.delay - now;
+                thread::sleep(cmp::min(wait, Duration::from_secs(15)));
+                continue
+            }
+
+            // A comment when there is nothing to send finds out if
+            // the client has gone away
+            if events.is_empty() {
+                try!(res.write_all(b": keepalive\n\n"));
+            }
+            let mut over = false;
+            for event in events.into_iter().take(ready) {
+                try!(res.write_all(format_event(&event).as_bytes()));
+                self.seq = event.seq;
+                if let GameEvent::GameOver { .. } = event.event {
+                    over = true;
+                }
+            }
+            try!(res.flush());
+            if over {
+                return Ok(())
+            }
+        }
+    }
+}
+
+impl WriteBody for SpectatorStream {
+    fn write_body(&mut self, res: &mut Write) -> io::Result<()>
+    {
+        let name = self.name.as_ref().map(|n| &n[..]);
+        self.stream.entry.spectators.lock().unwrap().join(name);
+        let ret = self.stream.write_body(res);
+        self.stream.entry.spectators.lock().unwrap().leave(name);
+        ret
+    }
+}
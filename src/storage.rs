@@ -0,0 +1,407 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Durable storage of games
+//!
+//! `Storage` keeps every game played with its players, turns and
+//! result, so that nothing is lost when the server stops and finished
+//! games can be looked up later.  `SqliteStorage` keeps them in an
+//! embedded SQLite database file; other databases can be added behind
+//! the same trait.
+//!
+//! Games are stored under ids of their own, since the ids of the
+//! registry start again from 1 whenever the server restarts.  A
+//! `Recorder` saves the changes to the games of a registry as they
+//! happen.
+
+use accounts::now_secs;
+use domain::DomainEvent;
+use errors::{QuoridorError, QuoridorResult};
+use player::Player;
+use quoridor::{Game, GameOptions};
+use record::GameRecord;
+use result::{GameResult, Reason};
+use rusqlite::{self, Connection, OptionalExtension};
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use turn::Turn;
+
+/// A stored game, without its turns
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredGame {
+    pub id: i64,
+    /// Id of the game in the registry when it was played
+    pub name: String,
+    pub players: Vec<String>,
+    pub turns: usize,
+    pub winner: Option<u8>,
+    /// Why the game ended, once it has
+    pub reason: Option<Reason>,
+    /// Unix times the game started and ended
+    pub created: u64,
+    pub ended: Option<u64>,
+}
+
+pub trait Storage: Send {
+    /// Store a new game, returning its id
+    fn create_game(&mut self, name: &str, options: &GameOptions, created: u64) -> QuoridorResult<i64>;
+
+    /// Store the player in `seat` of a game, replacing whoever was
+    /// there before
+    fn add_player(&mut self, game: i64, seat: u8, name: &str, account: Option<u64>) -> QuoridorResult<()>;
+
+    /// Store turn number `ply` of a game, with the time the mover had
+    /// left if the game is timed
+    fn add_turn(&mut self, game: i64, ply: usize, seat: u8, turn: &Turn, clock: Option<u64>, time: u64)
+                -> QuoridorResult<()>;
+
+    /// Remove the turns of a game from ply `from` on, when they are
+    /// taken back
+    fn remove_turns(&mut self, game: i64, from: usize) -> QuoridorResult<()>;
+
+    fn add_result(&mut self, game: i64, result: &GameResult, ended: u64) -> QuoridorResult<()>;
+
+    /// Stored games, latest first, optionally only those `player`
+    /// played in
+    fn games(&self, player: Option<&str>, limit: usize) -> QuoridorResult<Vec<StoredGame>>;
+
+    /// Record of a stored game
+    fn record(&self, game: i64) -> QuoridorResult<Option<GameRecord>>;
+
+    /// The latest game stored under registry id `name` that has no
+    /// result yet
+    fn unfinished(&self, name: &str) -> QuoridorResult<Option<StoredGame>>;
+}
+
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+/// Saves the changes to the games of a registry
+pub struct Recorder {
+    pub storage: Box<Storage>,
+    ids: HashMap<String, i64>,
+}
+
+impl From<rusqlite::Error> for QuoridorError {
+    fn from(e: rusqlite::Error) -> QuoridorError {
+        QuoridorError::StorageError(e.to_string())
+    }
+}
+
+impl StoredGame {
+    pub fn to_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_json());
+        d.insert("name".to_string(), self.name.to_json());
+        d.insert("players".to_string(), self.players.to_json());
+        d.insert("turns".to_string(), self.turns.to_json());
+        d.insert("winner".to_string(), self.winner.to_json());
+        d.insert("reason".to_string(), self.reason.map(|r| r.name().to_string()).to_json());
+        d.insert("created".to_string(), self.created.to_json());
+        d.insert("ended".to_string(), self.ended.to_json());
+        Json::Object(d)
+    }
+}
+
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS games (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        walls INTEGER NOT NULL,
+        time_control TEXT,
+        options TEXT NOT NULL,
+        created INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS players (
+        game INTEGER NOT NULL REFERENCES games(id),
+        seat INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        account INTEGER,
+        PRIMARY KEY (game, seat)
+    );
+    CREATE INDEX IF NOT EXISTS players_name ON players(name);
+    CREATE TABLE IF NOT EXISTS turns (
+        game INTEGER NOT NULL REFERENCES games(id),
+        ply INTEGER NOT NULL,
+        seat INTEGER NOT NULL,
+        turn TEXT NOT NULL,
+        clock INTEGER,
+        time INTEGER NOT NULL,
+        PRIMARY KEY (game, ply)
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        game INTEGER PRIMARY KEY REFERENCES games(id),
+        winner INTEGER,
+        reason TEXT,
+        ended INTEGER NOT NULL
+    );
+";
+
+impl SqliteStorage {
+    /// Open the database in `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> QuoridorResult<SqliteStorage>
+    {
+        if let Some(dir) = path.as_ref().parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        SqliteStorage::with_connection(try!(Connection::open(path)))
+    }
+
+    /// A database kept in memory, for tests
+    pub fn in_memory() -> QuoridorResult<SqliteStorage>
+    {
+        SqliteStorage::with_connection(try!(Connection::open_in_memory()))
+    }
+
+    fn with_connection(conn: Connection) -> QuoridorResult<SqliteStorage>
+    {
+        try!(conn.execute_batch(SCHEMA));
+        Ok(SqliteStorage { conn: conn })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_game(&mut self, name: &str, options: &GameOptions, created: u64) -> QuoridorResult<i64>
+    {
+        let time_control = options.time_control.as_ref().map(|c| c.to_json().to_string());
+        try!(self.conn.execute(
+            "INSERT INTO games (name, walls, time_control, options, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![name, options.walls, time_control, options.to_json().to_string(), created as i64]));
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn add_player(&mut self, game: i64, seat: u8, name: &str, account: Option<u64>) -> QuoridorResult<()>
+    {
+        try!(self.conn.execute(
+            "INSERT OR REPLACE INTO players (game, seat, name, account) VALUES (?1, ?2, ?3, ?4)",
+            params![game, seat, name, account.map(|a| a as i64)]));
+        Ok(())
+    }
+
+    fn add_turn(&mut self, game: i64, ply: usize, seat: u8, turn: &Turn, clock: Option<u64>, time: u64)
+                -> QuoridorResult<()>
+    {
+        try!(self.conn.execute(
+            "INSERT INTO turns (game, ply, seat, turn, clock, time) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![game, ply as i64, seat, turn.to_json().to_string(), clock.map(|c| c as i64), time as i64]));
+        Ok(())
+    }
+
+    fn remove_turns(&mut self, game: i64, from: usize) -> QuoridorResult<()>
+    {
+        try!(self.conn.execute("DELETE FROM turns WHERE game = ?1 AND ply >= ?2", params![game, from as i64]));
+        Ok(())
+    }
+
+    fn add_result(&mut self, game: i64, result: &GameResult, ended: u64) -> QuoridorResult<()>
+    {
+        try!(self.conn.execute(
+            "INSERT OR REPLACE INTO results (game, winner, reason, ended) VALUES (?1, ?2, ?3, ?4)",
+            params![game, result.winner, result.reason.name(), ended as i64]));
+        Ok(())
+    }
+
+    fn games(&self, player: Option<&str>, limit: usize) -> QuoridorResult<Vec<StoredGame>>
+    {
+        self.select_games(player, None, limit)
+    }
+
+    fn unfinished(&self, name: &str) -> QuoridorResult<Option<StoredGame>>
+    {
+        let id = try!(self.conn.query_row(
+            "SELECT id FROM games WHERE name = ?1 AND id NOT IN (SELECT game FROM results)
+             ORDER BY id DESC LIMIT 1", params![name], |row| row.get::<_, i64>(0)).optional());
+        match id {
+            Some(id) => Ok(try!(self.select_games(None, Some(id), 1)).pop()),
+            None => Ok(None),
+        }
+    }
+
+    fn record(&self, game: i64) -> QuoridorResult<Option<GameRecord>>
+    {
+        let options = match try!(self.conn.query_row("SELECT options FROM games WHERE id = ?1", params![game],
+                                                     |row| row.get::<_, String>(0)).optional()) {
+            None => return Ok(None),
+            Some(options) => {
+                let json = try!(Json::from_str(&options).map_err(|e| QuoridorError::ParseError(e.to_string())));
+                try!(GameOptions::from_json(&json).ok_or_else(|| QuoridorError::ParseError(format!("Invalid options {}", json))))
+            },
+        };
+        let mut stmt = try!(self.conn.prepare("SELECT turn, clock FROM turns WHERE game = ?1 ORDER BY ply"));
+        let rows = try!(stmt.query_map(params![game], |row| {
+            Ok((try!(row.get::<_, String>(0)), try!(row.get::<_, Option<i64>>(1))))
+        }));
+        let (mut turns, mut clocks) = (vec![], vec![]);
+        for row in rows {
+            let (turn, clock) = try!(row);
+            let json = try!(Json::from_str(&turn).map_err(|e| QuoridorError::ParseError(e.to_string())));
+            turns.push(try!(Turn::from_json(&json)));
+            if let Some(clock) = clock {
+                clocks.push(clock as u64);
+            }
+        }
+        let result = try!(self.conn.query_row("SELECT winner, reason FROM results WHERE game = ?1", params![game], |row| {
+            Ok((try!(row.get::<_, Option<u8>>(0)), try!(row.get::<_, Option<String>>(1))))
+        }).optional());
+        Ok(Some(GameRecord {
+            players: try!(self.players(game)),
+            turns: turns,
+            winner: result.as_ref().and_then(|r| r.0),
+            reason: result.and_then(|r| r.1).and_then(|r| Reason::parse(&r)),
+            clocks: clocks,
+            options: options,
+        }))
+    }
+}
+
+impl SqliteStorage {
+    /// Stored games, latest first, optionally only those `player`
+    /// played in or the one with id `id`
+    fn select_games(&self, player: Option<&str>, id: Option<i64>, limit: usize) -> QuoridorResult<Vec<StoredGame>>
+    {
+        let mut stmt = try!(self.conn.prepare(
+            "SELECT g.id, g.name, g.created, r.winner, r.ended,
+                    (SELECT COUNT(*) FROM turns t WHERE t.game = g.id), r.reason
+             FROM games g LEFT JOIN results r ON r.game = g.id
+             WHERE (?1 IS NULL OR EXISTS (SELECT 1 FROM players p WHERE p.game = g.id AND p.name = ?1))
+               AND (?2 IS NULL OR g.id = ?2)
+             ORDER BY g.id DESC LIMIT ?3"));
+        let rows = try!(stmt.query_map(params![player, id, limit as i64], |row| {
+            Ok(StoredGame {
+                id: try!(row.get(0)),
+                name: try!(row.get(1)),
+                players: vec![],
+                turns: try!(row.get::<_, i64>(5)) as usize,
+                winner: try!(row.get(3)),
+                reason: try!(row.get::<_, Option<String>>(6)).and_then(|r| Reason::parse(&r)),
+                created: try!(row.get::<_, i64>(2)) as u64,
+                ended: try!(row.get::<_, Option<i64>>(4)).map(|e| e as u64),
+            })
+        }));
+        let mut games = vec![];
+        for game in rows {
+            let mut game = try!(game);
+            game.players = try!(self.players(game.id));
+            games.push(game);
+        }
+        Ok(games)
+    }
+
+    /// Names of the players of a game, in seat order
+    fn players(&self, game: i64) -> QuoridorResult<Vec<String>>
+    {
+        let mut stmt = try!(self.conn.prepare("SELECT name FROM players WHERE game = ?1 ORDER BY seat"));
+        let rows = try!(stmt.query_map(params![game], |row| row.get(0)));
+        let mut players = vec![];
+        for name in rows {
+            players.push(try!(name));
+        }
+        Ok(players)
+    }
+}
+
+impl Recorder {
+    pub fn new(storage: Box<Storage>) -> Recorder
+    {
+        Recorder { storage: storage, ids: HashMap::new() }
+    }
+
+    /// Save the change to the game with registry id `id` since it had
+    /// `len` domain events: the players who joined or swapped seats,
+    /// the turns taken and the result once it is over.  A game is first
+    /// stored when somebody joins it.
+    pub fn record(&mut self, id: &str, len: usize, after: &Game) -> QuoridorResult<()>
+    {
+        // The seats of the players already stored, and how many turns
+        // are
+        let (game, seats, turns_stored): (i64, HashMap<String, u8>, usize) = match self.ids.get(id) {
+            Some(&game) => {
+                let since = &after.stream.events()[len..];
+                let moved = |p: &&Player| since.iter().any(|e| match *e {
+                    DomainEvent::PlayerJoined { ref name, .. } => *name == p.name,
+                    DomainEvent::SidesSwapped { seat, with } => p.id == seat || p.id == with,
+                    _ => false,
+                });
+                let seats = after.players.values().filter(|p| !moved(p)).map(|p| (p.name.clone(), p.id)).collect();
+                (game, seats, after.history_len(len))
+            },
+            None if after.players.is_empty() => return Ok(()),
+            None => {
+                let (game, seats, turns) = match try!(self.resumed(id, after)) {
+                    Some(stored) => {
+                        let seats = stored.players.iter().enumerate().map(|(seat, name)| (name.clone(), seat as u8));
+                        (stored.id, seats.collect(), stored.turns)
+                    },
+                    None => (try!(self.storage.create_game(id, &after.options, now_secs())), HashMap::new(), 0),
+                };
+                self.ids.insert(id.to_string(), game);
+                (game, seats, turns)
+            },
+        };
+
+        let mut players: Vec<_> = after.players.values().filter(|p| seats.get(&p.name) != Some(&p.id)).collect();
+        players.sort_by_key(|p| p.id);
+        for p in players {
+            try!(self.storage.add_player(game, p.id, &p.name, p.account));
+        }
+
+        if after.history.len() < turns_stored {
+            try!(self.storage.remove_turns(game, after.history.len()));
+        }
+        let (seats, first) = (after.players.len(), after.first_seat() as usize);
+        let clocks = after.clock.as_ref().map(|c| &c.log);
+        for ply in cmp::min(turns_stored, after.history.len())..after.history.len() {
+            let clock = clocks.and_then(|log| log.get(ply).cloned());
+            try!(self.storage.add_turn(game, ply, ((first + ply) % seats) as u8, &after.history[ply], clock, now_secs()));
+        }
+
+        if after.stream.ended_after(len) {
+            let result = after.result.unwrap_or(GameResult { winner: after.winner(), reason: Reason::Goal });
+            try!(self.storage.add_result(game, &result, now_secs()));
+            self.ids.remove(id);
+        }
+        Ok(())
+    }
+
+    /// The stored game that `game`, seen for the first time, carries
+    /// on, such as a game recovered after a restart
+    fn resumed(&self, id: &str, game: &Game) -> QuoridorResult<Option<StoredGame>>
+    {
+        let stored = match try!(self.storage.unfinished(id)) {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let mut players: Vec<_> = game.players.values().collect();
+        players.sort_by_key(|p| p.id);
+        let same_players = stored.players.len() <= players.len()
+            && stored.players.iter().zip(players.iter()).all(|(a, b)| *a == b.name);
+        match same_players && stored.turns <= game.history.len() {
+            true => Ok(Some(stored)),
+            false => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Board symmetries
+//!
+//! Reflecting or rotating the board, and moving every player to the
+//! seat whose goal edge their own goal edge lands on, gives an
+//! equivalent game.  Two player games have four such symmetries
+//! (including the identity), since the seats must stay on the top and
+//! bottom edges; four player games have all eight.
+
+use board::{Direction, Orientation, Point, Wall};
+use constants::N;
+use domain::DomainEvent;
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameState};
+use result::GameResult;
+use setup::Setup;
+use turn::Turn;
+
+/// A symmetry of the square board: an optional transposition (swapping
+/// x and y) followed by optional reflections of each axis
+#[derive(Hash, Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Symmetry {
+    pub transpose: bool,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+pub const IDENTITY: Symmetry = Symmetry { transpose: false, flip_x: false, flip_y: false };
+
+/// Left-right mirror image
+pub const MIRROR: Symmetry = Symmetry { transpose: false, flip_x: true, flip_y: false };
+
+/// Half turn, which swaps the two seats of a two player game
+pub const ROTATE_180: Symmetry = Symmetry { transpose: false, flip_x: true, flip_y: true };
+
+impl Symmetry {
+    /// Every symmetry of the board, starting with the identity
+    pub fn all() -> Vec<Symmetry>
+    {
+        let mut all = vec![];
+        for &transpose in &[false, true] {
+            for &flip_y in &[false, true] {
+                for &flip_x in &[false, true] {
+                    all.push(Symmetry { transpose: transpose, flip_x: flip_x, flip_y: flip_y });
+                }
+            }
+        }
+        all
+    }
+
+    /// The symmetry that undoes this one
+    pub fn inverse(&self) -> Symmetry
+    {
+        match self.transpose {
+            true => Symmetry { transpose: true, flip_x: self.flip_y, flip_y: self.flip_x },
+            false => *self,
+        }
+    }
+
+    /// Transform a square
+    pub fn point(&self, p: Point) -> Point
+    {
+        let (x, y) = if self.transpose { (p.y, p.x) } else { (p.x, p.y) };
+        point!(if self.flip_x { N - 1 - x } else { x },
+               if self.flip_y { N - 1 - y } else { y })
+    }
+
+    /// Transform a wall
+    pub fn wall(&self, w: Wall) -> Wall
+    {
+        let (x, y, orientation) = match self.transpose {
+            true => (w.y, w.x, match w.orientation {
+                Orientation::Horizontal => Orientation::Vertical,
+                Orientation::Vertical => Orientation::Horizontal,
+            }),
+            false => (w.x, w.y, w.orientation),
+        };
+        Wall {
+            x: if self.flip_x { N - x } else { x },
+            y: if self.flip_y { N - y } else { y },
+            orientation: orientation,
+        }
+    }
+
+    /// Transform a direction
+    pub fn direction(&self, d: Direction) -> Direction
+    {
+        let (dx, dy) = match d {
+            Direction::North => (0, -1),
+            Direction::East => (1, 0),
+            Direction::South => (0, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthEast => (1, -1),
+            Direction::SouthEast => (1, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::NorthWest => (-1, -1),
+            Direction::Invalid => return Direction::Invalid,
+        };
+        let (dx, dy) = if self.transpose { (dy, dx) } else { (dx, dy) };
+        let (dx, dy) = (if self.flip_x { -dx } else { dx }, if self.flip_y { -dy } else { dy });
+        match (dx, dy) {
+            (0, -1) => Direction::North,
+            (1, 0) => Direction::East,
+            (0, 1) => Direction::South,
+            (-1, 0) => Direction::West,
+            (1, -1) => Direction::NorthEast,
+            (1, 1) => Direction::SouthEast,
+            (-1, 1) => Direction::SouthWest,
+            _ => Direction::NorthWest,
+        }
+    }
+
+    /// Transform a turn
+    pub fn turn(&self, t: Turn) -> Turn
+    {
+        match t {
+            Turn::Move(d) => Turn::Move(self.direction(d)),
+            Turn::Jump(d) => Turn::Jump(self.direction(d)),
+            Turn::PlaceWall(w) => Turn::PlaceWall(self.wall(w)),
+        }
+    }
+
+    /// The seat whose goal edge the goal edge of `seat` is moved onto
+    pub fn seat(&self, seat: u8) -> u8
+    {
+        // Goal edges as (along the x axis, at the far end)
+        let (x_axis, far) = match seat {
+            0 => (false, true),
+            1 => (false, false),
+            2 => (true, true),
+            _ => (true, false),
+        };
+        let x_axis = x_axis != self.transpose;
+        let far = far != (if x_axis { self.flip_x } else { self.flip_y });
+        match (x_axis, far) {
+            (false, true) => 0,
+            (false, false) => 1,
+            (true, true) => 2,
+            (true, false) => 3,
+        }
+    }
+
+    /// Transform the result of a game
+    pub fn result(&self, result: GameResult) -> GameResult
+    {
+        GameResult { winner: result.winner.map(|s| self.seat(s)), reason: result.reason }
+    }
+
+    /// Transform a domain event
+    pub fn event(&self, event: &DomainEvent) -> DomainEvent
+    {
+        match *event {
+            DomainEvent::PlayerJoined { seat, ref name, ref key_hash, start, walls } => DomainEvent::PlayerJoined {
+                seat: self.seat(seat),
+                name: name.clone(),
+                key_hash: key_hash.clone(),
+                start: self.point(start),
+                walls: walls,
+            },
+            DomainEvent::GameStarted { first } => DomainEvent::GameStarted { first: self.seat(first) },
+            DomainEvent::PawnMoved { seat, from, to } =>
+                DomainEvent::PawnMoved { seat: self.seat(seat), from: self.point(from), to: self.point(to) },
+            DomainEvent::WallPlaced { seat, wall } =>
+                DomainEvent::WallPlaced { seat: seat.map(|s| self.seat(s)), wall: self.wall(wall) },
+            DomainEvent::TurnEnded { next } => DomainEvent::TurnEnded { next: self.seat(next) },
+            DomainEvent::Resigned { seat } => DomainEvent::Resigned { seat: self.seat(seat) },
+            DomainEvent::TimedOut { seat } => DomainEvent::TimedOut { seat: self.seat(seat) },
+            DomainEvent::DrawOffered { seat } => DomainEvent::DrawOffered { seat: self.seat(seat) },
+            DomainEvent::DrawDeclined { seat } => DomainEvent::DrawDeclined { seat: self.seat(seat) },
+            DomainEvent::TakebackRequested { seat } => DomainEvent::TakebackRequested { seat: self.seat(seat) },
+            DomainEvent::TakebackDeclined { seat } => DomainEvent::TakebackDeclined { seat: self.seat(seat) },
+            DomainEvent::TakenBack { seat, to } => DomainEvent::TakenBack { seat: self.seat(seat), to: to },
+            DomainEvent::SidesSwapped { seat, with } =>
+                DomainEvent::SidesSwapped { seat: self.seat(seat), with: self.seat(with) },
+            DomainEvent::GameEnded { result } => DomainEvent::GameEnded { result: self.result(result) },
+        }
+    }
+
+    /// Transform a setup: its walls, and each seat's walls and starting
+    /// square moved to the seat it becomes
+    pub fn setup(&self, setup: &Setup) -> Setup
+    {
+        let seats = |n: usize| {
+            let mut seats: Vec<_> = (0..n as u8).collect();
+            seats.sort_by_key(|&s| self.seat(s));
+            seats
+        };
+        Setup {
+            walls: setup.walls.as_ref().map(|w| seats(w.len()).iter().map(|&s| w[s as usize]).collect()),
+            starts: setup.starts.as_ref().map(|p| seats(p.len()).iter().map(|&s| self.point(p[s as usize])).collect()),
+            board: setup.board.iter().map(|&w| self.wall(w)).collect(),
+            first: self.seat(setup.first),
+        }
+    }
+
+    /// Can this symmetry be applied to `game` without moving a player
+    /// to a seat that isn't in play
+    pub fn applies_to(&self, game: &Game) -> bool
+    {
+        game.players.values().all(|p| (self.seat(p.id) as usize) < game.players.len())
+    }
+
+    /// Transform a game, including its history and the player to move
+    pub fn game(&self, game: &Game) -> QuoridorResult<Game>
+    {
+        if !self.applies_to(game) {
+            return Err(QuoridorError::AnalysisError(format!("{:?} does not apply to a {} player game", self, game.players.len())))
+        }
+
+        let mut g = game.clone();
+        g.options.setup = game.options.setup.as_ref().map(|s| self.setup(s));
+        g.walls = game.walls.iter().map(|&w| self.wall(w)).collect();
+        g.history = game.history.iter().map(|&t| self.turn(t)).collect();
+
+        // Replaying the transformed events rebuilds the snapshots and
+        // the hashes of the positions reached, for repetitions
+        let events: Vec<_> = game.stream.events().iter().map(|e| self.event(e)).collect();
+        let replayed = Game::replay(g.options.clone(), &events);
+        g.stream = replayed.stream;
+        g.positions = replayed.positions;
+        for p in g.players.values_mut() {
+            p.p = self.point(p.p);
+            p.id = self.seat(p.id);
+        }
+        if let GameState::Started(turn) = game.state {
+            g.state = GameState::Started(self.seat(turn));
+        }
+        g.result = game.result.map(|r| self.result(r));
+        g.draw_offer = game.draw_offer.map(|s| self.seat(s));
+        g.takeback_request = game.takeback_request.map(|s| self.seat(s));
+        Ok(g)
+    }
+}
+
+/// The symmetries that apply to `game`, with the hash of the position
+/// each one produces
+fn hashes(game: &Game) -> Vec<(Symmetry, u64)>
+{
+    Symmetry::all().into_iter()
+        .filter(|s| s.applies_to(game))
+        .map(|s| (s, s.game(game).unwrap().position_hash()))
+        .collect()
+}
+
+/// The canonical form of a position: the equivalent position with the
+/// smallest hash, and the symmetry that produces it
+pub fn canonical(game: &Game) -> (Game, Symmetry)
+{
+    let s = canonical_symmetry(game);
+    (s.game(game).unwrap(), s)
+}
+
+/// The symmetry producing the canonical form of a position
+pub fn canonical_symmetry(game: &Game) -> Symmetry
+{
+    let hashes = hashes(game);
+    let min = hashes.iter().map(|&(_, h)| h).min().unwrap();
+    hashes.into_iter().find(|&(_, h)| h == min).unwrap().0
+}
+
+/// Hash of the canonical form of a position, which is the same for
+/// every equivalent position
+pub fn canonical_hash(game: &Game) -> u64
+{
+    hashes(game).into_iter().map(|(_, h)| h).min().unwrap()
+}
+
+/// Hash of the canonical form of a position, with `turn` as it is
+/// played there.  When several symmetries give the canonical form the
+/// position is symmetric, so the smallest of the equivalent turns is
+/// used.
+pub fn canonical_turn(game: &Game, turn: Turn) -> (u64, Turn)
+{
+    let hashes = hashes(game);
+    let min = hashes.iter().map(|&(_, h)| h).min().unwrap();
+    let turn = hashes.into_iter().filter(|&(_, h)| h == min).map(|(s, _)| s.turn(turn)).min().unwrap();
+    (min, turn)
+}
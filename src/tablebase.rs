@@ -0,0 +1,305 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Endgame tablebases
+//!
+//! A tablebase stores every solved value for one wall layout so that
+//! engines and the server can probe it instead of solving the board
+//! again.  Tablebase files are laid out as:
+//!
+//! * the magic bytes `QTB1`
+//! * the board size and the number of walls, one byte each
+//! * each wall as three bytes: x, y and orientation (0 horizontal,
+//!   1 vertical)
+//! * one little endian u16 per position: the distance shifted left two
+//!   bits, or'd with 0 for a draw, 1 for a win and 2 for a loss
+//!
+//! The board is always the crate's N by N board, so each tablebase
+//! covers the endgames of one wall layout once every wall has been
+//! placed; `smallboard` solves every position of smaller boards,
+//! walls in hand included.  Solving a layout takes a while, so the
+//! server builds tablebases in the background and keeps only the most
+//! recently used in memory.
+
+use board::{Orientation, Wall};
+use constants::N;
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::Game;
+use solver::{EndgameSolver, Outcome, POSITIONS};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const MAGIC: &'static [u8] = b"QTB1";
+
+/// Write a solved board in tablebase format
+pub fn write<W: Write>(solver: &EndgameSolver, w: &mut W) -> QuoridorResult<()>
+{
+    let mut walls: Vec<&Wall> = solver.walls().iter().collect();
+    walls.sort();
+
+    try!(w.write_all(MAGIC));
+    try!(w.write_all(&[N as u8, walls.len() as u8]));
+    for wall in walls {
+        let orientation = match wall.orientation {
+            Orientation::Horizontal => 0,
+            Orientation::Vertical => 1,
+        };
+        try!(w.write_all(&[wall.x as u8, wall.y as u8, orientation]));
+    }
+
+    for outcome in solver.outcomes() {
+        let v = encode(*outcome);
+        try!(w.write_all(&[v as u8, (v >> 8) as u8]));
+    }
+    Ok(())
+}
+
+/// An outcome as stored in tablebase files
+pub fn encode(outcome: Outcome) -> u16
+{
+    match outcome {
+        Outcome::Draw => 0,
+        Outcome::Win(d) => (d as u16) << 2 | 1,
+        Outcome::Loss(d) => (d as u16) << 2 | 2,
+    }
+}
+
+/// An outcome stored by `encode`
+pub fn decode(v: u16) -> Outcome
+{
+    match v & 3 {
+        1 => Outcome::Win((v >> 2) as u32),
+        2 => Outcome::Loss((v >> 2) as u32),
+        _ => Outcome::Draw,
+    }
+}
+
+/// Read a solved board written by `write`
+pub fn read<R: Read>(r: &mut R) -> QuoridorResult<EndgameSolver>
+{
+    let mut header = [0; 6];
+    try!(r.read_exact(&mut header));
+    if &header[..4] != MAGIC {
+        return Err(QuoridorError::AnalysisError("Not a tablebase file".into()))
+    }
+    if header[4] as i32 != N {
+        return Err(QuoridorError::AnalysisError(format!("Tablebase is for a {}x{} board", header[4], header[4])))
+    }
+
+    let mut walls = HashSet::new();
+    for _ in 0..header[5] {
+        let mut w = [0; 3];
+        try!(r.read_exact(&mut w));
+        let wall = match w[2] {
+            0 => Wall::horizontal(w[0] as i32, w[1] as i32),
+            1 => Wall::vertical(w[0] as i32, w[1] as i32),
+            o => return Err(QuoridorError::ParseError(format!("Unknown wall orientation {} in tablebase", o))),
+        };
+        if !wall.inbounds() {
+            return Err(QuoridorError::ParseError(format!("Tablebase wall {:?} is off the board", wall)))
+        }
+        walls.insert(wall);
+    }
+
+    let mut data = vec![0; POSITIONS * 2];
+    try!(r.read_exact(&mut data));
+    let outcomes = data.chunks(2).map(|b| decode(b[0] as u16 | (b[1] as u16) << 8)).collect();
+
+    EndgameSolver::from_outcomes(walls, outcomes)
+}
+
+/// Tablebases kept in memory by default
+pub const CAPACITY: usize = 16;
+
+/// A collection of tablebases, one per wall layout, generated on
+/// demand and optionally cached in a directory.  Only the `capacity`
+/// most recently used are kept in memory.
+pub struct Tablebases {
+    dir: Option<PathBuf>,
+    /// Most tablebases kept in memory
+    pub capacity: usize,
+    loaded: HashMap<Vec<Wall>, Arc<EndgameSolver>>,
+    /// Layouts of the loaded tablebases, least recently used first
+    used: VecDeque<Vec<Wall>>,
+    /// Layouts being generated in the background
+    building: HashSet<Vec<Wall>>,
+}
+
+/// The wall layout of a game, as tablebases are keyed
+fn layout(game: &Game) -> Vec<Wall>
+{
+    let mut key: Vec<Wall> = game.walls.iter().cloned().collect();
+    key.sort();
+    key
+}
+
+impl Tablebases {
+    /// Create a collection, reading and writing tablebase files in
+    /// `dir` if given
+    pub fn new(dir: Option<PathBuf>) -> Tablebases
+    {
+        Tablebases {
+            dir: dir,
+            capacity: CAPACITY,
+            loaded: HashMap::new(),
+            used: VecDeque::new(),
+            building: HashSet::new(),
+        }
+    }
+
+    /// Number of tablebases in memory
+    pub fn len(&self) -> usize
+    {
+        self.loaded.len()
+    }
+
+    /// Return the tablebase for the board of `game`, loading or
+    /// generating it if needed
+    pub fn get(&mut self, game: &Game) -> QuoridorResult<Arc<EndgameSolver>>
+    {
+        if let Some(solver) = self.cached(game) {
+            return Ok(solver)
+        }
+        let key = layout(game);
+        let solver = try!(load_or_generate(self.dir.as_ref(), game, &key));
+        Ok(self.insert(key, solver))
+    }
+
+    /// The tablebase for the board of `game` if it is in memory
+    pub fn cached(&mut self, game: &Game) -> Option<Arc<EndgameSolver>>
+    {
+        let key = layout(game);
+        let solver = match self.loaded.get(&key) {
+            Some(solver) => solver.clone(),
+            None => return None,
+        };
+        self.used.retain(|k| *k != key);
+        self.used.push_back(key);
+        Some(solver)
+    }
+
+    /// Value of the current position of `game` for the player to move
+    pub fn probe(&mut self, game: &Game) -> QuoridorResult<Outcome>
+    {
+        try!(self.get(game)).probe(game)
+    }
+
+    /// Keep a tablebase in memory, forgetting the least recently used
+    /// if there are too many
+    fn insert(&mut self, key: Vec<Wall>, solver: EndgameSolver) -> Arc<EndgameSolver>
+    {
+        while self.loaded.len() >= cmp::max(self.capacity, 1) {
+            match self.used.pop_front() {
+                Some(old) => self.loaded.remove(&old),
+                None => break,
+            };
+        }
+        let solver = Arc::new(solver);
+        self.loaded.insert(key.clone(), solver.clone());
+        self.used.push_back(key);
+        solver
+    }
+}
+
+/// Return the tablebase for the board of `game` if it is in memory, or
+/// else start generating it on a thread of its own and return None,
+/// so that callers such as request handlers never wait for one to be
+/// solved.  Fails straight away if the game can't be solved.
+pub fn get_or_build(tablebases: &Arc<Mutex<Tablebases>>, game: &Game) -> QuoridorResult<Option<Arc<EndgameSolver>>>
+{
+    try!(EndgameSolver::check(game));
+    let key = layout(game);
+    let dir = {
+        let mut t = tablebases.lock().unwrap();
+        if let Some(solver) = t.cached(game) {
+            return Ok(Some(solver))
+        }
+        if !t.building.insert(key.clone()) {
+            return Ok(None)
+        }
+        t.dir.clone()
+    };
+
+    let (tablebases, game) = (tablebases.clone(), game.clone());
+    thread::spawn(move || {
+        let solver = load_or_generate(dir.as_ref(), &game, &key);
+        let mut t = tablebases.lock().unwrap();
+        t.building.remove(&key);
+        match solver {
+            Ok(solver) => {
+                t.insert(key, solver);
+            },
+            Err(e) => println!("Unable to build a tablebase: {}", e),
+        }
+    });
+    Ok(None)
+}
+
+/// Read the tablebase for wall layout `key` from `dir`, or solve the
+/// board of `game` and write it there
+fn load_or_generate(dir: Option<&PathBuf>, game: &Game, key: &[Wall]) -> QuoridorResult<EndgameSolver>
+{
+    let path = match dir {
+        Some(dir) => dir.join(file_name(key)),
+        None => return EndgameSolver::new(game),
+    };
+
+    if path.exists() {
+        return read(&mut BufReader::new(try!(File::open(&path))));
+    }
+
+    // Written whole and then renamed, so that a crash never leaves a
+    // truncated tablebase behind
+    let solver = try!(EndgameSolver::new(game));
+    try!(fs::create_dir_all(path.parent().unwrap()));
+    let tmp = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(try!(File::create(&tmp)));
+        try!(write(&solver, &mut w));
+        try!(w.flush());
+        try!(w.get_ref().sync_all());
+    }
+    try!(fs::rename(&tmp, &path));
+    Ok(solver)
+}
+
+/// File name identifying a wall layout
+fn file_name(walls: &[Wall]) -> String
+{
+    let names: Vec<String> = walls.iter().map(|w| {
+        let o = match w.orientation {
+            Orientation::Horizontal => "h",
+            Orientation::Vertical => "v",
+        };
+        format!("{}{}{}", o, w.x, w.y)
+    }).collect();
+
+    match names.len() {
+        0 => "empty.qtb".into(),
+        _ => format!("{}.qtb", names.join("_")),
+    }
+}
@@ -1,7 +1,45 @@
 use quoridor::constants::N;
-use quoridor::quoridor::Game;
+use quoridor::quoridor::{Game, GameOptions, Seating, Takebacks};
 use quoridor::adjacency_matrix::AdjacencyMatrix;
 use quoridor::board::Point;
+use quoridor::solver::{EndgameSolver, Outcome};
+use quoridor::tablebase::{self, Tablebases};
+use quoridor::smallboard::{self, Position, SmallTablebase};
+use quoridor::board::{Direction, Wall};
+use quoridor::book::{BookBuilder, OpeningBook};
+use quoridor::record::GameRecord;
+use quoridor::setup::Setup;
+use quoridor::turn::Turn;
+use quoridor::symmetry::{self, Symmetry, MIRROR, ROTATE_180};
+use quoridor::quoridor::GameState;
+use quoridor::evaluator::{Evaluator, Features};
+use quoridor::selfplay::{self, Sample, SelfPlay};
+use quoridor::tuning;
+use quoridor::registry::{Registry, DEFAULT_GAME};
+use quoridor::lobby::Lobby;
+use quoridor::clock::{Clock, TimeControl};
+use quoridor::events::{self, GameEvent};
+use quoridor::websocket;
+use quoridor::spectators::Spectators;
+use quoridor::sse::{self, EventStream, StreamLimit};
+use quoridor::auth::{self, Sessions};
+use quoridor::accounts::{self, Accounts};
+use quoridor::errors::QuoridorError;
+use quoridor::rating::{self, Rating, RatingSystem};
+use quoridor::tournament::{Format, Stage, Tournaments};
+use quoridor::series::Rematches;
+use quoridor::registry::Entrant;
+use quoridor::storage::{Recorder, SqliteStorage, Storage};
+use quoridor::journal::{self, Entry, Journal};
+use quoridor::domain::{DomainEvent, SNAPSHOT_INTERVAL};
+use quoridor::result::{GameResult, Reason};
+use iron::response::WriteBody;
+use rand::{SeedableRng, StdRng};
+use rustc_serialize::json::Json;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
 
 /// Convert (x, y) tuple to a point
 macro_rules! point { ( $x:expr, $y:expr ) => { { Point{ x: $x, y: $y} } }; }
@@ -161,3 +199,1477 @@ fn test_end_game() {
     }
     assert!(g.move_player("Player 1", "SOUTH").is_err());
 }
+
+#[test]
+fn test_reaching_goal_ends_game() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    g.players.get_mut("Player 1").unwrap().p = point!(3, N - 2);
+    assert!(g.move_player("Player 1", "SOUTH").is_ok());
+    assert_eq!(g.winner(), Some(0));
+    assert!(g.increment_turn().is_ok());
+    assert!(g.increment_turn().is_err());
+}
+
+#[test]
+fn test_endgame_solver_needs_empty_hands() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert!(EndgameSolver::new(&g).is_err());
+}
+
+#[test]
+fn test_endgame_solver_race() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    for p in g.players.values_mut() {
+        p.walls = 0;
+    }
+    let solver = EndgameSolver::new(&g).unwrap();
+    assert_eq!(solver.outcome(point!(0, N - 2), point!(8, 4), 0).unwrap(), Outcome::Win(1));
+    assert_eq!(solver.outcome(point!(0, N - 2), point!(8, 4), 1).unwrap(), Outcome::Loss(2));
+
+    g.players.get_mut("Player 1").unwrap().p = point!(0, N - 2);
+    g.players.get_mut("Player 2").unwrap().p = point!(8, 4);
+    assert_eq!(solver.best_move(&g).unwrap(), Some(point!(0, N - 1)));
+    assert_eq!(solver.principal_variation(&g).unwrap(), vec![point!(0, N - 1)]);
+}
+
+#[test]
+fn test_place_wall_uses_hand() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    g.players.get_mut("Player 1").unwrap().walls = 1;
+    assert!(g.place_wall("Player 1", &Wall::horizontal(2, 2)).is_ok());
+    assert_eq!(g.players["Player 1"].walls, 0);
+    assert!(g.place_wall("Player 1", &Wall::horizontal(5, 5)).is_err());
+}
+
+#[test]
+fn test_tablebase_round_trip() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert!(g.add_wall_tuples((3, 4), (5, 4)).is_ok());
+    for p in g.players.values_mut() {
+        p.walls = 0;
+    }
+    let solver = EndgameSolver::new(&g).unwrap();
+    let mut buf = vec![];
+    assert!(tablebase::write(&solver, &mut buf).is_ok());
+    let loaded = tablebase::read(&mut &buf[..]).unwrap();
+    assert_eq!(loaded.walls(), solver.walls());
+    assert!(loaded.outcomes() == solver.outcomes());
+    assert_eq!(loaded.probe(&g).unwrap(), solver.probe(&g).unwrap());
+    assert!(tablebase::read(&mut &buf[1..]).is_err());
+
+    // Walls must have a known orientation and be on the board
+    let mut bad = buf.clone();
+    bad[6 + 2] = 2;
+    assert!(tablebase::read(&mut &bad[..]).is_err());
+    let mut bad = buf.clone();
+    bad[6] = N as u8;
+    assert!(tablebase::read(&mut &bad[..]).is_err());
+
+    // Built in the background, and only the most recent kept
+    let tablebases = Arc::new(Mutex::new(Tablebases::new(None)));
+    tablebases.lock().unwrap().capacity = 1;
+    assert!(tablebase::get_or_build(&tablebases, &Game::new()).is_err());
+    assert!(tablebase::get_or_build(&tablebases, &g).unwrap().is_none());
+    let mut built = None;
+    for _ in 0..600 {
+        built = tablebase::get_or_build(&tablebases, &g).unwrap();
+        if built.is_some() {
+            break
+        }
+        ::std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(built.unwrap().probe(&g).unwrap(), solver.probe(&g).unwrap());
+    assert!(g.add_wall_tuples((3, 6), (5, 6)).is_ok());
+    assert!(tablebases.lock().unwrap().get(&g).is_ok());
+    assert_eq!(tablebases.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_small_tablebase() {
+    let t = SmallTablebase::generate(3, 1, smallboard::MAX_POSITIONS).unwrap();
+    let position = |a: (i32, i32), b: (i32, i32), walls: Vec<Wall>, in_hand: [u8; 2], turn: u8| Position {
+        pawns: [point!(a.0, a.1), point!(b.0, b.1)],
+        in_hand: in_hand,
+        walls: walls,
+        turn: turn,
+    };
+
+    // One step from the goal, or from blocking a step from the goal
+    let p = position((0, 1), (2, 1), vec![], [1, 1], 0);
+    assert_eq!(t.outcome(&p).unwrap(), Outcome::Win(1));
+    assert_eq!(t.best_turn(&p).unwrap(), Some(Turn::Move(Direction::South)));
+    let p = position((0, 1), (2, 2), vec![Wall::horizontal(2, 1)], [0, 1], 1);
+    assert_eq!(t.best_turn(&p).unwrap(), Some(Turn::PlaceWall(Wall::horizontal(1, 2))));
+
+    // Every value agrees with the values of the turns out of it
+    for a in 0..9 {
+        for b in 0..9 {
+            for &(ref walls, in_hand) in &[(vec![], [1, 1]), (vec![Wall::vertical(1, 1)], [0, 1])] {
+                for turn in 0..2 {
+                    let p = position((a % 3, a / 3), (b % 3, b / 3), walls.clone(), in_hand, turn);
+                    if a == b || a / 3 == 2 || b / 3 == 0 {
+                        continue;
+                    }
+                    let next: Vec<Outcome> = t.turns(&p).unwrap().iter().map(|&(_, ref q)| t.outcome(q).unwrap()).collect();
+                    let win = next.iter().filter_map(|o| match *o { Outcome::Loss(d) => Some(d + 1), _ => None }).min();
+                    let loss = next.iter().filter_map(|o| match *o { Outcome::Win(d) => Some(d + 1), _ => None }).max();
+                    let expected = match (win, next.contains(&Outcome::Draw), loss) {
+                        (Some(d), _, _) => Outcome::Win(d),
+                        (None, false, Some(d)) => Outcome::Loss(d),
+                        _ => Outcome::Draw,
+                    };
+                    assert_eq!(t.outcome(&p).unwrap(), expected);
+                }
+            }
+        }
+    }
+
+    assert!(t.outcome(&position((1, 0), (1, 0), vec![], [1, 1], 0)).is_err());
+    assert!(t.outcome(&position((1, 0), (1, 2), vec![], [1, 0], 0)).is_err());
+    assert!(SmallTablebase::generate(5, 3, 1000).is_err());
+
+    let mut buf = vec![];
+    assert!(smallboard::write(&t, &mut buf).is_ok());
+    let loaded = smallboard::read(&mut &buf[..]).unwrap();
+    assert_eq!(loaded.len(), t.len());
+    let p = position((1, 0), (1, 2), vec![], [1, 1], 0);
+    assert_eq!(loaded.outcome(&p).unwrap(), t.outcome(&p).unwrap());
+    assert!(smallboard::read(&mut &buf[..buf.len() - 1]).is_err());
+}
+
+#[test]
+fn test_turn_json() {
+    for turn in vec![Turn::Move(Direction::North),
+                     Turn::Jump(Direction::SouthWest),
+                     Turn::PlaceWall(Wall::vertical(3, 4))] {
+        assert_eq!(Turn::from_json(&turn.to_json()).unwrap(), turn);
+    }
+}
+
+#[test]
+fn test_record_replay() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert!(g.move_player("Player 1", "SOUTH").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(g.place_wall("Player 2", &Wall::horizontal(4, 3)).is_ok());
+    assert!(g.increment_turn().is_ok());
+
+    let record = GameRecord::from_game(&g);
+    assert_eq!(record.turns, vec![Turn::Move(Direction::South), Turn::PlaceWall(Wall::horizontal(4, 3))]);
+    assert_eq!(GameRecord::from_json(&record.to_json()).unwrap(), record);
+    let positions = record.positions().unwrap();
+    assert_eq!(positions.len(), 3);
+    assert_eq!(positions[2].position_hash(), g.position_hash());
+
+    // Replayed with the options the game was created with
+    let mut options = GameOptions::default();
+    options.walls = 3;
+    options.pie_rule = true;
+    let mut g = Game::with_options(options.clone());
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert!(g.move_player("Player 1", "SOUTH").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(g.swap_sides("Player 2").is_ok());
+    assert!(g.place_wall("Player 1", &Wall::horizontal(4, 3)).is_ok());
+    assert!(g.increment_turn().is_ok());
+    let record = GameRecord::from_json(&GameRecord::from_game(&g).to_json()).unwrap();
+    assert_eq!(record.options, options);
+    let replayed = record.positions().unwrap().pop().unwrap();
+    assert_eq!((replayed.position_hash(), replayed.options.pie_rule), (g.position_hash(), true));
+}
+
+#[test]
+fn test_opening_book_mirrored() {
+    let players = vec!["Player 1".to_string(), "Player 2".to_string()];
+    let east = Turn::Move(Direction::East);
+    let west = Turn::Move(Direction::West);
+    let mut builder = BookBuilder::new();
+    for turns in vec![vec![east, west], vec![west, east]] {
+        let record = GameRecord { players: players.clone(), turns: turns, winner: None, reason: None, clocks: vec![], options: GameOptions::default() };
+        assert!(builder.add_record(&record).is_ok());
+    }
+    let book = builder.build();
+    assert_eq!(book.len(), 2);
+
+    let positions = GameRecord { players: players, turns: vec![west], winner: None, reason: None, clocks: vec![], options: GameOptions::default() }.positions().unwrap();
+    assert_eq!(book.lookup(&positions[0])[0].weight, 2);
+    assert_eq!(book.best(&positions[1]), Some(east));
+
+    let mut buf = vec![];
+    assert!(book.write(&mut buf).is_ok());
+    let loaded = OpeningBook::read(&mut &buf[..]).unwrap();
+    assert_eq!(loaded.best(&positions[1]), Some(east));
+}
+
+#[test]
+fn test_symmetry_walls() {
+    let mut g = Game::new();
+    assert!(g.add_wall_tuples((1, 1), (1, 3)).is_ok());
+    assert!(g.add_wall_tuples((4, 6), (6, 6)).is_ok());
+    for s in Symmetry::all() {
+        let t = s.game(&g).unwrap();
+        assert_eq!(s.inverse().game(&t).unwrap().walls, g.walls);
+        for x in 0..N {
+            for y in 0..N {
+                let a = point!(x, y);
+                for b in vec![a.east(), a.south()] {
+                    assert_eq!(g.has_wall_between(a, b), t.has_wall_between(s.point(a), s.point(b)));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_symmetry_seats() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert!(g.move_player("Player 1", "EAST").is_ok());
+    assert_eq!(Symmetry::all().iter().filter(|s| s.applies_to(&g)).count(), 4);
+
+    let r = ROTATE_180.game(&g).unwrap();
+    assert_eq!(r.players["Player 1"].id, 1);
+    assert_eq!(r.players["Player 1"].p, point!(3, N - 1));
+    assert_eq!(r.history, vec![Turn::Move(Direction::West)]);
+    match r.state {
+        GameState::Started(1) => (),
+        _ => panic!("Expected player 1 to move"),
+    }
+
+    assert_eq!(r.positions.len(), g.positions.len());
+    assert!(r.positions != g.positions);
+    assert_eq!(ROTATE_180.game(&r).unwrap().positions, g.positions);
+
+    let m = MIRROR.game(&g).unwrap();
+    assert!(m.position_hash() != g.position_hash());
+    assert_eq!(symmetry::canonical_hash(&m), symmetry::canonical_hash(&g));
+    assert_eq!(symmetry::canonical_hash(&r), symmetry::canonical_hash(&g));
+}
+
+#[test]
+fn test_features() {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    assert_eq!(Features::compute(&g, 0).unwrap(), Features([0.0, 0.0, 0.0, 0.0, 0.0]));
+    assert!(g.move_player("Player 1", "SOUTH").is_ok());
+    let f = Features::compute(&g, 0).unwrap();
+    assert_eq!(f.0[0], 1.0);
+    assert_eq!(f.0[3], 1.0);
+    assert!(Evaluator::default().evaluate(&g, 0).unwrap() > 0.0);
+    assert!(Evaluator::default().evaluate(&g, 1).unwrap() < 0.0);
+}
+
+#[test]
+fn test_self_play_repeatable() {
+    let mut selfplay = SelfPlay::new(Evaluator::default());
+    selfplay.max_plies = 6;
+    let seed: &[_] = &[42];
+    let (record, samples) = selfplay.play(&mut StdRng::from_seed(seed)).unwrap();
+    assert_eq!(samples.len(), 6);
+    assert_eq!(selfplay.play(&mut StdRng::from_seed(seed)).unwrap().0, record);
+
+    let mut buf = vec![];
+    assert!(selfplay::write_header(&mut buf).is_ok());
+    assert!(selfplay::write_samples(&samples, &mut buf).is_ok());
+    assert_eq!(selfplay::read_samples(&buf[..]).unwrap(), samples);
+
+    // The engine plays book turns while it can
+    let mut g = Game::new();
+    assert!(g.add_player_to_seat("Player 1".to_string(), String::new(), None).is_ok());
+    assert!(g.add_player_to_seat("Player 2".to_string(), String::new(), None).is_ok());
+    let mut book = OpeningBook::new();
+    book.insert(&g, Turn::Move(Direction::East), 1);
+    selfplay.book = Some(book);
+    let (record, _) = selfplay.play(&mut StdRng::from_seed(seed)).unwrap();
+    assert_eq!(record.turns[0], Turn::Move(Direction::East));
+
+    // Once the walls are spent it plays the solver's move, never a
+    // random one
+    selfplay.epsilon = 1.0;
+    for p in g.players.values_mut() {
+        p.walls = 0;
+    }
+    g.players.get_mut("Player 1").unwrap().p = point!(0, N - 2);
+    g.players.get_mut("Player 2").unwrap().p = point!(8, 4);
+    let mut rng = StdRng::from_seed(seed);
+    for _ in 0..10 {
+        assert_eq!(selfplay.choose_turn(&g, &mut rng).unwrap(), Some(Turn::Move(Direction::South)));
+    }
+}
+
+#[test]
+fn test_tuning() {
+    let samples: Vec<Sample> = (-5..6).filter(|&i| i != 0).map(|i| Sample {
+        features: Features([i as f64, 0.0, 1.0, 0.0, 0.0]),
+        result: if i > 0 { 1.0 } else { 0.0 },
+    }).collect();
+
+    let fitted = tuning::logistic_regression(&samples, 200, 0.5);
+    assert!(fitted.weights[0] > 0.0);
+
+    let start = Evaluator::default();
+    let tuned = tuning::local_search(&start, &samples, 0.5, 0.01);
+    assert!(tuning::error(&tuned, &samples) < tuning::error(&start, &samples));
+
+    let samples = TempFile::new("samples.csv");
+    let weights = TempFile::new("weights.json");
+    let seed: &[_] = &[7];
+    let fitted = tuning::run(1, &mut StdRng::from_seed(seed), &samples, &weights).unwrap();
+    let json = Json::from_reader(&mut ::std::fs::File::open(&weights).unwrap()).unwrap();
+    assert_eq!(Evaluator::from_json(&json).unwrap(), fitted);
+}
+
+#[test]
+fn test_registry() {
+    let registry = Registry::new();
+    registry.insert(DEFAULT_GAME, Game::new());
+    let id = registry.create(Game::new()).unwrap();
+    assert!(registry.get(&id).unwrap().game.write().unwrap().add_player("Player 1", "a").is_ok());
+    assert!(registry.get(DEFAULT_GAME).unwrap().game.read().unwrap().players.is_empty());
+    assert_eq!(registry.ids(), vec![DEFAULT_GAME.to_string(), id.clone()]);
+    assert!(registry.remove(&id).is_some());
+    assert!(registry.get(&id).is_none());
+    assert!(registry.create(Game::new()).unwrap() != id);
+
+    // A change the hooks can't save isn't made known
+    registry.on_change(Box::new(|_: &str, _: u64, _: usize, _: &Game| Err(QuoridorError::Unauthorized)));
+    assert!(registry.create(Game::new()).is_err());
+    assert_eq!(registry.ids().len(), 2);
+    let entry = registry.get(DEFAULT_GAME).unwrap();
+    let mut g = entry.game.read().unwrap().clone();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(entry.publish_changes(0, &g).is_err());
+    assert_eq!(entry.version(), 0);
+}
+
+#[test]
+fn test_lobby_matchmaking() {
+    let registry = Registry::new();
+    let mut lobby = Lobby::new();
+    let sessions = Arc::new(Mutex::new(Sessions::new(Duration::from_secs(60))));
+    lobby.sessions = Some(sessions.clone());
+    assert_eq!(lobby.join(&registry, Entrant::guest("Player 1", "a"), Some(1500.0)).unwrap(), None);
+    assert_eq!(lobby.join(&registry, Entrant::guest("Player 2", "b"), Some(1900.0)).unwrap(), None);
+    assert!(lobby.status("Player 1", "b").is_err());
+
+    // Paired with the waiting player inside the rating band
+    let seat = lobby.join(&registry, Entrant::guest("Player 3", "c"), Some(1600.0)).unwrap().unwrap();
+    assert_eq!(seat.seat, 1);
+    let first = lobby.status("Player 1", "a").unwrap().unwrap();
+    assert_eq!((first.game.clone(), first.seat), (seat.game.clone(), 0));
+    let session = sessions.lock().unwrap().resolve(&first.token.unwrap(), Instant::now()).unwrap();
+    assert_eq!((session.game, session.name), (seat.game.clone(), "Player 1".to_string()));
+    assert!(seat.to_json().find("token").is_some());
+    assert_eq!(lobby.status("Player 2", "b").unwrap(), None);
+    let entry = registry.get(&seat.game).unwrap();
+    assert_eq!(entry.game.read().unwrap().state, GameState::Started(0));
+
+    // Players without a rating are paired first come first served
+    assert_eq!(lobby.join(&registry, Entrant::guest("Player 4", "d"), None).unwrap().unwrap().seat, 1);
+    assert_eq!(lobby.status("Player 2", "b").unwrap().unwrap().seat, 0);
+}
+
+#[test]
+fn test_lobby_challenges() {
+    let registry = Registry::new();
+    let mut lobby = Lobby::new();
+    let public = lobby.challenge(Entrant::guest("Player 1", "a"), false).unwrap();
+    let private = lobby.challenge(Entrant::guest("Player 2", "b"), true).unwrap();
+    assert_eq!(lobby.open_challenges().len(), 1);
+    assert!(lobby.accept(&registry, &private.id, Entrant::guest("Player 3", "c")).is_err());
+    assert!(lobby.accept(&registry, &public.id, Entrant::guest("Player 1", "a")).is_err());
+
+    let seat = lobby.accept(&registry, &public.id, Entrant::guest("Player 3", "c")).unwrap();
+    assert_eq!(lobby.status("Player 1", "a").unwrap().unwrap().game, seat.game);
+    let code = private.code.unwrap();
+    assert!(lobby.accept_code(&registry, &code, Entrant::guest("Player 4", "d")).is_ok());
+    assert!(lobby.accept_code(&registry, &code, Entrant::guest("Player 5", "e")).is_err());
+    assert_eq!(registry.ids().len(), 2);
+}
+
+#[test]
+fn test_clock() {
+    let control = TimeControl { base: Some(1000), increment: 500, move_limit: Some(1200) };
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut clock = Clock::new(control, 2);
+    clock.start(0, start);
+    assert_eq!(clock.time_left(0, at(300)), Some(700));
+    assert_eq!(clock.time_left(1, at(300)), Some(1000));
+    assert!(clock.punch(1, at(300)));
+    assert_eq!(clock.remaining, vec![1200, 1000]);
+    assert_eq!(clock.check(at(1200)), None);
+    assert!(!clock.punch(0, at(1300)));
+    assert_eq!(clock.flagged, Some(1));
+    assert_eq!(clock.log, vec![1200]);
+}
+
+#[test]
+fn test_loss_on_time() {
+    let mut options = GameOptions::default();
+    options.time_control = Some(TimeControl { base: Some(60000), increment: 0, move_limit: Some(0) });
+    let registry = Registry::new();
+    let id = registry.create(Game::with_options(options)).unwrap();
+    {
+        let entry = registry.get(&id).unwrap();
+        let mut g = entry.game.write().unwrap();
+        assert!(g.add_player("Player 1", "a").is_ok());
+        assert!(g.add_player("Player 2", "b").is_ok());
+    }
+    assert_eq!(registry.check_clocks(Instant::now() + Duration::from_millis(1)), vec![id.clone()]);
+
+    let g = registry.get(&id).unwrap().game.read().unwrap().clone();
+    assert_eq!(g.state, GameState::GameOver);
+    assert_eq!(g.winner(), Some(1));
+    assert_eq!(GameRecord::from_game(&g).winner, Some(1));
+}
+
+#[test]
+fn test_game_events() {
+    let registry = Registry::new();
+    let id = registry.create(Game::new()).unwrap();
+    let entry = registry.get(&id).unwrap();
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.add_player("Player 1", "a").is_ok());
+        assert!(g.add_player("Player 2", "b").is_ok());
+        let after_join = g.clone();
+        assert!(g.move_player("Player 1", "SOUTH").is_ok());
+        assert!(g.increment_turn().is_ok());
+        assert!(entry.publish_changes(before, &after_join).is_ok());
+        assert!(entry.publish_changes(after_join.stream.len(), &g).is_ok());
+    }
+
+    let all = entry.events_since(0);
+    assert_eq!(all.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(all[1].event, GameEvent::PlayerJoined { name: "Player 2".into(), seat: 1 });
+    assert_eq!(all[2].event, GameEvent::Move { seat: 0, from: point!(4, 0), to: point!(4, 1) });
+    assert_eq!(entry.wait_for_events(2, Duration::from_millis(10)).unwrap(), vec![all[2].clone()]);
+    assert_eq!(entry.wait_for_events(3, Duration::from_millis(10)).unwrap(), vec![]);
+    assert!(events::diff(0, &Game::new()).is_empty());
+
+    registry.remove(&id);
+    assert_eq!(entry.wait_for_events(3, Duration::from_millis(10)), None);
+}
+
+#[test]
+fn test_websocket_resource() {
+    assert_eq!(websocket::parse_resource("/games/3?since=12"), ("3".to_string(), 12));
+    assert_eq!(websocket::parse_resource("/"), (DEFAULT_GAME.to_string(), 0));
+    assert_eq!(websocket::bearer_token(b"Bearer abc"), Some("abc".to_string()));
+    assert_eq!(websocket::bearer_token(b"Basic abc"), None);
+}
+
+#[test]
+fn test_versioned_poll() {
+    let entry = Registry::new().insert("game", Game::new());
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.add_player("Player 1", "a").is_ok());
+        assert!(g.add_player("Player 2", "b").is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    let version = entry.version();
+    assert_eq!(version, 2);
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.move_player("Player 1", "SOUTH").is_ok());
+        assert!(g.increment_turn().is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+
+    // Returns at once when the game is already newer
+    let events = entry.wait_for_events(version, Duration::from_secs(60)).unwrap();
+    let g = entry.game.read().unwrap();
+    assert_eq!(events::turns_since(&events, &g), vec![Turn::Move(Direction::South)]);
+    assert!(events::turns_since(&entry.events_since(entry.version()), &g).is_empty());
+}
+
+#[test]
+fn test_sse_format() {
+    let entry = Registry::new().insert("game", Game::new());
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.add_player("Player 1", "a").is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    assert_eq!(sse::format_event(&entry.events_since(0)[0]),
+               "id: 1\nevent: joined\ndata: {\"name\":\"Player 1\",\"seat\":0,\"seq\":1,\"type\":\"joined\"}\n\n");
+
+    // Streams take a slot each, and end with the game
+    let streams = StreamLimit::new(1);
+    let slot = StreamLimit::acquire(&streams).unwrap();
+    assert!(StreamLimit::acquire(&streams).is_none());
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.add_player_to_seat("Player 2".to_string(), String::new(), None).is_ok());
+        assert!(g.resign("Player 2").is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    let mut stream = EventStream { entry: entry.clone(), seq: 0, delay: Duration::from_secs(0), slot: slot };
+    let mut body = vec![];
+    assert!(stream.write_body(&mut body).is_ok());
+    let body = String::from_utf8(body).unwrap();
+    assert_eq!(body.lines().filter(|l| l.starts_with("event: ")).last(), Some("event: game_over"));
+    drop(stream);
+    assert_eq!(streams.open(), 0);
+}
+
+#[test]
+fn test_spectators() {
+    let mut options = GameOptions::default();
+    options.spectator_delay = 60;
+    let entry = Registry::new().insert("game", Game::with_options(options));
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.add_player("Player 1", "secret-1").is_ok());
+        assert!(g.add_player("Player 2", "secret-2").is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+
+    // Spectators still see the empty board, and never a key
+    let state = entry.spectator_state();
+    assert_eq!(state, Game::new().to_json());
+    assert!(!entry.may_watch_live(None));
+    assert!(!entry.may_watch_live(Some("Alice")));
+    assert!(entry.may_watch_live(Some("Player 1")));
+    assert!(!entry.game.read().unwrap().to_json().to_string().contains("secret"));
+
+    let mut spectators = entry.spectators.lock().unwrap();
+    spectators.join(Some("Alice"));
+    spectators.join(Some("Alice"));
+    spectators.join(None);
+    assert_eq!(spectators.count(), 2);
+    spectators.leave(Some("Alice"));
+    assert_eq!(spectators.names(), vec!["Alice".to_string()]);
+    spectators.leave(Some("Alice"));
+    spectators.leave(None);
+    assert_eq!(spectators.count(), 0);
+
+    // Only the states spectators may still be shown are kept
+    let (now, delay) = (Instant::now(), Duration::from_secs(10));
+    let mut spectators = Spectators::new(Game::new().to_json());
+    let states: Vec<_> = (1..4).map(|walls| GameOptions { walls: walls, ..GameOptions::default() }.to_json()).collect();
+    spectators.record(states[0].clone(), delay, now + Duration::from_secs(1));
+    spectators.record(states[1].clone(), delay, now + Duration::from_secs(5));
+    assert_eq!(spectators.snapshots(), 3);
+    spectators.record(states[2].clone(), delay, now + Duration::from_secs(20));
+    assert_eq!(spectators.snapshots(), 2);
+    assert_eq!(spectators.state(delay, now + Duration::from_secs(20)), states[1]);
+}
+
+#[test]
+fn test_keys_are_hashed() {
+    let hash = auth::hash_key("secret");
+    assert!(!hash.contains("secret"));
+    assert!(hash != auth::hash_key("secret"));
+    assert!(auth::verify_key("secret", &hash));
+    assert!(!auth::verify_key("guess", &hash));
+    assert!(!auth::verify_key("", &auth::hash_key("")));
+
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "secret").is_ok());
+    assert!(g.players["Player 1"].check_key("secret"));
+    assert!(!g.players["Player 1"].check_key("Secret"));
+}
+
+#[test]
+fn test_sessions() {
+    let mut sessions = Sessions::new(Duration::from_secs(60));
+    let now = Instant::now();
+    let session = sessions.create("1", "Player 1", now);
+    assert_eq!(session.token.len(), 32);
+    assert_eq!(sessions.resolve(&session.token, now), Some(session.clone()));
+    assert_eq!(sessions.resolve(&session.token, now + Duration::from_secs(61)), None);
+    assert!(sessions.create("1", "Player 1", now).token != session.token);
+    assert!(sessions.revoke(&session.token));
+    assert_eq!(sessions.resolve(&session.token, now), None);
+}
+
+#[test]
+fn test_accounts() {
+    let path = TempFile::new("accounts.json");
+    let accounts = Arc::new(Mutex::new(Accounts::open(path.to_path_buf()).unwrap()));
+    let id = accounts.lock().unwrap().register("Player 1", "secret", false).unwrap().id;
+    assert!(accounts.lock().unwrap().register("Player 1", "other", false).is_err());
+    assert!(accounts::identify(&accounts, "Player 1", "wrong").is_err());
+    assert_eq!(accounts::identify(&accounts, "Player 1", "secret").unwrap().account, Some(id));
+    assert_eq!(accounts::identify(&accounts, "Guest", "key").unwrap().account, None);
+
+    let registry = Registry::new();
+    {
+        let accounts = accounts.clone();
+        registry.on_game_over(Box::new(move |_: &str, game: &Game| accounts.lock().unwrap().record_result(game).unwrap()));
+    }
+    let entry = registry.insert("game", Game::new());
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        let accounts = accounts.lock().unwrap();
+        assert!(accounts.join_game(&mut g, "Player 1", "wrong").is_err());
+        assert!(accounts.join_game(&mut g, "Player 1", "secret").is_ok());
+        assert!(accounts.join_game(&mut g, "Guest", "key").is_ok());
+        assert_eq!(g.players["Player 1"].account, Some(id));
+        assert_eq!(g.players["Guest"].account, None);
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        g.players.get_mut("Player 1").unwrap().p = point!(4, N - 1);
+        assert!(g.increment_turn().is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+
+    let reloaded = Accounts::open(path.to_path_buf()).unwrap();
+    let account = reloaded.find("Player 1").unwrap();
+    assert_eq!((account.stats.games, account.stats.wins), (1, 1));
+    assert!(reloaded.authenticate("Player 1", "secret").is_ok());
+}
+
+#[test]
+fn test_glicko2() {
+    // The worked example from Glickman's description of Glicko-2
+    let player = Rating { rating: 1500.0, deviation: 200.0, volatility: 0.06 };
+    let opponent = |rating, deviation| Rating { rating: rating, deviation: deviation, volatility: 0.06 };
+    let results = [(opponent(1400.0, 30.0), 1.0), (opponent(1550.0, 100.0), 0.0), (opponent(1700.0, 300.0), 0.0)];
+    let rated = rating::glicko2(&player, &results);
+    assert!((rated.rating - 1464.06).abs() < 0.01);
+    assert!((rated.deviation - 151.52).abs() < 0.01);
+    assert!((rated.volatility - 0.05999).abs() < 0.00001);
+
+    assert_eq!(rating::elo(1500.0, 1500.0, 1.0, 32.0), 1516.0);
+    let elo = RatingSystem::Elo(32.0).update(&player, &player, 0.0);
+    assert_eq!((elo.rating, elo.deviation), (1484.0, 200.0));
+
+    assert_eq!(RatingSystem::from_name("glicko2"), Some(RatingSystem::Glicko2));
+    assert_eq!(RatingSystem::from_name("elo"), Some(RatingSystem::Elo(32.0)));
+    assert_eq!(RatingSystem::from_name("elo:24"), Some(RatingSystem::Elo(24.0)));
+    assert_eq!(RatingSystem::from_name("elo:-1"), None);
+    assert_eq!(RatingSystem::from_name("chess"), None);
+}
+
+#[test]
+fn test_rated_game() {
+    let mut accounts = Accounts::in_memory();
+    assert!(accounts.register("Bot", "a", true).is_ok());
+    assert!(accounts.register("Human", "b", false).is_ok());
+    let mut g = Game::new();
+    assert!(accounts.join_game(&mut g, "Bot", "a").is_ok());
+    assert!(accounts.join_game(&mut g, "Human", "b").is_ok());
+    g.players.get_mut("Human").unwrap().p = point!(4, 0);
+    g.players.get_mut("Bot").unwrap().p = point!(3, 0);
+    assert!(g.increment_turn().is_ok());
+    assert!(accounts.record_result(&g).is_ok());
+
+    let board: Vec<_> = accounts.leaderboard(None).iter().map(|a| a.name.clone()).collect();
+    assert_eq!(board, vec!["Human".to_string(), "Bot".to_string()]);
+    assert!(accounts.find("Human").unwrap().rating.rating > 1500.0);
+    assert_eq!(accounts.find("Bot").unwrap().history.len(), 1);
+    assert_eq!(accounts.leaderboard(Some(true)).len(), 1);
+
+    // Handicap games count in the stats but aren't rated
+    let mut options = GameOptions::default();
+    options.setup = Some(Setup { walls: Some(vec![10, 3]), starts: None, board: vec![], first: 0 });
+    let mut g = Game::with_options(options);
+    assert!(accounts.join_game(&mut g, "Bot", "a").is_ok());
+    assert!(accounts.join_game(&mut g, "Human", "b").is_ok());
+    assert!(g.resign("Human").is_ok());
+    assert!(accounts.record_result(&g).is_ok());
+    let bot = accounts.find("Bot").unwrap();
+    assert_eq!((bot.stats.games, bot.stats.wins, bot.history.len()), (2, 1, 1));
+
+    // A setup that is the same for both seats is still rated
+    let mut options = GameOptions::default();
+    options.setup = Some(Setup { walls: Some(vec![5, 5]), starts: None, board: vec![], first: 1 });
+    let mut g = Game::with_options(options);
+    assert!(accounts.join_game(&mut g, "Bot", "a").is_ok());
+    assert!(accounts.join_game(&mut g, "Human", "b").is_ok());
+    assert!(g.resign("Human").is_ok());
+    assert!(accounts.record_result(&g).is_ok());
+    let bot = accounts.find("Bot").unwrap();
+    assert_eq!((bot.stats.games, bot.stats.wins, bot.history.len()), (3, 2, 2));
+}
+
+/// Finish a game in the registry with a win for `seat`
+fn win_game(registry: &Registry, id: &str, seat: u8) {
+    let entry = registry.get(id).unwrap();
+    let mut g = entry.game.write().unwrap();
+    let before = g.stream.len();
+    let name = g.players.values().find(|p| p.id == seat).unwrap().name.clone();
+    g.players.get_mut(&name).unwrap().p = point!(0, if seat == 0 { N - 1 } else { 0 });
+    assert!(g.increment_turn().is_ok());
+    assert!(entry.publish_changes(before, &g).is_ok());
+}
+
+/// Registry whose finished games are recorded in `tournaments`, and a
+/// tournament in it with `n` players
+fn tournament(format: Format, n: usize) -> (Arc<Registry>, Arc<Mutex<Tournaments>>, String) {
+    let registry = Arc::new(Registry::new());
+    let tournaments = Arc::new(Mutex::new(Tournaments::new()));
+    {
+        let (tournaments, registry_ref) = (tournaments.clone(), Arc::downgrade(&registry));
+        registry.on_game_over(Box::new(move |id: &str, game: &Game| {
+            let registry = registry_ref.upgrade().unwrap();
+            tournaments.lock().unwrap().record_result(&registry, id, game).unwrap();
+        }));
+    }
+    let id = tournaments.lock().unwrap().create("Championship", format, GameOptions::default());
+    {
+        let mut tournaments = tournaments.lock().unwrap();
+        let t = tournaments.get_mut(&id).unwrap();
+        for i in 0..n {
+            let name = format!("Bot {}", i);
+            assert!(t.join(Entrant { name: name, key_hash: auth::hash_key("k"), account: None }).is_ok());
+        }
+        assert!(t.join(Entrant { name: "Bot 0".into(), key_hash: String::new(), account: None }).is_err());
+        assert!(t.start(&registry).is_ok());
+    }
+    (registry, tournaments, id)
+}
+
+/// Games of the current round that are still being played
+fn open_games(tournaments: &Mutex<Tournaments>, id: &str) -> Vec<String> {
+    let tournaments = tournaments.lock().unwrap();
+    let t = tournaments.get(id).unwrap();
+    t.rounds.last().unwrap().iter().filter(|p| p.score.is_none()).filter_map(|p| p.game.clone()).collect()
+}
+
+#[test]
+fn test_round_robin_tournament() {
+    let (registry, tournaments, id) = tournament(Format::DoubleRoundRobin, 4);
+    while tournaments.lock().unwrap().get(&id).unwrap().stage == Stage::Running {
+        for game in open_games(&tournaments, &id) {
+            win_game(&registry, &game, 0);
+        }
+    }
+    let tournaments = tournaments.lock().unwrap();
+    let t = tournaments.get(&id).unwrap();
+    assert_eq!(t.rounds.len(), 6);
+    assert_eq!(registry.ids().len(), 12);
+
+    // Everybody meets everybody once each way round
+    let mut pairs: Vec<_> = t.rounds.iter().flat_map(|r| r.iter()).map(|p| (p.first, p.second.unwrap())).collect();
+    pairs.sort();
+    pairs.dedup();
+    assert_eq!(pairs.len(), 12);
+    assert!(pairs.iter().all(|&(a, b)| a != b));
+
+    // Whoever moves first wins, so everybody wins half their games
+    let standings = t.standings();
+    assert!(standings.iter().all(|s| s.wins == 3 && s.losses == 3));
+    assert_eq!(t.winner(), Some("Bot 0"));
+}
+
+#[test]
+fn test_swiss_tournament() {
+    let (registry, tournaments, id) = tournament(Format::Swiss(Some(3)), 5);
+    while tournaments.lock().unwrap().get(&id).unwrap().stage == Stage::Running {
+        for game in open_games(&tournaments, &id) {
+            win_game(&registry, &game, 1);
+        }
+    }
+    let tournaments = tournaments.lock().unwrap();
+    let t = tournaments.get(&id).unwrap();
+    assert_eq!(t.rounds.len(), 3);
+
+    // No rematches, and a different bye every round
+    let mut pairs: Vec<_> = t.rounds.iter().flat_map(|r| r.iter())
+        .filter_map(|p| p.second.map(|s| (p.first.min(s), p.first.max(s)))).collect();
+    let games = pairs.len();
+    pairs.sort();
+    pairs.dedup();
+    assert_eq!((games, pairs.len()), (6, 6));
+    let mut byes: Vec<_> = t.rounds.iter().flat_map(|r| r.iter()).filter(|p| p.second.is_none()).map(|p| p.first).collect();
+    byes.sort();
+    byes.dedup();
+    assert_eq!(byes.len(), 3);
+
+    // Standings are ordered by points, then Buchholz
+    let standings = t.standings();
+    assert_eq!(standings.iter().map(|s| s.points).sum::<f64>(), 9.0);
+    for w in standings.windows(2) {
+        assert!(w[0].points > w[1].points || (w[0].points == w[1].points && w[0].buchholz >= w[1].buchholz));
+    }
+}
+
+#[test]
+fn test_knockout_tournament() {
+    let (registry, tournaments, id) = tournament(Format::Knockout, 5);
+    {
+        let tournaments = tournaments.lock().unwrap();
+        let t = tournaments.get(&id).unwrap();
+        assert_eq!(t.total_rounds(), 3);
+        // The top three seeds get byes, and the fourth and fifth play
+        let byes: Vec<_> = t.rounds[0].iter().filter(|p| p.second.is_none()).map(|p| p.first).collect();
+        assert_eq!(byes, vec![0, 1, 2]);
+        assert_eq!(t.rounds[0][1].first, 3);
+        assert_eq!(t.rounds[0][1].second, Some(4));
+    }
+    // The second player wins every game
+    while tournaments.lock().unwrap().get(&id).unwrap().stage == Stage::Running {
+        for game in open_games(&tournaments, &id) {
+            win_game(&registry, &game, 1);
+        }
+    }
+    let tournaments = tournaments.lock().unwrap();
+    let t = tournaments.get(&id).unwrap();
+    assert_eq!(t.rounds.iter().map(|r| r.len()).collect::<Vec<_>>(), vec![4, 2, 1]);
+    assert_eq!(t.pairings_json().as_array().unwrap()[1].as_array().unwrap()[0].find("second").and_then(|s| s.as_string()), Some("Bot 4"));
+    assert_eq!(t.winner(), Some("Bot 2"));
+}
+
+#[test]
+fn test_abandoned_tournament_game_replayed() {
+    let (registry, tournaments, id) = tournament(Format::RoundRobin, 2);
+    let game = open_games(&tournaments, &id)[0].clone();
+    {
+        let entry = registry.get(&game).unwrap();
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.abandon().is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    let replay = open_games(&tournaments, &id);
+    assert_eq!(replay.len(), 1);
+    assert!(replay[0] != game);
+    assert!(tournaments.lock().unwrap().get(&id).unwrap().standings().iter().all(|s| s.points == 0.0));
+}
+
+#[test]
+fn test_tournament_scores_swapped_seats() {
+    let (registry, tournaments, id) = tournament(Format::RoundRobin, 2);
+    let game = open_games(&tournaments, &id)[0].clone();
+    {
+        let entry = registry.get(&game).unwrap();
+        let mut g = entry.game.write().unwrap();
+        g.options.pie_rule = true;
+        assert!(g.move_player("Bot 0", "SOUTH").is_ok());
+        assert!(g.increment_turn().is_ok());
+        assert!(g.swap_sides("Bot 1").is_ok());
+    }
+    // Bot 1 took over seat 0, and wins from it
+    win_game(&registry, &game, 0);
+    let tournaments = tournaments.lock().unwrap();
+    let t = tournaments.get(&id).unwrap();
+    assert_eq!((t.rounds[0][0].first, t.rounds[0][0].score, t.rounds[0][0].moved_first), (0, Some(0.0), Some(1)));
+    assert_eq!(t.winner(), Some("Bot 1"));
+}
+
+#[test]
+fn test_storage() {
+    let registry = Registry::new();
+    let recorder = Arc::new(Mutex::new(Recorder::new(Box::new(SqliteStorage::in_memory().unwrap()))));
+    {
+        let recorder = recorder.clone();
+        registry.on_change(Box::new(move |id: &str, _: u64, len: usize, after: &Game| {
+            recorder.lock().unwrap().record(id, len, after)
+        }));
+    }
+    let players = entrants(&["Alice", "Bob"]);
+    let id = registry.create_with_players(Game::new(), &players).unwrap();
+    let entry = registry.get(&id).unwrap();
+    for &(name, direction) in &[("Alice", "SOUTH"), ("Bob", "NORTH")] {
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.move_player(name, direction).is_ok());
+        assert!(g.increment_turn().is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+    }
+    win_game(&registry, &id, 1);
+
+    let recorder = recorder.lock().unwrap();
+    let games = recorder.storage.games(Some("Bob"), 10).unwrap();
+    assert_eq!(games.len(), 1);
+    assert_eq!((games[0].name.clone(), games[0].turns, games[0].winner), (id.clone(), 2, Some(1)));
+    assert!(games[0].ended.is_some());
+    assert!(recorder.storage.games(Some("Carol"), 10).unwrap().is_empty());
+
+    let record = recorder.storage.record(games[0].id).unwrap().unwrap();
+    assert_eq!(record, GameRecord::from_game(&entry.game.read().unwrap()));
+    assert_eq!(recorder.storage.record(games[0].id + 1).unwrap(), None);
+}
+
+#[test]
+fn test_storage_file() {
+    let path = TempFile::new("games.db");
+    {
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let game = storage.create_game("1", &GameOptions::default(), 100).unwrap();
+        assert!(storage.add_player(game, 0, "Alice", Some(3)).is_ok());
+        assert!(storage.add_turn(game, 0, 0, &Turn::Move(Direction::South), None, 101).is_ok());
+    }
+    let storage = SqliteStorage::open(&path).unwrap();
+    let games = storage.games(None, 10).unwrap();
+    assert_eq!(games[0].players, vec!["Alice".to_string()]);
+    assert_eq!((games[0].turns, games[0].winner, games[0].ended), (1, None, None));
+}
+
+/// Entrants with the given names, each signing in with the key "k"
+fn entrants(names: &[&str]) -> Vec<Entrant> {
+    names.iter().map(|name| {
+        Entrant { name: name.to_string(), key_hash: auth::hash_key("k"), account: None }
+    }).collect()
+}
+
+/// A file in the temporary directory, removed before use and when
+/// dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let path = ::std::env::temp_dir().join(format!("quoridor-{}-{}", ::std::process::id(), name));
+        let _ = ::std::fs::remove_file(&path);
+        TempFile(path)
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = ::std::fs::remove_file(&self.0);
+    }
+}
+
+/// Registry whose changes are journaled in `path`, with the games
+/// recovered from it
+fn journaled_registry(path: &Path) -> (Registry, Vec<String>) {
+    let registry = Registry::new();
+    let (journal, entries) = Journal::open(path.to_path_buf()).unwrap();
+    let recovered = journal::recover(&registry, &entries).unwrap();
+    let journal = Mutex::new(journal);
+    registry.on_change(Box::new(move |id: &str, version: u64, len: usize, after: &Game| {
+        journal.lock().unwrap().record(id, version, len, after)
+    }));
+    (registry, recovered)
+}
+
+/// Take a turn in a game of the registry
+fn journaled_turn(registry: &Registry, id: &str, name: &str, direction: &str) {
+    let entry = registry.get(id).unwrap();
+    let mut g = entry.game.write().unwrap();
+    let before = g.stream.len();
+    assert!(g.move_player(name, direction).is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(entry.publish_changes(before, &g).is_ok());
+}
+
+#[test]
+fn test_journal_recovery() {
+    let path = TempFile::new("journal.log");
+    let players = entrants(&["Alice", "Bob"]);
+
+    let (game, version) = {
+        let (registry, recovered) = journaled_registry(&path);
+        assert!(recovered.is_empty());
+        let finished = registry.create_with_players(Game::new(), &players).unwrap();
+        win_game(&registry, &finished, 0);
+        let id = registry.create_with_players(Game::new(), &players).unwrap();
+        journaled_turn(&registry, &id, "Alice", "SOUTH");
+        journaled_turn(&registry, &id, "Bob", "NORTH");
+        let entry = registry.get(&id).unwrap();
+        let game = entry.game.read().unwrap().clone();
+        (game, entry.version())
+    };
+
+    // Only the game in progress comes back, as it was
+    let (registry, recovered) = journaled_registry(&path);
+    assert_eq!(recovered, vec!["2".to_string()]);
+    let entry = registry.get("2").unwrap();
+    {
+        let g = entry.game.read().unwrap();
+        assert_eq!((g.state.clone(), g.history.clone()), (GameState::Started(0), game.history.clone()));
+        assert_eq!(g.players["Bob"].p, game.players["Bob"].p);
+        assert!(g.players["Alice"].check_key("k"));
+    }
+    assert_eq!(entry.version(), version);
+    assert_eq!(registry.create(Game::new()).unwrap(), "3");
+
+    // and carries on being journaled
+    journaled_turn(&registry, "2", "Alice", "SOUTH");
+    let (registry, _) = journaled_registry(&path);
+    assert_eq!(registry.get("2").unwrap().game.read().unwrap().history.len(), 3);
+
+    // Games nobody has joined come back, and the ids of finished games
+    // aren't given out again
+    win_game(&registry, "2", 0);
+    let (registry, recovered) = journaled_registry(&path);
+    assert_eq!(recovered, vec!["3".to_string()]);
+    let id = registry.create_with_players(Game::new(), &players).unwrap();
+    win_game(&registry, &id, 0);
+    let (registry, _) = journaled_registry(&path);
+    assert_eq!(registry.create(Game::new()).unwrap(), "5");
+}
+
+#[test]
+fn test_journal_clocks() {
+    let path = TempFile::new("journal-clocks.log");
+    let mut options = GameOptions::default();
+    options.time_control = Some(TimeControl { base: Some(60000), increment: 2000, move_limit: None });
+    let players = entrants(&["Alice", "Bob"]);
+    let remaining = {
+        let (registry, _) = journaled_registry(&path);
+        let id = registry.create_with_players(Game::with_options(options.clone()), &players).unwrap();
+        ::std::thread::sleep(Duration::from_millis(50));
+        journaled_turn(&registry, &id, "Alice", "SOUTH");
+        let remaining = registry.get(&id).unwrap().game.read().unwrap().clock.as_ref().unwrap().remaining.clone();
+        remaining
+    };
+    assert!(remaining[0] < 62000);
+
+    // A half written entry is dropped
+    {
+        use std::io::Write;
+        let mut f = ::std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(f, "{{\"game\":\"1\",\"type\":\"tu").unwrap();
+    }
+    let (journal, entries) = Journal::open(path.to_path_buf()).unwrap();
+    drop(journal);
+    assert_eq!(entries.len(), 5);
+    assert_eq!(entries[0], Entry::Reserve { game: "1".to_string() });
+    assert_eq!(Entry::from_json(&entries[4].to_json()).unwrap(), entries[4]);
+
+    let (registry, _) = journaled_registry(&path);
+    let g = registry.get("1").unwrap().game.read().unwrap().clone();
+    assert_eq!(g.options, options);
+    let clock = g.clock.unwrap();
+    assert_eq!((clock.remaining.clone(), clock.log.clone(), clock.running()), (remaining.clone(), vec![remaining[0]], Some(1)));
+}
+
+#[test]
+fn test_tournaments_saved() {
+    let path = TempFile::new("tournaments.json");
+    let registry = Registry::new();
+    let json = {
+        let mut tournaments = Tournaments::open(path.to_path_buf()).unwrap();
+        let id = tournaments.create("Monthly", Format::Swiss(Some(2)), GameOptions::default());
+        for name in &["A", "B", "C"] {
+            let entrant = Entrant { name: name.to_string(), key_hash: auth::hash_key("k"), account: Some(1) };
+            assert!(tournaments.get_mut(&id).unwrap().join(entrant).is_ok());
+        }
+        assert!(tournaments.get_mut(&id).unwrap().start(&registry).is_ok());
+        assert!(tournaments.save().is_ok());
+        tournaments.get(&id).unwrap().to_json()
+    };
+    let mut tournaments = Tournaments::open(path.to_path_buf()).unwrap();
+    assert_eq!(tournaments.get("1").unwrap().to_json(), json);
+    assert_eq!(tournaments.get("1").unwrap().format, Format::Swiss(Some(2)));
+    assert_eq!(tournaments.create("Next", Format::Knockout, GameOptions::default()), "2");
+
+    // A new registry doesn't hand out the ids of the tournament's games
+    let registry = Registry::new();
+    for id in tournaments.game_ids() {
+        registry.reserve(&id);
+    }
+    assert!(!tournaments.game_ids().contains(&registry.create(Game::new()).unwrap()));
+}
+
+/// Two player game where the players step back and forth `plies` times
+fn shuffled_game(plies: usize) -> Game {
+    let mut g = Game::new();
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    for ply in 0..plies {
+        let name = if ply % 2 == 0 { "Player 1" } else { "Player 2" };
+        let direction = if (ply / 2) % 2 == 0 { "EAST" } else { "WEST" };
+        assert!(g.move_player(name, direction).is_ok());
+        assert!(g.increment_turn().is_ok());
+    }
+    g
+}
+
+#[test]
+fn test_domain_replay() {
+    let g = shuffled_game(20);
+    let events = g.stream.events().to_vec();
+    assert_eq!(events.len(), 3 + 2 * 20);
+    assert_eq!(events[2], DomainEvent::GameStarted { first: 0 });
+    assert_eq!(events[3], DomainEvent::PawnMoved { seat: 0, from: point!(4, 0), to: point!(5, 0) });
+
+    let replayed = Game::replay(GameOptions::default(), &events);
+    assert_eq!((replayed.position_hash(), &replayed.history, &replayed.state), (g.position_hash(), &g.history, &g.state));
+
+    // Earlier positions match the game as it was, on either side of a snapshot
+    for &len in &[0, 3, SNAPSHOT_INTERVAL - 1, SNAPSHOT_INTERVAL, SNAPSHOT_INTERVAL + 5] {
+        let at = g.at(len);
+        let prefix = Game::replay(GameOptions::default(), &events[..len]);
+        assert_eq!(at.stream.len(), len);
+        assert_eq!((at.position_hash(), &at.history, &at.state), (prefix.position_hash(), &prefix.history, &prefix.state));
+        assert_eq!(at.positions, prefix.positions);
+        assert_eq!(g.history_len(len), prefix.history.len());
+    }
+    // Snapshots don't copy the history or positions
+    let snapshot = g.stream.snapshot(g.stream.len()).unwrap();
+    assert!(snapshot.game.history.is_empty() && snapshot.game.positions.is_empty());
+
+    // A move taken back from before the last snapshot leaves the
+    // history and positions as if it was never played
+    let mut options = GameOptions::default();
+    options.takebacks = Takebacks::Unlimited;
+    let plies = (SNAPSHOT_INTERVAL - 3) / 2 + 1;
+    let mut t = Game::replay(options.clone(), &events[..3 + 2 * plies]);
+    assert!(t.take_back(0).is_ok());
+    let shorter = Game::replay(options, &events[..3 + 2 * (plies - 1)]);
+    assert_eq!((&t.history, &t.positions), (&shorter.history, &shorter.positions));
+    let at = t.at(t.stream.len());
+    assert_eq!((&at.history, &at.positions), (&shorter.history, &shorter.positions));
+}
+
+#[test]
+fn test_undo_rewinds_events() {
+    let mut g = shuffled_game(3);
+    let player = g.get_player(&"Player 1").clone();
+    assert!(Turn::Move(Direction::East).undo(&mut g, &player).is_err());
+    assert!(Turn::Move(Direction::West).undo(&mut g, &player).is_ok());
+    assert_eq!(g.state, GameState::Started(0));
+    assert_eq!(g.get_player(&"Player 1").p, point!(5, 0));
+    assert_eq!(g.history.len(), 2);
+    assert_eq!(g.stream.len(), 3 + 2 * 2);
+}
+
+#[test]
+fn test_resign() {
+    let mut g = shuffled_game(1);
+    assert!(g.resign("Player 3").is_err());
+    assert!(g.resign("Player 1").is_ok());
+    assert_eq!(g.state, GameState::GameOver);
+    assert_eq!(g.result, Some(GameResult::win(1, Reason::Resignation)));
+    assert_eq!(g.winner(), Some(1));
+    assert!(g.resign("Player 2").is_err());
+
+    let record = GameRecord::from_game(&g);
+    assert_eq!((record.winner, record.reason), (Some(1), Some(Reason::Resignation)));
+    assert_eq!(GameRecord::from_json(&record.to_json()).unwrap(), record);
+    assert_eq!(g.to_json().find("result").unwrap().find("reason").unwrap().as_string(), Some("resignation"));
+}
+
+#[test]
+fn test_draw_offers() {
+    let mut g = shuffled_game(0);
+    assert!(g.accept_draw("Player 2").is_err());
+    assert!(g.offer_draw("Player 1").is_ok());
+    assert!(g.offer_draw("Player 1").is_err());
+    assert!(g.accept_draw("Player 1").is_err());
+    assert!(g.decline_draw("Player 2").is_ok());
+    assert_eq!(g.draw_offer, None);
+
+    // Playing on declines the offer, but the player offering may move
+    let before = g.stream.len();
+    assert!(g.offer_draw("Player 1").is_ok());
+    assert!(g.move_player("Player 1", "SOUTH").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert_eq!(g.draw_offer, Some(0));
+    assert_eq!(events::diff(before, &g)[0], GameEvent::DrawOffered { seat: 0 });
+    assert!(g.move_player("Player 2", "NORTH").is_ok());
+    assert_eq!(g.draw_offer, None);
+
+    assert!(g.offer_draw("Player 2").is_ok());
+    let before = g.stream.len();
+    assert!(g.accept_draw("Player 1").is_ok());
+    assert_eq!(g.result, Some(GameResult::draw(Reason::Agreement)));
+    assert_eq!(events::diff(before, &g), vec![GameEvent::GameOver { result: GameResult::draw(Reason::Agreement) }]);
+}
+
+#[test]
+fn test_abandoned_result_stored() {
+    let mut recorder = Recorder::new(Box::new(SqliteStorage::in_memory().unwrap()));
+    let mut g = Game::new();
+    let before = g.stream.len();
+    assert!(g.add_player("Alice", "a").is_ok());
+    assert!(g.add_player("Bob", "b").is_ok());
+    assert!(recorder.record("1", before, &g).is_ok());
+    let before = g.stream.len();
+    assert!(g.abandon().is_ok());
+    assert!(g.abandon().is_err());
+    assert!(recorder.record("1", before, &g).is_ok());
+
+    let games = recorder.storage.games(None, 10).unwrap();
+    assert_eq!((games[0].winner, games[0].reason), (None, Some(Reason::Abandonment)));
+    assert_eq!(recorder.storage.record(games[0].id).unwrap().unwrap().reason, Some(Reason::Abandonment));
+}
+
+#[test]
+fn test_takebacks() {
+    let mut g = shuffled_game(2);
+    assert!(g.request_takeback("Player 1").is_err());
+
+    g.options.takebacks = Takebacks::Allowed;
+    assert!(g.request_takeback("Player 1").is_ok());
+    assert!(g.accept_takeback("Player 1").is_err());
+    let before = g.stream.len();
+    assert!(g.accept_takeback("Player 2").is_ok());
+
+    // Both moves since Player 1's are gone, and it is their turn again
+    assert_eq!(g.state, GameState::Started(0));
+    assert!(g.history.is_empty());
+    assert_eq!(g.get_player(&"Player 1").p, point!(4, 0));
+    assert_eq!(events::diff(before, &g), vec![GameEvent::TakenBack { seat: 0, turns: 0 }]);
+    let replayed = Game::replay(g.options.clone(), g.stream.events());
+    assert_eq!((replayed.position_hash(), &replayed.state), (g.position_hash(), &g.state));
+
+    // Each player gets one
+    assert!(g.move_player("Player 1", "EAST").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(g.request_takeback("Player 1").is_err());
+    assert!(g.request_takeback("Player 2").is_err());
+    assert!(g.move_player("Player 2", "WEST").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(g.request_takeback("Player 2").is_ok());
+    assert!(g.decline_takeback("Player 1").is_ok());
+    assert_eq!((g.takeback_request, g.history.len()), (None, 2));
+}
+
+#[test]
+fn test_journal_takeback() {
+    let path = TempFile::new("journal-takeback.log");
+    let players = entrants(&["Alice", "Bob"]);
+    let mut options = GameOptions::default();
+    options.takebacks = Takebacks::Unlimited;
+
+    let game = {
+        let (registry, _) = journaled_registry(&path);
+        let id = registry.create_with_players(Game::with_options(options), &players).unwrap();
+        journaled_turn(&registry, &id, "Alice", "SOUTH");
+        journaled_turn(&registry, &id, "Bob", "NORTH");
+        let entry = registry.get(&id).unwrap();
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.request_takeback("Bob").is_ok());
+        assert!(g.accept_takeback("Alice").is_ok());
+        assert!(entry.publish_changes(before, &g).is_ok());
+        g.clone()
+    };
+
+    let (registry, _) = journaled_registry(&path);
+    let recovered = registry.get("1").unwrap().game.read().unwrap().clone();
+    assert_eq!((recovered.history.clone(), recovered.state.clone()), (vec![Turn::Move(Direction::South)], GameState::Started(1)));
+    assert_eq!(recovered.position_hash(), game.position_hash());
+}
+
+#[test]
+fn test_draw_rules() {
+    let mut options = GameOptions::default();
+    options.repetitions = Some(3);
+    let mut g = Game::with_options(options.clone());
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    // The opening position comes back every four turns
+    for &(name, direction) in [("Player 1", "EAST"), ("Player 2", "EAST"), ("Player 1", "WEST"), ("Player 2", "WEST")].iter().cycle().take(7) {
+        assert!(g.move_player(name, direction).is_ok());
+        assert!(g.increment_turn().is_ok());
+    }
+    assert_eq!((g.state.clone(), g.repetitions()), (GameState::Started(1), 2));
+    assert!(g.move_player("Player 2", "WEST").is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert_eq!(g.state, GameState::GameOver);
+    assert_eq!(g.result, Some(GameResult::draw(Reason::Repetition)));
+    assert_eq!(GameOptions::from_json(&options.to_json()), Some(options));
+
+    let mut options = GameOptions::default();
+    options.max_plies = Some(3);
+    let mut g = Game::with_options(options);
+    assert!(g.add_player("Player 1", "a").is_ok());
+    assert!(g.add_player("Player 2", "b").is_ok());
+    for &(name, direction) in &[("Player 1", "SOUTH"), ("Player 2", "NORTH"), ("Player 1", "SOUTH")] {
+        assert!(g.move_player(name, direction).is_ok());
+        assert!(g.increment_turn().is_ok());
+    }
+    assert_eq!(g.result, Some(GameResult::draw(Reason::MoveLimit)));
+    assert_eq!(g.winner(), None);
+}
+
+#[test]
+fn test_rematch_series() {
+    let registry = Registry::new();
+    let mut rematches = Rematches::new();
+    let players = entrants(&["Alice", "Bob"]);
+    let mut options = GameOptions::default();
+    options.walls = 6;
+    options.time_control = Some(TimeControl { base: Some(60000), increment: 1000, move_limit: None });
+    let first = registry.create_with_players(Game::with_options(options.clone()), &players).unwrap();
+    let game = |id: &str| registry.get(id).unwrap().game.read().unwrap().clone();
+    assert!(rematches.rematch(&registry, &first, &game(&first)).is_err());
+
+    win_game(&registry, &first, 0);
+    let second = rematches.rematch(&registry, &first, &game(&first)).unwrap();
+    assert_eq!(rematches.rematch(&registry, &first, &game(&first)).unwrap(), second);
+
+    // The players swap seats and keep the settings
+    let g = game(&second);
+    assert_eq!((g.get_player(&"Bob").id, g.get_player(&"Alice").id), (0, 1));
+    assert_eq!(g.get_player(&"Bob").p, point!(4, 0));
+    assert_eq!(g.options, options);
+
+    win_game(&registry, &second, 0);
+    rematches.record_result(&second, &game(&second));
+    let series = rematches.of_game(&second).unwrap();
+    assert_eq!((series.games.clone(), series.score()), (vec![first.clone(), second.clone()], vec![1.0, 1.0]));
+
+    let third = rematches.rematch(&registry, &second, &game(&second)).unwrap();
+    assert_eq!(game(&third).get_player(&"Alice").id, 0);
+    assert_eq!(rematches.get("1").unwrap().games.len(), 3);
+}
+
+#[test]
+fn test_seating() {
+    let mut g = Game::new();
+    assert!(g.add_player_to_seat("Alice".into(), String::new(), Some(1)).is_ok());
+    assert!(g.add_player_to_seat("Bob".into(), String::new(), Some(1)).is_err());
+    assert!(g.add_player_with_hash("Bob".into(), String::new()).is_ok());
+    assert_eq!((g.get_player(&"Alice").id, g.get_player(&"Alice").p), (1, point!(4, 8)));
+    assert_eq!((g.get_player(&"Bob").id, g.state.clone()), (0, GameState::Started(0)));
+
+    let mut options = GameOptions::default();
+    options.seating = Seating::Random;
+    assert_eq!(GameOptions::from_json(&options.to_json()), Some(options.clone()));
+    let mut first = vec![];
+    for _ in 0..16 {
+        let mut g = Game::with_options(options.clone());
+        assert!(g.add_player_to_seat("Alice".into(), String::new(), Some(0)).is_err());
+        assert!(g.add_player_to_seat("Alice".into(), String::new(), None).is_ok());
+        assert!(g.add_player_to_seat("Bob".into(), String::new(), None).is_ok());
+        first.push(g.get_player(&"Alice").id == 0);
+    }
+    assert!(first.contains(&true) && first.contains(&false));
+}
+
+#[test]
+fn test_pie_rule() {
+    let path = TempFile::new("journal-swap.log");
+    let players = entrants(&["Alice", "Bob"]);
+    let mut options = GameOptions::default();
+    options.pie_rule = true;
+
+    let game = {
+        let (registry, _) = journaled_registry(&path);
+        let id = registry.create_with_players(Game::with_options(options), &players).unwrap();
+        let entry = registry.get(&id).unwrap();
+        assert!(entry.game.write().unwrap().swap_sides("Bob").is_err());
+        journaled_turn(&registry, &id, "Alice", "SOUTH");
+        let mut g = entry.game.write().unwrap();
+        let before = g.stream.len();
+        assert!(g.swap_sides("Alice").is_err());
+        assert!(g.swap_sides("Bob").is_ok());
+        assert!(g.swap_sides("Alice").is_err());
+        assert_eq!(events::diff(before, &g), vec![GameEvent::SidesSwapped { seat: 1, with: 0 }]);
+        assert!(entry.publish_changes(before, &g).is_ok());
+        assert_eq!((g.get_player(&"Bob").id, g.get_player(&"Bob").p), (0, point!(4, 1)));
+        assert_eq!((g.get_player(&"Alice").id, g.get_player(&"Alice").p), (1, point!(4, 8)));
+        assert_eq!(g.state, GameState::Started(1));
+        g.clone()
+    };
+    journaled_turn(&journaled_registry(&path).0, "1", "Alice", "NORTH");
+
+    let (registry, _) = journaled_registry(&path);
+    let recovered = registry.get("1").unwrap().game.read().unwrap().clone();
+    assert_eq!(recovered.get_player(&"Bob").id, 0);
+    assert_eq!(recovered.history.len(), game.history.len() + 1);
+    assert_eq!(recovered.state, GameState::Started(0));
+}
+
+#[test]
+fn test_setup() {
+    let setup = Setup {
+        walls: Some(vec![10, 3]),
+        starts: Some(vec![point!(4, 2), point!(4, 8)]),
+        board: vec![Wall::from_tuples((3, 5), (5, 5)).unwrap()],
+        first: 1,
+    };
+    assert!(setup.validate().is_ok());
+    assert!(setup.is_handicap());
+    assert!(!Setup { walls: Some(vec![3, 3]), starts: None, ..setup.clone() }.is_handicap());
+    assert!(Setup { walls: None, ..setup.clone() }.is_handicap());
+    assert!(!Setup { walls: None, starts: Some(vec![point!(4, 2), point!(4, 6)]), ..setup.clone() }.is_handicap());
+    let mut options = GameOptions::default();
+    options.setup = Some(setup.clone());
+    assert_eq!(GameOptions::from_json(&options.to_json()), Some(options.clone()));
+
+    let mut g = Game::with_options(options);
+    assert!(g.add_player("Senior", "a").is_ok());
+    assert!(g.add_player("Junior", "b").is_ok());
+    assert_eq!((g.get_player(&"Senior").p, g.get_player(&"Senior").walls), (point!(4, 2), 10));
+    assert_eq!(g.get_player(&"Junior").walls, 3);
+    assert_eq!(g.walls.len(), 1);
+    assert_eq!(g.state, GameState::Started(1));
+    assert!(g.place_wall("Junior", &Wall::from_tuples((0, 2), (2, 2)).unwrap()).is_ok());
+    assert!(g.increment_turn().is_ok());
+    assert!(g.move_player("Senior", "SOUTH").is_ok());
+    assert!(g.increment_turn().is_ok());
+
+    let record = GameRecord::from_json(&GameRecord::from_game(&g).to_json()).unwrap();
+    let replayed = record.positions().unwrap().pop().unwrap();
+    assert_eq!(replayed.position_hash(), g.position_hash());
+    let rotated = ROTATE_180.game(&g).unwrap();
+    assert_eq!(rotated.options.setup, Some(Setup { first: 0, ..setup.swapped() }));
+    assert_eq!(rotated.positions.last(), Some(&rotated.position_hash()));
+
+    // Every player must be able to reach their goal
+    let boxed_in = Setup {
+        walls: None,
+        starts: Some(vec![point!(0, 0), point!(4, 8)]),
+        board: vec![Wall::from_tuples((1, 0), (1, 2)).unwrap(), Wall::from_tuples((0, 2), (2, 2)).unwrap()],
+        first: 0,
+    };
+    assert!(boxed_in.validate().is_err());
+    let on_goal = Setup { starts: Some(vec![point!(4, 8), point!(4, 0)]), ..setup.clone() };
+    assert!(on_goal.validate().is_err());
+    assert!(Setup { first: 2, ..setup.clone() }.validate().is_err());
+    assert!(Setup { walls: Some(vec![3]), ..setup.clone() }.validate().is_err());
+
+    // A rematch keeps each player's handicap
+    let registry = Registry::new();
+    let players = entrants(&["Senior", "Junior"]);
+    let mut options = GameOptions::default();
+    options.setup = Some(setup.clone());
+    let id = registry.create_with_players(Game::with_options(options), &players).unwrap();
+    win_game(&registry, &id, 0);
+    let game = registry.get(&id).unwrap().game.read().unwrap().clone();
+    let rematch = Rematches::new().rematch(&registry, &id, &game).unwrap();
+    let g = registry.get(&rematch).unwrap().game.read().unwrap().clone();
+    let junior = g.get_player(&"Junior");
+    assert_eq!((junior.id, junior.p, junior.walls), (0, point!(4, 0), 3));
+    assert_eq!((g.get_player(&"Senior").p, g.get_player(&"Senior").walls), (point!(4, 6), 10));
+    assert_eq!(g.walls.iter().cloned().collect::<Vec<_>>(), vec![Wall::from_tuples((4, 4), (6, 4)).unwrap()]);
+    assert_eq!(g.state, GameState::Started(1));
+    assert!(setup.swapped().validate().is_ok());
+
+    // Numbers too big for a wall count or seat aren't wrapped
+    let mut json = setup.to_json();
+    json.as_object_mut().unwrap().insert("first".to_string(), Json::U64(257));
+    assert_eq!(Setup::from_json(&json), None);
+    json.as_object_mut().unwrap().insert("first".to_string(), Json::U64(1));
+    json.as_object_mut().unwrap().insert("walls".to_string(), Json::Array(vec![Json::U64(10), Json::U64(259)]));
+    assert_eq!(Setup::from_json(&json), None);
+}
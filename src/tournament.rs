@@ -0,0 +1,724 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tournaments
+//!
+//! A tournament takes entries until it is started, then plays its
+//! participants in rounds.  The games of a round are created as soon
+//! as the round starts, and the next round starts once they have all
+//! finished.  The first player of a pairing takes seat 0, which moves
+//! first unless the tournament's games are set up otherwise or seat
+//! players at random, and a bye counts as a win.
+//!
+//! * Round robin: everybody plays everybody once, paired with the
+//!   circle method.
+//! * Double round robin: everybody plays everybody twice, with seats
+//!   swapped the second time.
+//! * Swiss: a fixed number of rounds, each pairing players with the
+//!   same score who have not met yet.  Ties in the standings are
+//!   broken by Buchholz, the sum of the opponents' scores.
+//! * Knockout: single elimination, seeded in order of entry.  The top
+//!   seeds get byes when the field is not a power of two, and a drawn
+//!   game goes through the higher seed.
+
+use errors::{QuoridorError, QuoridorResult};
+use quoridor::{Game, GameOptions};
+use registry::{Entrant, Registry};
+use result::Reason;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    RoundRobin,
+    DoubleRoundRobin,
+    /// Swiss with a number of rounds, or enough rounds to find a
+    /// clear winner if None
+    Swiss(Option<usize>),
+    Knockout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Registration,
+    Running,
+    Finished,
+}
+
+/// Two participants paired in a round, by their order of entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pairing {
+    pub first: usize,
+    /// The opponent, or None for a bye
+    pub second: Option<usize>,
+    pub game: Option<String>,
+    /// Points scored by `first` once the game is over: 1 for a win,
+    /// 0.5 for a draw and 0 for a loss
+    pub score: Option<f64>,
+    /// The participant who moved first, once the game is over.  Seats
+    /// may be drawn or swapped, so it need not be `first`.
+    pub moved_first: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub participant: usize,
+    pub points: f64,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub byes: u32,
+    pub buchholz: f64,
+}
+
+pub struct Tournament {
+    pub id: String,
+    pub name: String,
+    pub format: Format,
+    /// Settings of every game in the tournament
+    pub options: GameOptions,
+    pub stage: Stage,
+    pub rounds: Vec<Vec<Pairing>>,
+    participants: Vec<Entrant>,
+}
+
+pub struct Tournaments {
+    tournaments: BTreeMap<u64, Tournament>,
+    next_id: u64,
+    /// File the tournaments are kept in, if any
+    path: Option<PathBuf>,
+}
+
+impl Format {
+    /// Parse a format by name: `round_robin`, `double_round_robin`,
+    /// `swiss` or `knockout`
+    pub fn parse(name: &str, rounds: Option<usize>) -> QuoridorResult<Format>
+    {
+        match name {
+            "round_robin" => Ok(Format::RoundRobin),
+            "double_round_robin" => Ok(Format::DoubleRoundRobin),
+            "swiss" => Ok(Format::Swiss(rounds)),
+            "knockout" => Ok(Format::Knockout),
+            _ => Err(QuoridorError::TournamentError(format!("Unknown format {}", name))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Format::RoundRobin => "round_robin",
+            Format::DoubleRoundRobin => "double_round_robin",
+            Format::Swiss(_) => "swiss",
+            Format::Knockout => "knockout",
+        }
+    }
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Stage::Registration => "registration",
+            Stage::Running => "running",
+            Stage::Finished => "finished",
+        }
+    }
+}
+
+impl Pairing {
+    /// The participant going through to the next knockout round
+    fn winner(&self) -> Option<usize>
+    {
+        let second = match self.second {
+            Some(second) => second,
+            None => return Some(self.first),
+        };
+        self.score.map(|score| if score > 0.5 {
+            self.first
+        } else if score < 0.5 {
+            second
+        } else {
+            self.first.min(second)
+        })
+    }
+}
+
+impl Standing {
+    fn new(participant: usize) -> Standing
+    {
+        Standing { participant: participant, points: 0.0, wins: 0, draws: 0, losses: 0, byes: 0, buchholz: 0.0 }
+    }
+
+    fn add(&mut self, score: f64)
+    {
+        self.points += score;
+        if score > 0.5 {
+            self.wins += 1;
+        } else if score < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+}
+
+/// Points scored by player `name` in a finished game
+fn score_of(game: &Game, name: &str) -> f64
+{
+    match game.winner() {
+        Some(seat) if game.players.get(name).map_or(false, |p| p.id == seat) => 1.0,
+        Some(_) => 0.0,
+        None => 0.5,
+    }
+}
+
+/// Order of the seeds in a knockout bracket of `size` players, so that
+/// the top two seeds can only meet in the final
+fn bracket(size: usize) -> Vec<usize>
+{
+    let mut order = vec![0];
+    while order.len() < size {
+        let n = order.len() * 2;
+        order = order.iter().flat_map(|&s| vec![s, n - 1 - s]).collect();
+    }
+    order
+}
+
+impl Tournament {
+    pub fn new<S: Into<String>>(id: S, name: S, format: Format, options: GameOptions) -> Tournament
+    {
+        Tournament {
+            id: id.into(),
+            name: name.into(),
+            format: format,
+            options: options,
+            stage: Stage::Registration,
+            rounds: vec![],
+            participants: vec![],
+        }
+    }
+
+    /// Names of the participants, in order of entry
+    pub fn participants(&self) -> Vec<&str>
+    {
+        self.participants.iter().map(|p| &p.name[..]).collect()
+    }
+
+    /// Enter a player while the tournament is taking entries
+    pub fn join(&mut self, entrant: Entrant) -> QuoridorResult<()>
+    {
+        if self.stage != Stage::Registration {
+            return Err(QuoridorError::TournamentError("Tournament already started".into()))
+        }
+        if self.participants.iter().any(|p| p.name == entrant.name) {
+            return Err(QuoridorError::TournamentError(format!("{} already entered", entrant.name)))
+        }
+        self.participants.push(entrant);
+        Ok(())
+    }
+
+    /// Close entries and start the first round
+    pub fn start(&mut self, registry: &Registry) -> QuoridorResult<()>
+    {
+        if self.stage != Stage::Registration {
+            return Err(QuoridorError::TournamentError("Tournament already started".into()))
+        }
+        if self.participants.len() < 2 {
+            return Err(QuoridorError::TournamentError("At least two players are needed".into()))
+        }
+        self.stage = Stage::Running;
+        self.next_round(registry)
+    }
+
+    /// Number of rounds the tournament is played over
+    pub fn total_rounds(&self) -> usize
+    {
+        let n = self.participants.len();
+        let even = n + n % 2;
+        match self.format {
+            Format::RoundRobin => even - 1,
+            Format::DoubleRoundRobin => 2 * (even - 1),
+            Format::Swiss(Some(rounds)) => rounds,
+            Format::Swiss(None) | Format::Knockout => n.next_power_of_two().trailing_zeros() as usize,
+        }
+    }
+
+    /// Record the result of a finished game, starting the next round
+    /// if it was the last game of its round.  An abandoned game isn't
+    /// scored but played again in a new game.  Returns false if the
+    /// game is not one of this tournament's.
+    pub fn record_result(&mut self, registry: &Registry, id: &str, game: &Game) -> QuoridorResult<bool>
+    {
+        if self.stage != Stage::Running {
+            return Ok(false)
+        }
+        {
+            let participants = &self.participants;
+            let options = &self.options;
+            let round = self.rounds.last_mut().unwrap();
+            let pairing = match round.iter_mut().find(|p| p.game.as_ref().map_or(false, |g| g == id)) {
+                Some(pairing) => pairing,
+                None => return Ok(false),
+            };
+            if game.result.map_or(false, |r| r.reason == Reason::Abandonment) {
+                let players = [participants[pairing.first].clone(), participants[pairing.second.unwrap()].clone()];
+                pairing.game = Some(try!(registry.create_with_players(Game::with_options(options.clone()), &players)));
+                return Ok(true)
+            }
+            pairing.score = Some(score_of(game, &participants[pairing.first].name));
+            let first_seat = game.first_seat();
+            pairing.moved_first = game.players.values()
+                .find(|p| p.id == first_seat)
+                .and_then(|p| participants.iter().position(|e| e.name == p.name));
+        }
+        if self.round_finished() {
+            try!(self.next_round(registry));
+        }
+        Ok(true)
+    }
+
+    fn round_finished(&self) -> bool
+    {
+        self.rounds.last().map_or(true, |round| round.iter().all(|p| p.score.is_some()))
+    }
+
+    /// Pair the next round and create its games, or finish the
+    /// tournament after the last round
+    fn next_round(&mut self, registry: &Registry) -> QuoridorResult<()>
+    {
+        while self.round_finished() {
+            if self.rounds.len() >= self.total_rounds() {
+                self.stage = Stage::Finished;
+                return Ok(())
+            }
+            let mut round = vec![];
+            for (first, second) in self.pair_round(self.rounds.len()) {
+                round.push(match second {
+                    Some(second) => {
+                        let players = [self.participants[first].clone(), self.participants[second].clone()];
+                        let game = Game::with_options(self.options.clone());
+                        let id = try!(registry.create_with_players(game, &players));
+                        Pairing { first: first, second: Some(second), game: Some(id), score: None, moved_first: None }
+                    },
+                    None => Pairing { first: first, second: None, game: None, score: Some(1.0), moved_first: None },
+                });
+            }
+            self.rounds.push(round);
+        }
+        Ok(())
+    }
+
+    /// Pairings of round `r`, counting from 0
+    fn pair_round(&self, r: usize) -> Vec<(usize, Option<usize>)>
+    {
+        match self.format {
+            Format::RoundRobin | Format::DoubleRoundRobin => self.pair_round_robin(r),
+            Format::Swiss(_) => self.pair_swiss(),
+            Format::Knockout => self.pair_knockout(r),
+        }
+    }
+
+    /// Circle method: the first player stays put while the rest rotate
+    /// one place a round.  The second cycle swaps every pairing's seats.
+    fn pair_round_robin(&self, r: usize) -> Vec<(usize, Option<usize>)>
+    {
+        let mut ids: Vec<Option<usize>> = (0..self.participants.len()).map(Some).collect();
+        if ids.len() % 2 == 1 {
+            ids.push(None);
+        }
+        let (m, cycle) = (ids.len(), ids.len() - 1);
+        let (swap, r) = (r >= cycle, r % cycle);
+
+        let mut circle = vec![ids[0]];
+        circle.extend((0..cycle).map(|i| ids[1 + (i + cycle - r) % cycle]));
+
+        (0..m / 2).map(|i| {
+            let (a, b) = (circle[i], circle[m - 1 - i]);
+            let (a, b) = if ((i + r) % 2 == 1) != swap { (b, a) } else { (a, b) };
+            match (a, b) {
+                (Some(a), b) => (a, b),
+                (None, b) => (b.unwrap(), None),
+            }
+        }).collect()
+    }
+
+    /// Pair down the standings, each player with the next one they
+    /// have not played yet.  With an odd number of players the lowest
+    /// ranked player who has not had a bye gets one.
+    fn pair_swiss(&self) -> Vec<(usize, Option<usize>)>
+    {
+        let played: Vec<&Pairing> = self.rounds.iter().flat_map(|r| r.iter()).collect();
+        let met = |a: usize, b: usize| played.iter().any(|p| {
+            (p.first == a && p.second == Some(b)) || (p.first == b && p.second == Some(a))
+        });
+        // Tournaments saved before who moved first was kept count `first`
+        let firsts = |a: usize| played.iter().filter(|p| {
+            p.second.is_some() && p.moved_first.unwrap_or(p.first) == a
+        }).count();
+
+        let mut order: Vec<usize> = self.standings().iter().map(|s| s.participant).collect();
+        let mut bye = None;
+        if order.len() % 2 == 1 {
+            let had_bye = |a: usize| played.iter().any(|p| p.first == a && p.second.is_none());
+            let pos = order.iter().rposition(|&a| !had_bye(a)).unwrap_or(order.len() - 1);
+            bye = Some(order.remove(pos));
+        }
+
+        let mut pairs = vec![];
+        while !order.is_empty() {
+            let a = order.remove(0);
+            let pos = order.iter().position(|&b| !met(a, b)).unwrap_or(0);
+            let b = order.remove(pos);
+            // Whoever has moved first less often moves first
+            pairs.push(if firsts(b) < firsts(a) { (b, Some(a)) } else { (a, Some(b)) });
+        }
+        if let Some(a) = bye {
+            pairs.push((a, None));
+        }
+        pairs
+    }
+
+    /// Seed the bracket in the first round, then pair the winners of
+    /// neighbouring games
+    fn pair_knockout(&self, r: usize) -> Vec<(usize, Option<usize>)>
+    {
+        let n = self.participants.len();
+        let seeds: Vec<Option<usize>> = match r {
+            0 => bracket(n.next_power_of_two()).into_iter().map(|s| if s < n { Some(s) } else { None }).collect(),
+            _ => self.rounds[r - 1].iter().map(|p| p.winner()).collect(),
+        };
+        seeds.chunks(2).map(|pair| match (pair[0], pair[1]) {
+            (Some(a), b) => (a, b),
+            (None, b) => (b.unwrap(), None),
+        }).collect()
+    }
+
+    /// Every participant's results, best first
+    pub fn standings(&self) -> Vec<Standing>
+    {
+        let n = self.participants.len();
+        let mut rows: Vec<Standing> = (0..n).map(Standing::new).collect();
+        let mut opponents: Vec<Vec<usize>> = vec![vec![]; n];
+        for pairing in self.rounds.iter().flat_map(|r| r.iter()) {
+            let score = match pairing.score {
+                Some(score) => score,
+                None => continue,
+            };
+            match pairing.second {
+                Some(second) => {
+                    rows[pairing.first].add(score);
+                    rows[second].add(1.0 - score);
+                    opponents[pairing.first].push(second);
+                    opponents[second].push(pairing.first);
+                },
+                None => {
+                    rows[pairing.first].points += 1.0;
+                    rows[pairing.first].byes += 1;
+                },
+            }
+        }
+        let points: Vec<f64> = rows.iter().map(|s| s.points).collect();
+        for row in rows.iter_mut() {
+            row.buchholz = opponents[row.participant].iter().fold(0.0, |sum, &o| sum + points[o]);
+        }
+        rows.sort_by(|a, b| {
+            b.points.partial_cmp(&a.points).unwrap_or(Ordering::Equal)
+                .then(b.buchholz.partial_cmp(&a.buchholz).unwrap_or(Ordering::Equal))
+                .then(a.participant.cmp(&b.participant))
+        });
+        rows
+    }
+
+    /// The winner of a finished tournament: the winner of a knockout
+    /// final, or else the top of the standings
+    pub fn winner(&self) -> Option<&str>
+    {
+        if self.stage != Stage::Finished {
+            return None
+        }
+        let winner = match self.format {
+            Format::Knockout => self.rounds.last().and_then(|r| r[0].winner()),
+            _ => self.standings().first().map(|s| s.participant),
+        };
+        winner.map(|w| &self.participants[w].name[..])
+    }
+
+    pub fn standings_json(&self) -> Json
+    {
+        Json::Array(self.standings().iter().enumerate().map(|(i, s)| {
+            let mut d = BTreeMap::new();
+            d.insert("rank".to_string(), (i + 1).to_json());
+            d.insert("name".to_string(), self.participants[s.participant].name.to_json());
+            d.insert("points".to_string(), s.points.to_json());
+            d.insert("wins".to_string(), s.wins.to_json());
+            d.insert("draws".to_string(), s.draws.to_json());
+            d.insert("losses".to_string(), s.losses.to_json());
+            d.insert("byes".to_string(), s.byes.to_json());
+            d.insert("buchholz".to_string(), s.buchholz.to_json());
+            Json::Object(d)
+        }).collect())
+    }
+
+    /// Pairings of every round so far
+    pub fn pairings_json(&self) -> Json
+    {
+        Json::Array(self.rounds.iter().map(|round| {
+            Json::Array(round.iter().map(|p| {
+                let mut d = BTreeMap::new();
+                d.insert("first".to_string(), self.participants[p.first].name.to_json());
+                d.insert("second".to_string(), p.second.map(|s| self.participants[s].name.clone()).to_json());
+                d.insert("game".to_string(), p.game.to_json());
+                d.insert("score".to_string(), p.score.to_json());
+                Json::Object(d)
+            }).collect())
+        }).collect())
+    }
+
+    /// Return the whole tournament as JSON to be saved, including the
+    /// participants' key hashes
+    fn save_json(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_json());
+        d.insert("name".to_string(), self.name.to_json());
+        d.insert("format".to_string(), self.format.name().to_json());
+        if let Format::Swiss(rounds) = self.format {
+            d.insert("swiss_rounds".to_string(), rounds.to_json());
+        }
+        d.insert("options".to_string(), self.options.to_json());
+        d.insert("stage".to_string(), self.stage.name().to_json());
+        d.insert("participants".to_string(), Json::Array(self.participants.iter().map(|p| {
+            let mut d = BTreeMap::new();
+            d.insert("name".to_string(), p.name.to_json());
+            d.insert("key_hash".to_string(), p.key_hash.to_json());
+            d.insert("account".to_string(), p.account.to_json());
+            Json::Object(d)
+        }).collect()));
+        d.insert("rounds".to_string(), Json::Array(self.rounds.iter().map(|round| {
+            Json::Array(round.iter().map(|p| {
+                let mut d = BTreeMap::new();
+                d.insert("first".to_string(), p.first.to_json());
+                d.insert("second".to_string(), p.second.to_json());
+                d.insert("game".to_string(), p.game.to_json());
+                d.insert("score".to_string(), p.score.to_json());
+                d.insert("moved_first".to_string(), p.moved_first.to_json());
+                Json::Object(d)
+            }).collect())
+        }).collect()));
+        Json::Object(d)
+    }
+
+    /// Parse a tournament written by `save_json`
+    fn from_saved_json(json: &Json) -> Option<Tournament>
+    {
+        let string = |j: &Json, key| j.find(key).and_then(|v| v.as_string()).map(|s| s.to_string());
+        let rounds = json.find("swiss_rounds").and_then(|r| r.as_u64()).map(|r| r as usize);
+        let format = match string(json, "format").map(|f| Format::parse(&f, rounds)) {
+            Some(Ok(format)) => format,
+            _ => return None,
+        };
+        let stage = match json.find("stage").and_then(|s| s.as_string()) {
+            Some("registration") => Stage::Registration,
+            Some("running") => Stage::Running,
+            Some("finished") => Stage::Finished,
+            _ => return None,
+        };
+        let mut participants = vec![];
+        for p in json.find("participants").and_then(|p| p.as_array()).unwrap_or(&vec![]) {
+            match (string(p, "name"), string(p, "key_hash")) {
+                (Some(name), Some(key_hash)) => participants.push(Entrant {
+                    name: name,
+                    key_hash: key_hash,
+                    account: p.find("account").and_then(|a| a.as_u64()),
+                }),
+                _ => return None,
+            }
+        }
+        let mut rounds = vec![];
+        for round in json.find("rounds").and_then(|r| r.as_array()).unwrap_or(&vec![]) {
+            let mut pairings = vec![];
+            for p in round.as_array().unwrap_or(&vec![]) {
+                let first = match p.find("first").and_then(|f| f.as_u64()) {
+                    Some(first) if (first as usize) < participants.len() => first as usize,
+                    _ => return None,
+                };
+                pairings.push(Pairing {
+                    first: first,
+                    second: p.find("second").and_then(|s| s.as_u64()).map(|s| s as usize),
+                    game: string(p, "game"),
+                    score: p.find("score").and_then(|s| s.as_f64()),
+                    moved_first: p.find("moved_first").and_then(|m| m.as_u64()).map(|m| m as usize),
+                });
+            }
+            rounds.push(pairings);
+        }
+        match (string(json, "id"), string(json, "name"), json.find("options").and_then(GameOptions::from_json)) {
+            (Some(id), Some(name), Some(options)) => Some(Tournament {
+                id: id,
+                name: name,
+                format: format,
+                options: options,
+                stage: stage,
+                rounds: rounds,
+                participants: participants,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Short description of the tournament for listings
+    pub fn summary(&self) -> Json
+    {
+        let mut d = BTreeMap::new();
+        d.insert("id".to_string(), self.id.to_json());
+        d.insert("name".to_string(), self.name.to_json());
+        d.insert("format".to_string(), self.format.name().to_json());
+        d.insert("stage".to_string(), self.stage.name().to_json());
+        d.insert("participants".to_string(), self.participants.iter().map(|p| p.name.clone()).collect::<Vec<_>>().to_json());
+        d.insert("round".to_string(), self.rounds.len().to_json());
+        d.insert("rounds".to_string(), match self.stage {
+            Stage::Registration => Json::Null,
+            _ => self.total_rounds().to_json(),
+        });
+        d.insert("winner".to_string(), self.winner().map(|w| w.to_string()).to_json());
+        Json::Object(d)
+    }
+
+    pub fn to_json(&self) -> Json
+    {
+        let mut json = self.summary();
+        if let Json::Object(ref mut d) = json {
+            d.insert("pairings".to_string(), self.pairings_json());
+            d.insert("standings".to_string(), self.standings_json());
+        }
+        json
+    }
+}
+
+impl Tournaments {
+    pub fn new() -> Tournaments
+    {
+        Tournaments { tournaments: BTreeMap::new(), next_id: 1, path: None }
+    }
+
+    /// Load the tournaments kept in `path`, which is created on the
+    /// first save if it doesn't exist
+    pub fn open(path: PathBuf) -> QuoridorResult<Tournaments>
+    {
+        let mut tournaments = Tournaments::new();
+        if path.exists() {
+            let json = match Json::from_reader(&mut BufReader::new(try!(File::open(&path)))) {
+                Ok(json) => json,
+                Err(e) => return Err(QuoridorError::ParseError(e.to_string())),
+            };
+            let invalid = || QuoridorError::ParseError(format!("Unable to parse tournaments in {}", path.display()));
+            for t in try!(json.find("tournaments").and_then(|t| t.as_array()).ok_or_else(&invalid)) {
+                let t = try!(Tournament::from_saved_json(t).ok_or_else(&invalid));
+                let id = try!(t.id.parse::<u64>().map_err(|_| invalid()));
+                tournaments.next_id = ::std::cmp::max(tournaments.next_id, id + 1);
+                tournaments.tournaments.insert(id, t);
+            }
+        }
+        tournaments.path = Some(path);
+        Ok(tournaments)
+    }
+
+    /// Write the tournaments to disk, replacing the old file in one step
+    pub fn save(&self) -> QuoridorResult<()>
+    {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut d = BTreeMap::new();
+        d.insert("tournaments".to_string(),
+                 Json::Array(self.tournaments.values().map(|t| t.save_json()).collect()));
+
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(try!(File::create(&tmp)));
+            try!(write!(w, "{}", Json::Object(d)));
+            try!(w.flush());
+            try!(w.get_ref().sync_all());
+        }
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+
+    /// Create a tournament taking entries, returning its id
+    pub fn create<S: Into<String>>(&mut self, name: S, format: Format, options: GameOptions) -> String
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tournaments.insert(id, Tournament::new(id.to_string(), name.into(), format, options));
+        id.to_string()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Tournament>
+    {
+        id.parse().ok().and_then(|id| self.tournaments.get(&id))
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Tournament>
+    {
+        match id.parse() {
+            Ok(id) => self.tournaments.get_mut(&id),
+            Err(_) => None,
+        }
+    }
+
+    /// Ids of every game played in the tournaments, over or not
+    pub fn game_ids(&self) -> Vec<String>
+    {
+        self.tournaments.values()
+            .flat_map(|t| t.rounds.iter())
+            .flat_map(|round| round.iter())
+            .filter_map(|p| p.game.clone())
+            .collect()
+    }
+
+    /// Record the result of a finished game in the tournament it
+    /// belongs to, if any
+    pub fn record_result(&mut self, registry: &Registry, id: &str, game: &Game) -> QuoridorResult<bool>
+    {
+        for tournament in self.tournaments.values_mut() {
+            if try!(tournament.record_result(registry, id, game)) {
+                try!(self.save());
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+
+    /// Summaries of every tournament
+    pub fn to_json(&self) -> Json
+    {
+        Json::Array(self.tournaments.values().map(|t| t.summary()).collect())
+    }
+}
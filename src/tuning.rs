@@ -0,0 +1,132 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Fitting evaluator weights to self-play samples
+//!
+//! An evaluator's score is read as a win probability through the
+//! logistic function.  Weights are fitted either by logistic regression
+//! or by Texel-style local search on the squared error.  `run` ties
+//! the pipeline together: it plays self-play games, saves their samples
+//! and writes the fitted weights.
+
+use errors::QuoridorResult;
+use evaluator::{Evaluator, FEATURES};
+use rand::Rng;
+use selfplay::{self, Sample, SelfPlay};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+fn sigmoid(x: f64) -> f64
+{
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Mean squared error between the results of `samples` and the win
+/// probabilities predicted by `evaluator`
+pub fn error(evaluator: &Evaluator, samples: &[Sample]) -> f64
+{
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = samples.iter().map(|s| {
+        let e = s.result - sigmoid(evaluator.score(&s.features));
+        e * e
+    }).sum();
+    total / samples.len() as f64
+}
+
+/// Texel-style local search: nudge each weight up or down by `step`
+/// while that lowers the error, halving the step when nothing helps,
+/// until the step falls below `min_step`
+pub fn local_search(evaluator: &Evaluator, samples: &[Sample], step: f64, min_step: f64) -> Evaluator
+{
+    let mut best = *evaluator;
+    let mut best_error = error(&best, samples);
+    let mut step = step;
+
+    while step >= min_step {
+        let mut improved = false;
+        for i in 0..FEATURES {
+            for &delta in &[step, -step] {
+                let mut candidate = best;
+                candidate.weights[i] += delta;
+                let e = error(&candidate, samples);
+                if e < best_error {
+                    best = candidate;
+                    best_error = e;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        if !improved {
+            step /= 2.0;
+        }
+    }
+    best
+}
+
+/// Logistic regression by batch gradient descent on the log loss,
+/// starting from all weights zero
+pub fn logistic_regression(samples: &[Sample], iterations: usize, rate: f64) -> Evaluator
+{
+    let mut evaluator = Evaluator::new([0.0; FEATURES]);
+    if samples.is_empty() {
+        return evaluator;
+    }
+
+    for _ in 0..iterations {
+        let mut gradient = [0.0; FEATURES];
+        for s in samples {
+            let e = sigmoid(evaluator.score(&s.features)) - s.result;
+            for i in 0..FEATURES {
+                gradient[i] += e * s.features.0[i];
+            }
+        }
+        for i in 0..FEATURES {
+            evaluator.weights[i] -= rate * gradient[i] / samples.len() as f64;
+        }
+    }
+    evaluator
+}
+
+/// Play `games` self-play games with the default evaluator, writing
+/// their samples to `samples`, then fit weights to them by logistic
+/// regression refined by local search and write the weights as JSON to
+/// `weights`.  Returns the fitted evaluator.
+pub fn run<R: Rng>(games: usize, rng: &mut R, samples: &Path, weights: &Path) -> QuoridorResult<Evaluator>
+{
+    {
+        let mut w = BufWriter::new(try!(File::create(samples)));
+        try!(SelfPlay::new(Evaluator::default()).generate(games, rng, &mut w));
+        try!(w.flush());
+    }
+    let samples = try!(selfplay::read_samples(BufReader::new(try!(File::open(samples)))));
+
+    let fitted = logistic_regression(&samples, 1000, 0.5);
+    let tuned = local_search(&fitted, &samples, 0.1, 0.001);
+
+    let mut w = try!(File::create(weights));
+    try!(writeln!(w, "{}", tuned.to_json().pretty()));
+    Ok(tuned)
+}
@@ -23,10 +23,15 @@
 //! Quoridor board data structures and logic
 
 use player::Player;
-use board::{Direction, Wall};
+use board::{Direction, Point, Wall};
 use quoridor::Game;
 use errors::{QuoridorError, QuoridorResult};
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
 
+/// A single turn.  Jumps in a straight direction cover two squares,
+/// diagonal jumps one square each way.
+#[derive(Hash, Debug, PartialOrd, Ord, PartialEq, Eq, Copy, Clone)]
 pub enum Turn {
     Move(Direction),
     PlaceWall(Wall),
@@ -34,35 +39,101 @@ pub enum Turn {
 }
 
 impl Turn {
-    /// Take a turn
-    pub fn take(&mut self, game: &mut Game, player: Player) -> QuoridorResult<()> {
+    /// The turn that takes a pawn from `a` to `b`, if `b` is a step or
+    /// a jump away
+    pub fn between(a: Point, b: Point) -> Option<Turn>
+    {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let direction = match (dx.signum(), dy.signum()) {
+            (0, -1) => Direction::North,
+            (1, 0) => Direction::East,
+            (0, 1) => Direction::South,
+            (-1, 0) => Direction::West,
+            (1, -1) => Direction::NorthEast,
+            (1, 1) => Direction::SouthEast,
+            (-1, 1) => Direction::SouthWest,
+            (-1, -1) => Direction::NorthWest,
+            _ => return None,
+        };
+        match (dx.abs(), dy.abs()) {
+            (0, 1) | (1, 0) => Some(Turn::Move(direction)),
+            (0, 2) | (2, 0) | (1, 1) => Some(Turn::Jump(direction)),
+            _ => None,
+        }
+    }
+
+    /// Where a pawn at `p` ends up after this turn, if it is a move
+    pub fn destination(&self, p: Point) -> Option<Point>
+    {
         match *self {
-            Turn::PlaceWall(wall) => {
-                let _ = game.add_wall(&wall);
-            },
-            Turn::Move(direction) => {
-                let _ = game.move_player(player.name, direction);
+            Turn::Move(direction) => Some(p.shift(direction)),
+            Turn::Jump(direction) => match direction {
+                Direction::North | Direction::East | Direction::South | Direction::West =>
+                    Some(p.shift(direction).shift(direction)),
+                _ => Some(p.shift(direction)),
             },
-            Turn::Jump(direction) => {
-                let _ = game.move_player(player.name, direction);
+            Turn::PlaceWall(_) => None,
+        }
+    }
+
+    /// Take a turn
+    pub fn take(&self, game: &mut Game, player: &Player) -> QuoridorResult<String> {
+        match *self {
+            Turn::PlaceWall(wall) => game.place_wall(player.name.clone(), &wall),
+            _ => game.move_player_to(player.name.clone(), self.destination(player.p).unwrap()),
+        }
+    }
+
+    /// Undo a turn, which must be the last one `player` took, by
+    /// rewinding the game to before it
+    pub fn undo(&self, game: &mut Game, player: &Player) -> QuoridorResult<()> {
+        let last = game.stream.in_effect(game.stream.len()).into_iter().rev().find(|&i| game.stream.events()[i].turn().is_some());
+        match last.map(|i| (i, &game.stream.events()[i])) {
+            Some((i, event)) if event.turn() == Some(*self) && event.seat() == Some(player.id) => {
+                game.rewind(i);
+                Ok(())
             },
-        };
-        Ok(())
+            _ => Err(QuoridorError::TurnError(format!("{:?} is not the last turn {} took", self, player.name))),
+        }
     }
 
-    /// Undo a turn
-    pub fn undo(&mut self, game: &mut Game, player: Player) -> QuoridorResult<()> {
+    /// Return the turn as JSON, in the same shape as the API requests
+    pub fn to_json(&self) -> Json {
+        let mut d = BTreeMap::new();
         match *self {
+            Turn::Move(direction) => d.insert("move".to_string(), direction.to_string().to_json()),
+            Turn::Jump(direction) => d.insert("jump".to_string(), direction.to_string().to_json()),
             Turn::PlaceWall(wall) => {
-                game.walls.remove(&wall);
-            },
-            Turn::Move(direction) => {
-                let _ = game.move_player(player.name, direction.reversed());
-            },
-            Turn::Jump(direction) => {
-                let _ = try!(game.is_valid_jump(player.p, player.p.shift(direction)));
+                let (a, b) = wall.to_tuples();
+                d.insert("wall".to_string(), vec![vec![a.0, a.1], vec![b.0, b.1]].to_json())
             },
         };
-        Ok(())
+        Json::Object(d)
+    }
+
+    /// Parse a turn written by `to_json`
+    pub fn from_json(json: &Json) -> QuoridorResult<Turn> {
+        let direction = |key| match json.find(key).and_then(|d| d.as_string()).map(Direction::from) {
+            Some(Direction::Invalid) | None => None,
+            d => d,
+        };
+
+        if let Some(d) = direction("move") {
+            return Ok(Turn::Move(d))
+        }
+        if let Some(d) = direction("jump") {
+            return Ok(Turn::Jump(d))
+        }
+
+        let points: Option<Vec<Vec<i64>>> = json.find("wall").and_then(|w| w.as_array()).map(|w| {
+            w.iter().map(|p| p.as_array().map_or(vec![], |p| p.iter().filter_map(|v| v.as_i64()).collect())).collect()
+        });
+        match points {
+            Some(ref p) if p.len() == 2 && p[0].len() == 2 && p[1].len() == 2 => {
+                let wall = try!(Wall::from_tuples((p[0][0] as i32, p[0][1] as i32), (p[1][0] as i32, p[1][1] as i32)));
+                Ok(Turn::PlaceWall(wall))
+            },
+            _ => Err(QuoridorError::ParseError(format!("Unable to parse turn {}", json))),
+        }
     }
 }
@@ -0,0 +1,153 @@
+// Copyright (c) 2015-2016 Joshua S. Miller
+//
+// Permission is hereby granted, free of charge, to any person
+// obtaining a copy of this software and associated documentation
+// files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy,
+// modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS
+// BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN
+// ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! WebSocket push channel for game events
+//!
+//! Connect to `/games/<id>` (or `/` for the default game) and every
+//! event of that game is sent as a JSON message.  Add `?since=<seq>`
+//! when reconnecting to get only the events after `seq`.  Games that
+//! delay spectators only push to players, who send their session
+//! token in an `Authorization: Bearer <token>` header of the handshake.
+
+use auth::Sessions;
+use registry::{Registry, DEFAULT_GAME};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws::{self, CloseCode, Handler, Handshake, Sender};
+
+struct Subscriber {
+    registry: Arc<Registry>,
+    sessions: Arc<Mutex<Sessions>>,
+    out: Sender,
+    closed: Arc<AtomicBool>,
+}
+
+/// Value of a query string parameter of a resource
+pub fn resource_param(resource: &str, name: &str) -> Option<String>
+{
+    resource.splitn(2, '?').nth(1).unwrap_or("")
+        .split('&')
+        .filter_map(|kv| {
+            let mut kv = kv.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k == name => Some(v.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// The game id and sequence number asked for by a resource such as
+/// `/games/3?since=12`
+pub fn parse_resource(resource: &str) -> (String, u64)
+{
+    let path: Vec<&str> = resource.splitn(2, '?').next().unwrap_or("").split('/').filter(|s| !s.is_empty()).collect();
+    let id = match path.iter().position(|&s| s == "games") {
+        Some(i) if i + 1 < path.len() => path[i + 1].to_string(),
+        _ => DEFAULT_GAME.to_string(),
+    };
+    let since = resource_param(resource, "since").and_then(|s| s.parse().ok()).unwrap_or(0);
+    (id, since)
+}
+
+/// The bearer token of an `Authorization` header value
+pub fn bearer_token(header: &[u8]) -> Option<String>
+{
+    let header = String::from_utf8_lossy(header);
+    let mut parts = header.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("Bearer") => Some(token.trim().to_string()),
+        _ => None,
+    }
+}
+
+impl Subscriber {
+    /// Name of the player of game `id` whose token the handshake sent
+    fn player(&self, shake: &Handshake, id: &str) -> Option<String>
+    {
+        let token = match shake.request.header("Authorization").and_then(|h| bearer_token(h)) {
+            Some(token) => token,
+            None => return None,
+        };
+        self.sessions.lock().unwrap().resolve(&token, Instant::now())
+            .and_then(|session| match session.game == id {
+                true => Some(session.name),
+                false => None,
+            })
+    }
+}
+
+impl Handler for Subscriber {
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()>
+    {
+        let resource = shake.request.resource();
+        let (id, since) = parse_resource(resource);
+        let entry = match self.registry.get(&id) {
+            Some(entry) => entry,
+            None => return self.out.close_with_reason(CloseCode::Policy, format!("Game {} not found", id)),
+        };
+        let player = self.player(&shake, &id);
+        if !entry.may_watch_live(player.as_ref().map(|n| &n[..])) {
+            return self.out.close_with_reason(CloseCode::Policy, "Spectators of this game are delayed")
+        }
+
+        let (out, closed) = (self.out.clone(), self.closed.clone());
+        thread::spawn(move || {
+            let mut seq = since;
+            while !closed.load(Ordering::SeqCst) {
+                let events = match entry.wait_for_events(seq, Duration::from_secs(1)) {
+                    Some(events) => events,
+                    None => {
+                        let _ = out.close_with_reason(CloseCode::Normal, "Game removed");
+                        return
+                    },
+                };
+                for event in events {
+                    if out.send(event.to_json().to_string()).is_err() {
+                        return
+                    }
+                    seq = event.seq;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: CloseCode, _: &str)
+    {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Serve game events over WebSocket on `host`
+pub fn listen(host: String, registry: Arc<Registry>, sessions: Arc<Mutex<Sessions>>)
+{
+    println!("Pushing game events on ws://{} ...", host);
+    ws::listen(&*host, |out| Subscriber {
+        registry: registry.clone(),
+        sessions: sessions.clone(),
+        out: out,
+        closed: Arc::new(AtomicBool::new(false)),
+    }).unwrap();
+}